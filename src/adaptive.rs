@@ -0,0 +1,107 @@
+/// Sampling adapting to the observed rate of metrics, so that load spikes do not flood the collector.
+use std::io::Result;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use {accept_sample, to_int_rate, SendStats, StatsdOutlet, FULL_SAMPLING_RATE};
+
+/// Effective rates never go below this, so that some samples always make it through.
+const MIN_RATE: f64 = 0.001;
+
+/// How often the effective rate is re-evaluated.
+const WINDOW: Duration = Duration::from_secs(1);
+
+/// A client lowering its sampling rate when more than `max_per_sec` metrics per second are offered to it,
+/// and raising it back (up to full sampling) when load drops.
+/// The rate is re-evaluated every second from the number of metrics offered during the previous second.
+/// Each line carries the `|@rate` in effect when it was sampled so the server can scale it back.
+pub struct AdaptiveOutlet<S: SendStats> {
+    outlet: StatsdOutlet<S>,
+    max_per_sec: u64,
+    state: Mutex<Estimator>,
+}
+
+struct Estimator {
+    window_start: Instant,
+    offered: u64,
+    rate: f64,
+    int_rate: u32,
+    rate_suffix: String,
+}
+
+impl Estimator {
+    /// Start a new window if the current one is over, recomputing the rate from the load observed.
+    fn roll(&mut self, now: Instant, max_per_sec: u64) {
+        let elapsed = now.saturating_duration_since(self.window_start);
+        if elapsed < WINDOW { return }
+        let offered_per_sec = self.offered as f64 / elapsed.as_secs_f64();
+        let rate = if offered_per_sec <= max_per_sec as f64 {
+            FULL_SAMPLING_RATE
+        } else {
+            // quantize to keep the rate suffix short
+            ((max_per_sec as f64 / offered_per_sec * 1000.0).floor() / 1000.0).max(MIN_RATE)
+        };
+        self.window_start = now;
+        self.offered = 0;
+        if rate != self.rate {
+            self.rate = rate;
+            self.int_rate = to_int_rate(rate);
+            self.rate_suffix = if rate < FULL_SAMPLING_RATE { format!("|@{}", rate) } else { String::new() };
+        }
+    }
+}
+
+impl<S: SendStats> AdaptiveOutlet<S> {
+
+    /// Create a new `AdaptiveOutlet` handing metrics to `sender`, emitting at most around `max_per_sec` metrics per second.
+    /// Sent metric keys will be prepended with `prefix`.
+    pub fn outlet(sender: S, prefix_str: &str, max_per_sec: u64) -> Result<AdaptiveOutlet<S>> {
+        Ok(AdaptiveOutlet {
+            outlet: StatsdOutlet::outlet(sender, prefix_str, FULL_SAMPLING_RATE)?,
+            max_per_sec,
+            state: Mutex::new(Estimator {
+                window_start: Instant::now(),
+                offered: 0,
+                rate: FULL_SAMPLING_RATE,
+                int_rate: to_int_rate(FULL_SAMPLING_RATE),
+                rate_suffix: String::new(),
+            }),
+        })
+    }
+
+    /// The sampling rate currently in effect, between 1.0 (full sampling) and 0.001.
+    pub fn effective_rate(&self) -> f64 {
+        self.state.lock().unwrap().rate
+    }
+
+    /// Report to statsd a count of items.
+    pub fn count(&self, key: &str, value: u64) {
+        self.emit(key, value, &self.outlet.suffixes.count, Instant::now())
+    }
+
+    /// Report to statsd a non-cumulative (instant) count of items.
+    pub fn gauge(&self, key: &str, value: u64) {
+        self.emit(key, value, &self.outlet.suffixes.gauge, Instant::now())
+    }
+
+    /// Report to statsd a time interval of items.
+    pub fn time_interval_ms(&self, key: &str, interval_ms: u64) {
+        self.emit(key, interval_ms, &self.outlet.suffixes.timing, Instant::now())
+    }
+
+    pub(crate) fn emit(&self, key: &str, value: u64, type_suffix: &str, now: Instant) {
+        let rate_suffix = {
+            let mut state = self.state.lock().unwrap();
+            state.roll(now, self.max_per_sec);
+            state.offered += 1;
+            if !accept_sample(state.int_rate) { return }
+            state.rate_suffix.clone()
+        };
+        // send without holding the lock, a slow sender must not stall other callers
+        self.outlet.send_value_tagged(key, value, &[type_suffix, &rate_suffix], &[]);
+    }
+
+    /// Consume the client, returning the underlying sender.
+    pub fn into_inner(self) -> S {
+        self.outlet.into_inner()
+    }
+}
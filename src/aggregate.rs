@@ -0,0 +1,137 @@
+/// Local pre-aggregation of counts, sending one total per key instead of a packet per call.
+use std::collections::HashMap;
+use std::io::Result;
+use std::mem;
+use std::sync::Mutex;
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
+use {Encoding, Metric, Packet, SendStats, StatsdOutlet};
+
+/// Counts accumulated since the last flush.
+pub(crate) struct Aggregation {
+    interval: Duration,
+    // totals are flushed once there are that many keys
+    max_keys: usize,
+    state: Mutex<Totals>,
+}
+
+impl Aggregation {
+    /// Whether the totals can be locked right away, i.e. no thread holds them.
+    pub(crate) fn is_unlocked(&self) -> bool {
+        self.state.try_lock().is_ok()
+    }
+}
+
+struct Totals {
+    counts: HashMap<String, i64>,
+    // totals of `count_extrapolated()`, already scaled by the rate so sent without it
+    extrapolated: HashMap<String, i64>,
+    since: Instant,
+}
+
+impl<S: SendStats> StatsdOutlet<S> {
+
+    /// Accumulate the values of `count()` per key, sending each key's total as a single count
+    /// on `flush()` or on the first count after `interval` elapsed since the previous flush.
+    /// Sampling still applies to every call, totals carry the client's rate.
+    /// Only integer values fitting an `i64` are aggregated, other counts and all other metric types are sent right away.
+    pub fn with_aggregation(mut self, interval: Duration) -> StatsdOutlet<S> {
+        self.aggregation = Some(Aggregation {
+            interval,
+            max_keys: usize::MAX,
+            state: Mutex::new(Totals { counts: HashMap::new(), extrapolated: HashMap::new(), since: Instant::now() }),
+        });
+        self
+    }
+
+    /// Also flush the totals as soon as `n` distinct keys are accumulated, bounding the size of a flush
+    /// and the memory held between flushes when keys are many. Does nothing without `with_aggregation()`.
+    pub fn flush_on_distinct_keys(mut self, n: usize) -> StatsdOutlet<S> {
+        if let Some(ref mut aggregation) = self.aggregation {
+            aggregation.max_keys = n;
+        }
+        self
+    }
+
+    /// Add `count` to the total of `key`, flushing totals if the interval elapsed or the key limit is reached.
+    /// `extrapolated` counts are totalled apart, to be sent without the rate. Totals saturate, counting an `overflows()`.
+    pub(crate) fn aggregate(&self, aggregation: &Aggregation, key: &str, count: i64, extrapolated: bool) {
+        let due = {
+            let mut totals = aggregation.state.lock().unwrap();
+            let counts = if extrapolated { &mut totals.extrapolated } else { &mut totals.counts };
+            match counts.get_mut(key) {
+                Some(total) => *total = total.checked_add(count).unwrap_or_else(|| {
+                    self.overflows.fetch_add(1, Ordering::Relaxed);
+                    total.saturating_add(count)
+                }),
+                None => { counts.insert(key.to_string(), count); }
+            }
+            totals.since.elapsed() >= aggregation.interval || totals.counts.len() + totals.extrapolated.len() >= aggregation.max_keys
+        };
+        if due {
+            let _ = self.flush_counts();
+        }
+    }
+
+    /// Send the accumulated totals as gauges, in key order and packed in as few packets as possible,
+    /// without resetting them: unlike `flush()`, this only peeks at the counts in flight, e.g. for a debug endpoint.
+    /// Totals are sent as counted, not scaled up by the sampling rate. A negative statsd gauge would be read as
+    /// a decrement, so negative totals are sent after a `0` gauge. Does nothing without aggregation.
+    pub fn emit_snapshot(&self) {
+        let counts = match self.aggregation {
+            Some(ref aggregation) => {
+                let totals = aggregation.state.lock().unwrap();
+                let mut counts = totals.counts.clone();
+                for (key, total) in &totals.extrapolated {
+                    let sum = counts.entry(key.clone()).or_insert(0);
+                    *sum = sum.saturating_add(*total);
+                }
+                counts
+            }
+            None => return,
+        };
+        let mut counts: Vec<(String, i64)> = counts.into_iter().collect();
+        counts.sort();
+        let mut packet = Packet::default();
+        let mut line = String::new();
+        for (key, total) in counts {
+            if total < 0 && self.encoding == Encoding::Statsd {
+                line.clear();
+                self.encode_line(&mut line, Metric::Gauge { key: &key, value: 0 }, &[]);
+                self.pack(&mut packet, &key, &line);
+            }
+            line.clear();
+            self.encode_line(&mut line, Metric::Gauge { key: &key, value: total }, &[]);
+            self.pack(&mut packet, &key, &line);
+        }
+        self.send_packed(packet);
+    }
+
+    /// Send all accumulated totals, in key order, returning the first send error.
+    /// The totals are swapped out under the lock so that each count is sent exactly once
+    /// even with concurrent flushes.
+    pub(crate) fn flush_counts(&self) -> Result<()> {
+        let (counts, extrapolated) = match self.aggregation {
+            Some(ref aggregation) => {
+                let mut totals = aggregation.state.lock().unwrap();
+                totals.since = Instant::now();
+                (mem::take(&mut totals.counts), mem::take(&mut totals.extrapolated))
+            }
+            None => return Ok(()),
+        };
+        let mut counts: Vec<(String, i64)> = counts.into_iter().collect();
+        counts.sort();
+        let mut result = Ok(());
+        for (key, total) in counts {
+            let sent = self.try_send_metric(Metric::Count { key: &key, value: total }, &[]).map(|_| ());
+            result = result.and(sent);
+        }
+        let mut extrapolated: Vec<(String, i64)> = extrapolated.into_iter().collect();
+        extrapolated.sort();
+        for (key, total) in extrapolated {
+            let sent = self.try_send_value_tagged(&key, total, &["|c"], &[]).map(|_| ());
+            result = result.and(sent);
+        }
+        result
+    }
+}
@@ -0,0 +1,134 @@
+/// Sending from a background thread, so that callers only pay for formatting and a channel push.
+use std::io::{Error, ErrorKind, Result};
+use std::net::ToSocketAddrs;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, SyncSender, TrySendError};
+use std::thread::{self, JoinHandle};
+use {connect_udp, SendStats, StatsdOutlet};
+
+enum Queue {
+    Unbounded(mpsc::Sender<String>),
+    Bounded(SyncSender<String>),
+}
+
+/// Settings of the thread sending for an `AsyncSender`, by default named `statsd-sender` and at normal priority.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SenderThread {
+    name: String,
+    low_priority: bool,
+}
+
+impl Default for SenderThread {
+    fn default() -> SenderThread {
+        SenderThread { name: "statsd-sender".to_string(), low_priority: false }
+    }
+}
+
+impl SenderThread {
+    /// Name the thread `name`, as shown in thread dumps and debuggers.
+    pub fn name(mut self, name: &str) -> SenderThread {
+        self.name = name.to_string();
+        self
+    }
+
+    /// Run the thread at a lower priority (a nice value 10 above the process's) if `low_priority`,
+    /// so that it never takes CPU from other threads. Only Linux has per-thread priorities, elsewhere this does nothing.
+    pub fn low_priority(mut self, low_priority: bool) -> SenderThread {
+        self.low_priority = low_priority;
+        self
+    }
+
+    /// Lower the priority of the calling thread, if requested and possible. Failures are ignored.
+    fn apply_priority(&self) {
+        #[cfg(target_os = "linux")]
+        unsafe {
+            if self.low_priority {
+                let thread_id = ::libc::syscall(::libc::SYS_gettid) as ::libc::id_t;
+                let nice = ::libc::getpriority(::libc::PRIO_PROCESS as _, thread_id);
+                ::libc::setpriority(::libc::PRIO_PROCESS as _, thread_id, (nice + 10).min(19));
+            }
+        }
+    }
+}
+
+/// A sender handing metric lines over a channel to a thread which sends them with the wrapped sender,
+/// see `SenderThread`. When dropped, the remaining queued lines are sent before the thread is joined.
+pub struct AsyncSender {
+    // None once dropped, closing the channel
+    queue: Option<Queue>,
+    worker: Option<JoinHandle<()>>,
+    dropped: AtomicU64,
+}
+
+impl AsyncSender {
+    /// Send through `sender` from a background thread, queueing lines without limit.
+    pub fn new<S: SendStats + Send + 'static>(sender: S) -> Result<AsyncSender> {
+        let (queue, lines) = mpsc::channel();
+        AsyncSender::spawn(sender, Queue::Unbounded(queue), lines, SenderThread::default())
+    }
+
+    /// Send through `sender` from a background thread, queueing at most `capacity` lines.
+    /// Lines pushed while the queue is full are dropped and counted, see `dropped()`.
+    pub fn bounded<S: SendStats + Send + 'static>(sender: S, capacity: usize) -> Result<AsyncSender> {
+        AsyncSender::bounded_on(sender, capacity, SenderThread::default())
+    }
+
+    /// Like `bounded`, sending from a thread configured by `thread`.
+    pub fn bounded_on<S: SendStats + Send + 'static>(sender: S, capacity: usize, thread: SenderThread) -> Result<AsyncSender> {
+        let (queue, lines) = mpsc::sync_channel(capacity);
+        AsyncSender::spawn(sender, Queue::Bounded(queue), lines, thread)
+    }
+
+    fn spawn<S: SendStats + Send + 'static>(sender: S, queue: Queue, lines: mpsc::Receiver<String>, settings: SenderThread) -> Result<AsyncSender> {
+        let worker = thread::Builder::new().name(settings.name.clone()).spawn(move || {
+            settings.apply_priority();
+            for line in lines { let _ = sender.send_stats(line); }
+        })?;
+        Ok(AsyncSender { queue: Some(queue), worker: Some(worker), dropped: AtomicU64::new(0) })
+    }
+
+    /// Number of lines dropped because the queue was full.
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+impl SendStats for AsyncSender {
+    /// Fails with `WouldBlock` if the queue is full and `BrokenPipe` if the sending thread is gone.
+    fn send_stats(&self, str: String) -> Result<()> {
+        let sent = match self.queue {
+            Some(Queue::Unbounded(ref queue)) => queue.send(str).map_err(|_| ErrorKind::BrokenPipe),
+            Some(Queue::Bounded(ref queue)) => queue.try_send(str).map_err(|e| match e {
+                TrySendError::Full(_) => {
+                    self.dropped.fetch_add(1, Ordering::Relaxed);
+                    ErrorKind::WouldBlock
+                }
+                TrySendError::Disconnected(_) => ErrorKind::BrokenPipe,
+            }),
+            None => Err(ErrorKind::BrokenPipe),
+        };
+        sent.map_err(Error::from)
+    }
+}
+
+impl Drop for AsyncSender {
+    fn drop(&mut self) {
+        self.queue = None;
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+impl StatsdOutlet<AsyncSender> {
+    /// Create a new client like `StatsdClient::new`, sending from a background thread
+    /// with a queue of at most `capacity` lines.
+    pub fn new_async<A: ToSocketAddrs>(address: A, prefix_str: &str, float_rate: f64, capacity: usize) -> Result<StatsdOutlet<AsyncSender>> {
+        StatsdOutlet::outlet(AsyncSender::bounded(connect_udp(address)?, capacity)?, prefix_str, float_rate)
+    }
+
+    /// Number of lines dropped because the queue was full.
+    pub fn dropped(&self) -> u64 {
+        self.sender().dropped()
+    }
+}
@@ -0,0 +1,159 @@
+/// Construction of a `StatsdClient` from named options rather than positional arguments.
+use std::io::Result;
+use std::mem;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use metric::check_order;
+use {check_rate, connect_udp_first, AsyncSender, DryRun, Encoding, Segment, SendStats, SenderThread, StatsdClient, StatsdOutlet, FULL_SAMPLING_RATE, MAX_UDP_PAYLOAD, STATSD_ORDER};
+
+/// Options of a `StatsdClient` sending to a UDP `address`, see `StatsdClient::builder`.
+/// Defaults are no prefix, full sampling, 576 byte packets, no default tags, no batching, statsd lines
+/// a socket bound to the wildcard address, sampling reseeded after forks and a default `SenderThread` for `build_async()`.
+#[derive(Debug)]
+pub struct StatsdClientBuilder {
+    // resolved right away, errors are returned by `build()`
+    addresses: Result<Vec<SocketAddr>>,
+    prefix: String,
+    float_rate: f64,
+    max_payload: usize,
+    default_tags: Vec<(String, String)>,
+    batching: bool,
+    encoding: Encoding,
+    local_address: Option<SocketAddr>,
+    sender_thread: SenderThread,
+    segment_order: Vec<Segment>,
+    fork_reseeding: bool,
+}
+
+impl StatsdClientBuilder {
+    /// Start building a client sending packets to the specified `address`.
+    /// The address is resolved now, the client connecting to the first resolved address that it can.
+    pub fn new<A: ToSocketAddrs>(address: A) -> StatsdClientBuilder {
+        StatsdClientBuilder {
+            addresses: address.to_socket_addrs().map(|addresses| addresses.collect()),
+            prefix: String::new(),
+            float_rate: FULL_SAMPLING_RATE,
+            max_payload: MAX_UDP_PAYLOAD,
+            default_tags: Vec::new(),
+            batching: false,
+            encoding: Encoding::Statsd,
+            local_address: None,
+            sender_thread: SenderThread::default(),
+            segment_order: STATSD_ORDER.to_vec(),
+            fork_reseeding: true,
+        }
+    }
+
+    /// Prepend `prefix` to the sent metric keys.
+    pub fn prefix(mut self, prefix: &str) -> StatsdClientBuilder {
+        self.prefix = prefix.to_string();
+        self
+    }
+
+    /// Sample metrics at `float_rate`, from 0.0 (nothing) to 1.0 (everything).
+    pub fn sample_rate(mut self, float_rate: f64) -> StatsdClientBuilder {
+        self.float_rate = float_rate;
+        self
+    }
+
+    /// See `StatsdOutlet::with_max_payload`.
+    pub fn max_payload(mut self, bytes: usize) -> StatsdClientBuilder {
+        self.max_payload = bytes;
+        self
+    }
+
+    /// See `StatsdOutlet::with_default_tags`.
+    pub fn default_tags(mut self, tags: &[(&str, &str)]) -> StatsdClientBuilder {
+        self.default_tags = tags.iter().map(|&(name, value)| (name.to_string(), value.to_string())).collect();
+        self
+    }
+
+    /// See `StatsdOutlet::with_batching`.
+    pub fn batching(mut self, batching: bool) -> StatsdClientBuilder {
+        self.batching = batching;
+        self
+    }
+
+    /// See `StatsdOutlet::with_encoding`.
+    pub fn encoding(mut self, encoding: Encoding) -> StatsdClientBuilder {
+        self.encoding = encoding;
+        self
+    }
+
+    /// Bind the socket to `local_address` instead of the wildcard address, e.g. to send from a given interface.
+    /// Port 0 picks any free port. Statsd addresses of another IP version cannot be reached from it,
+    /// `build()` failing with an `InvalidInput` error if no resolved address has the same version.
+    pub fn bind_address(mut self, local_address: SocketAddr) -> StatsdClientBuilder {
+        self.local_address = Some(local_address);
+        self
+    }
+
+    /// See `StatsdOutlet::with_segment_order`, the order being checked by `build()`.
+    pub fn segment_order(mut self, order: &[Segment]) -> StatsdClientBuilder {
+        self.segment_order = order.to_vec();
+        self
+    }
+
+    /// See `StatsdOutlet::with_fork_reseeding`.
+    pub fn fork_reseeding(mut self, reseed: bool) -> StatsdClientBuilder {
+        self.fork_reseeding = reseed;
+        self
+    }
+
+    /// Send from a thread configured by `sender_thread` when built with `build_async()`.
+    pub fn sender_thread(mut self, sender_thread: SenderThread) -> StatsdClientBuilder {
+        self.sender_thread = sender_thread;
+        self
+    }
+
+    /// Open the socket and create the client.
+    /// Returns the error of resolving the address if it failed, an `InvalidInput` error if the sample rate is not within 0.0 to 1.0
+    /// or if the segment order is invalid.
+    pub fn build(mut self) -> Result<StatsdClient> {
+        let udp_socket = self.connect()?;
+        self.configure(udp_socket)
+    }
+
+    /// Open the socket and create a client sending from a background thread configured by `sender_thread()`,
+    /// with a queue of at most `capacity` lines. Fails like `build()`, or if the thread cannot be spawned.
+    pub fn build_async(mut self, capacity: usize) -> Result<StatsdOutlet<AsyncSender>> {
+        let udp_socket = self.connect()?;
+        let sender = AsyncSender::bounded_on(udp_socket, capacity, self.sender_thread.clone())?;
+        self.configure(sender)
+    }
+
+    /// Create a client that formats and validates metrics like `StatsdOutlet::dry_run`, against the configured
+    /// maximum payload, but sends nothing: the address is neither used nor required to resolve.
+    /// Fails like `build()` for an invalid sample rate or segment order.
+    pub fn build_dry_run(self) -> Result<StatsdOutlet<DryRun>> {
+        check_rate(self.float_rate)?;
+        check_order(&self.segment_order)?;
+        self.configure(DryRun::new(self.max_payload))
+    }
+
+    /// Check the sample rate, then open the socket.
+    fn connect(&mut self) -> Result<UdpSocket> {
+        check_rate(self.float_rate)?;
+        check_order(&self.segment_order)?;
+        let addresses = mem::replace(&mut self.addresses, Ok(Vec::new()))?;
+        connect_udp_first(&addresses, self.local_address)
+    }
+
+    /// Create the client sending through `sender`, with every option applied.
+    fn configure<S: SendStats>(&self, sender: S) -> Result<StatsdOutlet<S>> {
+        let tags: Vec<(&str, &str)> = self.default_tags.iter().map(|(name, value)| (name.as_str(), value.as_str())).collect();
+        let client = StatsdOutlet::outlet(sender, &self.prefix, self.float_rate)?
+            .with_max_payload(self.max_payload)
+            .with_default_tags(&tags)
+            .with_encoding(self.encoding)
+            .with_fork_reseeding(self.fork_reseeding)
+            .with_segment_order(&self.segment_order)?;
+        Ok(if self.batching { client.with_batching() } else { client })
+    }
+}
+
+impl StatsdClient {
+    /// Start building a client sending packets to the specified `address`.
+    pub fn builder<A: ToSocketAddrs>(address: A) -> StatsdClientBuilder {
+        StatsdClientBuilder::new(address)
+    }
+}
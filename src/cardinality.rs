@@ -0,0 +1,70 @@
+/// Client-side unique counting, to avoid sending every set member to the server.
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::io::{Error, ErrorKind, Result};
+use std::sync::Mutex;
+use {SendStats, StatsdOutlet};
+
+/// Estimates the number of distinct members added per key over a window with a HyperLogLog sketch,
+/// reporting it as a gauge (`key.unique`) on flush.
+/// Each key uses `2^precision` bytes, for a standard error of about `1.04 / sqrt(2^precision)`.
+pub struct CardinalitySet {
+    precision: u8,
+    sketches: Mutex<HashMap<String, Vec<u8>>>,
+}
+
+impl CardinalitySet {
+    /// Estimate cardinalities with `2^precision` registers per key.
+    /// Returns an `InvalidInput` error unless `precision` is between 4 and 16.
+    pub fn new(precision: u8) -> Result<CardinalitySet> {
+        if !(4..=16).contains(&precision) {
+            return Err(Error::new(ErrorKind::InvalidInput, format!("precision {} is not within 4 to 16", precision)))
+        }
+        Ok(CardinalitySet { precision, sketches: Mutex::new(HashMap::new()) })
+    }
+
+    /// Record `member` as seen for `key`.
+    pub fn add<T: Hash + ?Sized>(&self, key: &str, member: &T) {
+        let mut hasher = DefaultHasher::new();
+        member.hash(&mut hasher);
+        let hash = hasher.finish();
+        let register = (hash >> (64 - self.precision)) as usize;
+        let rank = ((hash << self.precision) | (1 << (self.precision - 1))).leading_zeros() as u8 + 1;
+
+        let mut sketches = self.sketches.lock().unwrap();
+        if !sketches.contains_key(key) {
+            sketches.insert(key.to_string(), vec![0; 1 << self.precision]);
+        }
+        let sketch = sketches.get_mut(key).unwrap();
+        sketch[register] = sketch[register].max(rank);
+    }
+
+    /// Report the estimated cardinality of every key seen since the last flush, then start a new window.
+    pub fn flush<S: SendStats>(&self, outlet: &StatsdOutlet<S>) {
+        let sketches = ::std::mem::take(&mut *self.sketches.lock().unwrap());
+        for (key, sketch) in sketches {
+            outlet.gauge(&format!("{}.unique", key), estimate(&sketch));
+        }
+    }
+}
+
+fn estimate(registers: &[u8]) -> u64 {
+    let m = registers.len() as f64;
+    let alpha = match registers.len() {
+        16 => 0.673,
+        32 => 0.697,
+        64 => 0.709,
+        _ => 0.7213 / (1.0 + 1.079 / m),
+    };
+    let sum: f64 = registers.iter().map(|&r| 2f64.powi(-i32::from(r))).sum();
+    let raw = alpha * m * m / sum;
+    let zeros = registers.iter().filter(|&&r| r == 0).count();
+    let estimate = if raw <= 2.5 * m && zeros > 0 {
+        // linear counting is more accurate for small cardinalities
+        m * (m / zeros as f64).ln()
+    } else {
+        raw
+    };
+    estimate.round() as u64
+}
@@ -0,0 +1,48 @@
+/// Timing of CPU-bound work with the per-thread CPU clock, excluding time the thread was descheduled.
+/// Only available on Unix, where `clock_gettime` provides such a clock.
+use libc;
+use std::sync::atomic::Ordering;
+use {SendStats, StatsdOutlet};
+
+/// A point in CPU time of the current thread, to be passed to `stop_cpu_time()` on the same thread.
+/// `None` if the clock could not be read.
+pub struct CpuStartTime(Option<u64>);
+
+impl CpuStartTime {
+    /// The CPU time consumed by the current thread so far
+    fn now() -> CpuStartTime {
+        CpuStartTime(thread_cpu_time_ns())
+    }
+
+    /// The number of milliseconds of CPU time consumed by the current thread since this CpuStartTime,
+    /// `None` if the clock could not be read at either end.
+    fn elapsed_ms(self) -> Option<u64> {
+        Some(thread_cpu_time_ns()?.saturating_sub(self.0?) / 1_000_000)
+    }
+}
+
+fn thread_cpu_time_ns() -> Option<u64> {
+    let mut time = libc::timespec { tv_sec: 0, tv_nsec: 0 };
+    if unsafe { libc::clock_gettime(libc::CLOCK_THREAD_CPUTIME_ID, &mut time) } != 0 { return None }
+    Some(time.tv_sec as u64 * 1_000_000_000 + time.tv_nsec as u64)
+}
+
+impl<S: SendStats> StatsdOutlet<S> {
+
+    /// Query the current thread's CPU time to use eventually with `stop_cpu_time()`
+    pub fn start_cpu_time(&self) -> CpuStartTime {
+        CpuStartTime::now()
+    }
+
+    /// Report the CPU time consumed by the current thread since `start_time`, skipping the clock query if the sample will not be collected.
+    /// Must be called on the thread that called `start_cpu_time()`. If the clock could not be read,
+    /// nothing is sent and the metric is counted as `malformed()`.
+    pub fn stop_cpu_time(&self, key: &str, start_time: CpuStartTime) {
+        if self.sample(key)  {
+            match start_time.elapsed_ms() {
+                Some(elapsed_ms) => self.send_time_ms(key, elapsed_ms),
+                None => { self.malformed.fetch_add(1, Ordering::Relaxed); }
+            }
+        }
+    }
+}
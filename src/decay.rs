@@ -0,0 +1,49 @@
+/// A gauge decaying exponentially toward zero between updates, for "recent activity" indicators.
+use std::io::{Error, ErrorKind, Result};
+use std::sync::Mutex;
+use std::time::Instant;
+use {SendStats, StatsdOutlet};
+
+/// Remembers the last value set and re-emits it decayed by `factor` for every second elapsed since.
+pub struct DecayingGauge {
+    key: String,
+    factor: f64,
+    last: Mutex<Option<(f64, Instant)>>,
+}
+
+impl DecayingGauge {
+    /// Create a gauge reported under `key`, keeping `factor` of its value for every second elapsed.
+    /// Returns an `InvalidInput` error unless the factor is between 0.0 (instant drop to zero) and 1.0 (no decay).
+    pub fn new(key: &str, factor: f64) -> Result<DecayingGauge> {
+        if !(0.0..=1.0).contains(&factor) {
+            return Err(Error::new(ErrorKind::InvalidInput, format!("decay factor {} is not within 0.0 to 1.0", factor)))
+        }
+        Ok(DecayingGauge { key: key.to_string(), factor, last: Mutex::new(None) })
+    }
+
+    /// Set the gauge to `value` and report it.
+    pub fn set<S: SendStats>(&self, outlet: &StatsdOutlet<S>, value: u64) {
+        self.set_at(outlet, value, Instant::now())
+    }
+
+    /// Apply decay since the last update and report the decayed value.
+    /// Nothing is reported if the gauge was never set.
+    pub fn touch<S: SendStats>(&self, outlet: &StatsdOutlet<S>) {
+        self.touch_at(outlet, Instant::now())
+    }
+
+    pub(crate) fn set_at<S: SendStats>(&self, outlet: &StatsdOutlet<S>, value: u64, now: Instant) {
+        *self.last.lock().unwrap() = Some((value as f64, now));
+        outlet.gauge(&self.key, value);
+    }
+
+    pub(crate) fn touch_at<S: SendStats>(&self, outlet: &StatsdOutlet<S>, now: Instant) {
+        let mut last = self.last.lock().unwrap();
+        if let Some((value, then)) = *last {
+            let elapsed = now.saturating_duration_since(then).as_secs_f64();
+            let decayed = value * self.factor.powf(elapsed);
+            *last = Some((decayed, now));
+            outlet.gauge(&self.key, decayed.round() as u64);
+        }
+    }
+}
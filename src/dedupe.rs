@@ -0,0 +1,60 @@
+/// Deduplication of counts by caller-supplied idempotency tokens, so that retried operations are not counted twice.
+use std::collections::{HashSet, VecDeque};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use {SendStats, StatsdOutlet};
+
+/// Remembers tokens seen within `window`, up to `capacity` of them. The oldest tokens are forgotten first.
+pub struct Deduplicator {
+    window: Duration,
+    capacity: usize,
+    seen: Mutex<Seen>,
+}
+
+#[derive(Default)]
+struct Seen {
+    tokens: HashSet<String>,
+    // tokens in the order they were seen, the oldest first
+    order: VecDeque<(String, Instant)>,
+}
+
+impl Deduplicator {
+    /// Create a deduplicator remembering at most `capacity` tokens for `window`.
+    pub fn new(window: Duration, capacity: usize) -> Deduplicator {
+        Deduplicator { window, capacity, seen: Mutex::new(Seen::default()) }
+    }
+
+    /// Report to statsd a count of items unless `token` was already seen within the window.
+    /// Returns `true` if the token was new (the count itself is still subject to sampling).
+    pub fn count_once<S: SendStats>(&self, outlet: &StatsdOutlet<S>, key: &str, value: u64, token: &str) -> bool {
+        self.count_once_at(outlet, key, value, token, Instant::now())
+    }
+
+    pub(crate) fn count_once_at<S: SendStats>(&self, outlet: &StatsdOutlet<S>, key: &str, value: u64, token: &str, now: Instant) -> bool {
+        if !self.first_seen(token, now) { return false }
+        outlet.count(key, value);
+        true
+    }
+
+    fn first_seen(&self, token: &str, now: Instant) -> bool {
+        let mut seen = self.seen.lock().unwrap();
+        while seen.order.front().map(|&(_, at)| now.saturating_duration_since(at) >= self.window).unwrap_or(false) {
+            seen.forget_oldest();
+        }
+        if seen.tokens.contains(token) { return false }
+        while !seen.order.is_empty() && seen.order.len() >= self.capacity {
+            seen.forget_oldest();
+        }
+        seen.tokens.insert(token.to_string());
+        seen.order.push_back((token.to_string(), now));
+        true
+    }
+}
+
+impl Seen {
+    fn forget_oldest(&mut self) {
+        if let Some((token, _)) = self.order.pop_front() {
+            self.tokens.remove(&token);
+        }
+    }
+}
@@ -0,0 +1,40 @@
+/// Conversion of monotonic absolute counters (e.g. kernel counters) into statsd counts.
+use std::collections::HashMap;
+use std::sync::Mutex;
+use {SendStats, StatsdOutlet};
+
+/// What to report when an absolute counter went backward, usually because its source was reset.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OnReset {
+    /// Assume the counter restarted from zero and report its current value.
+    EmitCurrent,
+    /// Report a delta of zero.
+    EmitZero,
+}
+
+/// Remembers the last absolute value of each key and reports the difference as a count.
+pub struct DeltaCounter {
+    on_reset: OnReset,
+    last: Mutex<HashMap<String, u64>>,
+}
+
+impl DeltaCounter {
+    pub fn new(on_reset: OnReset) -> DeltaCounter {
+        DeltaCounter { on_reset, last: Mutex::new(HashMap::new()) }
+    }
+
+    /// Report to statsd the increase of `key` since its previous absolute value.
+    /// The first value recorded for a key only serves as a baseline and reports nothing.
+    pub fn record_absolute<S: SendStats>(&self, outlet: &StatsdOutlet<S>, key: &str, current: u64) {
+        let previous = self.last.lock().unwrap().insert(key.to_string(), current);
+        let delta = match previous {
+            None => return,
+            Some(previous) if current >= previous => current - previous,
+            Some(_) => match self.on_reset {
+                OnReset::EmitCurrent => current,
+                OnReset::EmitZero => 0,
+            },
+        };
+        outlet.count(key, delta)
+    }
+}
@@ -0,0 +1,98 @@
+/// DogStatsD messages other than metrics, sent over the same socket.
+use std::sync::atomic::Ordering;
+use tags::{tag_list, valid_tags};
+use {SendStats, StatsdOutlet};
+
+/// The status of a service check.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ServiceStatus {
+    /// The service works.
+    Ok = 0,
+    /// The service works, but something needs attention.
+    Warning = 1,
+    /// The service does not work.
+    Critical = 2,
+    /// The status could not be determined.
+    Unknown = 3,
+}
+
+/// The priority of an event.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventPriority {
+    Normal,
+    Low,
+}
+
+/// The kind of an event.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AlertType {
+    Error,
+    Warning,
+    Info,
+    Success,
+}
+
+/// Optional fields of an event.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct EventOptions<'a> {
+    /// `Normal` if not given.
+    pub priority: Option<EventPriority>,
+    /// `Info` if not given.
+    pub alert_type: Option<AlertType>,
+    /// Tags of the event, after the default tags.
+    pub tags: &'a [(&'a str, &'a str)],
+}
+
+impl<S: SendStats> StatsdOutlet<S> {
+
+    /// Report an event such as a deploy or a restart, titled `title` and described by `text`.
+    /// Newlines in the title and text are escaped as a backslash followed by `n`. Like service checks, events are neither prefixed nor sampled.
+    /// Events without a title or with tags holding reserved characters are counted as `malformed()`.
+    pub fn event(&self, title: &str, text: &str, opts: EventOptions) {
+        if self.rejects_statsd_lines() {
+            return
+        }
+        if title.is_empty() || !valid_tags(opts.tags) {
+            self.malformed.fetch_add(1, Ordering::Relaxed);
+            return
+        }
+        let title = title.replace('\n', "\\n");
+        let text = text.replace('\n', "\\n");
+        // lengths are in bytes, as the receiver reads them from the packet
+        let mut str = format!("_e{{{},{}}}:{}|{}", title.len(), text.len(), title, text);
+        if let Some(priority) = opts.priority {
+            str.push_str(match priority { EventPriority::Normal => "|p:normal", EventPriority::Low => "|p:low" });
+        }
+        if let Some(alert_type) = opts.alert_type {
+            str.push_str(match alert_type {
+                AlertType::Error => "|t:error",
+                AlertType::Warning => "|t:warning",
+                AlertType::Info => "|t:info",
+                AlertType::Success => "|t:success",
+            });
+        }
+        self.push_tags(&mut str, &[&tag_list(opts.tags)]);
+        let _ = self.send_formatted(&title, &str);
+    }
+
+    /// Report the status of a service check, e.g. the health of a connection, with an optional message.
+    /// Unlike metric keys, the check `name` is not prefixed since checks are namespaced separately.
+    /// Checks are not sampled and carry the default tags. Names containing `|` or newlines are counted as `malformed()`.
+    pub fn service_check(&self, name: &str, status: ServiceStatus, message: Option<&str>) {
+        if self.rejects_statsd_lines() {
+            return
+        }
+        if name.is_empty() || name.contains(&['|', '\n'][..]) {
+            self.malformed.fetch_add(1, Ordering::Relaxed);
+            return
+        }
+        let mut str = format!("_sc|{}|{}", name, status as u8);
+        self.push_tags(&mut str, &[]);
+        if let Some(message) = message {
+            // the message comes last and runs to the end of the line
+            str.push_str("|m:");
+            str.push_str(&message.replace('\n', "\\n").replace("m:", "m\\:"));
+        }
+        let _ = self.send_formatted(name, &str);
+    }
+}
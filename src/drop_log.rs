@@ -0,0 +1,57 @@
+/// Opt-in reporting of why metrics were not sent, to diagnose missing metrics.
+use {SendStats, StatsdOutlet};
+
+/// Why a metric was not sent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DropReason {
+    /// Rejected by sampling.
+    SampledOut,
+    /// Suppressed by a throttling cooldown.
+    RateLimited,
+    /// A queue between the client and the transport (e.g. the socket send buffer) was full.
+    QueueFull,
+    /// The transport failed to send the metric.
+    SendError,
+    /// The metric line exceeded what the transport can send.
+    PayloadTooLarge,
+}
+
+/// A callback told about every metric not sent.
+pub(crate) type DropLogger = Box<dyn Fn(&str, DropReason) + Send + Sync>;
+
+impl<S: SendStats> StatsdOutlet<S> {
+
+    /// Call `logger` with the key and the reason for every metric not sent.
+    /// Without a logger, dropped metrics cost nothing more than a branch.
+    pub fn with_drop_logger<F: Fn(&str, DropReason) + Send + Sync + 'static>(mut self, logger: F) -> StatsdOutlet<S> {
+        self.drop_logger = Some(Box::new(logger));
+        self
+    }
+
+    /// Make the sampling decision for a metric under `key`, reporting it to the drop logger if rejected.
+    pub(crate) fn sample(&self, key: &str) -> bool {
+        self.sample_at(key, self.int_rate)
+    }
+
+    /// Like `sample`, at the integer rate `int_rate` instead of the client's.
+    /// Samples taken still count against the key cap if any, reported as rate limited if over it.
+    pub(crate) fn sample_at(&self, key: &str, int_rate: u32) -> bool {
+        if !self.accept(int_rate, key) {
+            self.log_drop(key, DropReason::SampledOut);
+            return false
+        }
+        if let Some(ref key_cap) = self.key_cap {
+            if !key_cap.allow(key) {
+                self.log_drop(key, DropReason::RateLimited);
+                return false
+            }
+        }
+        true
+    }
+
+    pub(crate) fn log_drop(&self, key: &str, reason: DropReason) {
+        if let Some(ref logger) = self.drop_logger {
+            logger(key, reason)
+        }
+    }
+}
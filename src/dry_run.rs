@@ -0,0 +1,188 @@
+/// A sink that validates formatted metric lines without ever touching the network.
+/// Meant for checking instrumentation code in tests or CI.
+use std::io::Result;
+use std::sync::Mutex;
+use {SendStats, StatsdOutlet, MAX_UDP_PAYLOAD};
+
+/// What is wrong with a metric line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Problem {
+    /// The packet is longer than the maximum payload size.
+    PayloadTooLarge,
+    /// The key is empty or contains reserved characters (`|`, `@`, whitespace).
+    InvalidKey,
+    /// The value is not a number.
+    InvalidValue,
+    /// The line does not follow the `key:value|type[|@rate]` structure.
+    Malformed,
+}
+
+/// A rejected metric line and the reason it was rejected.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Invalid {
+    pub line: String,
+    pub problem: Problem,
+}
+
+/// Validating null sink, records every invalid line it is handed.
+/// Packets are checked against the default maximum payload size unless created with `new`,
+/// and keys are expected to be followed by `:` unless set by `with_separator`.
+/// Metric lines, packed values (`key:1|ms:2|ms`), events and service checks are accepted.
+pub struct DryRun {
+    max_payload: usize,
+    separator: String,
+    invalid: Mutex<Vec<Invalid>>,
+}
+
+impl Default for DryRun {
+    fn default() -> DryRun {
+        DryRun::new(MAX_UDP_PAYLOAD)
+    }
+}
+
+impl DryRun {
+    /// Check packets against `max_payload`, which should be the one given to the client's `with_max_payload`.
+    pub fn new(max_payload: usize) -> DryRun {
+        DryRun { max_payload, separator: ":".to_string(), invalid: Mutex::new(Vec::new()) }
+    }
+
+    /// Expect `separator` between keys and values, which should be the one given to the client's `with_separator`.
+    pub fn with_separator(mut self, separator: &str) -> DryRun {
+        self.separator = separator.to_string();
+        self
+    }
+
+    /// All invalid lines seen so far, in the order they were sent.
+    pub fn report(&self) -> Vec<Invalid> {
+        self.invalid.lock().unwrap().clone()
+    }
+}
+
+impl SendStats for DryRun {
+    fn send_stats(&self, str: String) -> Result<()> {
+        // batched packets hold many lines, the whole packet is reported if any of them is invalid
+        let checked = if str.len() > self.max_payload { Err(Problem::PayloadTooLarge) } else { str.split('\n').try_for_each(|line| self.validate(line)) };
+        if let Err(problem) = checked {
+            self.invalid.lock().unwrap().push(Invalid { line: str, problem });
+        }
+        Ok(())
+    }
+}
+
+impl StatsdOutlet<DryRun> {
+    /// Create a client that performs all formatting and validation but sends nothing.
+    /// Use `report()` to obtain the invalid metrics once done.
+    pub fn dry_run(prefix_str: &str, float_rate: f64) -> Result<StatsdOutlet<DryRun>> {
+        StatsdOutlet::outlet(DryRun::default(), prefix_str, float_rate)
+    }
+
+    /// Like `dry_run`, for a client packing packets up to `max_payload` bytes, see `with_max_payload`.
+    pub fn dry_run_sized(prefix_str: &str, float_rate: f64, max_payload: usize) -> Result<StatsdOutlet<DryRun>> {
+        Ok(StatsdOutlet::outlet(DryRun::new(max_payload), prefix_str, float_rate)?.with_max_payload(max_payload))
+    }
+
+    /// All invalid metrics sent through this client so far.
+    pub fn report(&self) -> Vec<Invalid> {
+        self.sender().report()
+    }
+}
+
+impl DryRun {
+    fn validate(&self, line: &str) -> ::std::result::Result<(), Problem> {
+        if let Some(event) = line.strip_prefix("_e{") {
+            return validate_event(event)
+        }
+        if let Some(check) = line.strip_prefix("_sc|") {
+            return validate_service_check(check)
+        }
+        let (key, rest) = line.split_once(self.separator.as_str()).ok_or(Problem::Malformed)?;
+        if key.is_empty() || key.contains(|c: char| c == '|' || c == '@' || c.is_whitespace()) {
+            return Err(Problem::InvalidKey)
+        }
+        let mut fields = rest.split('|');
+        let mut value = fields.next().ok_or(Problem::Malformed)?;
+        loop {
+            let kind = fields.next().ok_or(Problem::Malformed)?;
+            // packed values go on as `type:value|type`
+            let (kind, packed) = match kind.split_once(self.separator.as_str()) {
+                Some((kind, packed)) => (kind, Some(packed)),
+                None => (kind, None),
+            };
+            validate_value(kind, value)?;
+            match packed {
+                Some(packed) => value = packed,
+                None => break,
+            }
+        }
+        for field in fields {
+            let valid = match field.strip_prefix('@') {
+                Some(rate) => rate.parse::<f64>().map(|r| (0.0..=1.0).contains(&r)).unwrap_or(false),
+                None => field.starts_with('#'),
+            };
+            if !valid {
+                return Err(Problem::Malformed)
+            }
+        }
+        Ok(())
+    }
+}
+
+fn validate_value(kind: &str, value: &str) -> ::std::result::Result<(), Problem> {
+    match kind {
+        "s" => Ok(()),
+        "c" | "g" | "ms" | "h" | "d" => {
+            let digits = value.trim_start_matches(['+', '-']);
+            if digits.parse::<f64>().is_err() || !digits.starts_with(|c: char| c.is_ascii_digit()) {
+                return Err(Problem::InvalidValue)
+            }
+            Ok(())
+        }
+        _ => Err(Problem::Malformed)
+    }
+}
+
+/// Check `{title_len,text_len}:title|text[|field...]`, lengths being in bytes.
+fn validate_event(event: &str) -> ::std::result::Result<(), Problem> {
+    let (lengths, rest) = event.split_once("}:").ok_or(Problem::Malformed)?;
+    let (title_len, text_len) = lengths.split_once(',').ok_or(Problem::Malformed)?;
+    let title_len: usize = title_len.parse().map_err(|_| Problem::Malformed)?;
+    let text_len: usize = text_len.parse().map_err(|_| Problem::Malformed)?;
+    if title_len == 0 {
+        return Err(Problem::InvalidKey)
+    }
+    let rest = rest.get(title_len..).and_then(|rest| rest.strip_prefix('|')).ok_or(Problem::Malformed)?;
+    let rest = rest.get(text_len..).ok_or(Problem::Malformed)?;
+    if rest.is_empty() {
+        return Ok(())
+    }
+    let fields = rest.strip_prefix('|').ok_or(Problem::Malformed)?;
+    for field in fields.split('|') {
+        let known = ["d:", "h:", "k:", "p:", "s:", "t:", "#"].iter().any(|&prefix| field.starts_with(prefix));
+        if !known {
+            return Err(Problem::Malformed)
+        }
+    }
+    Ok(())
+}
+
+/// Check `name|status[|field...]`, a trailing `m:` message running to the end of the line.
+fn validate_service_check(check: &str) -> ::std::result::Result<(), Problem> {
+    let mut fields = check.split('|');
+    match fields.next() {
+        Some(name) if !name.is_empty() => {}
+        _ => return Err(Problem::InvalidKey)
+    }
+    match fields.next() {
+        Some("0") | Some("1") | Some("2") | Some("3") => {}
+        _ => return Err(Problem::InvalidValue)
+    }
+    for field in fields {
+        if field.starts_with("m:") {
+            break
+        }
+        if !["d:", "h:", "#"].iter().any(|&prefix| field.starts_with(prefix)) {
+            return Err(Problem::Malformed)
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,38 @@
+/// A gauge smoothed with an exponential moving average, to reduce dashboard noise.
+use std::io::{Error, ErrorKind, Result};
+use std::sync::Mutex;
+use {SendStats, StatsdOutlet};
+
+/// Reports the exponential moving average of observed values instead of the raw values.
+pub struct EmaGauge {
+    key: String,
+    alpha: f64,
+    average: Mutex<Option<f64>>,
+}
+
+impl EmaGauge {
+    /// Create a gauge reported under `key`, giving weight `alpha` to each new observation.
+    /// Returns an `InvalidInput` error unless alpha is between 0.0 (never move) and 1.0 (no smoothing).
+    pub fn new(key: &str, alpha: f64) -> Result<EmaGauge> {
+        if !(0.0..=1.0).contains(&alpha) {
+            return Err(Error::new(ErrorKind::InvalidInput, format!("smoothing factor {} is not within 0.0 to 1.0", alpha)))
+        }
+        Ok(EmaGauge { key: key.to_string(), alpha, average: Mutex::new(None) })
+    }
+
+    /// Update the average with `value` and report it, rounded to the nearest integer.
+    /// The first observation initializes the average.
+    pub fn observe<S: SendStats>(&self, outlet: &StatsdOutlet<S>, value: u64) {
+        let smoothed = {
+            let mut average = self.average.lock().unwrap();
+            let value = value as f64;
+            let smoothed = match *average {
+                Some(previous) => previous + self.alpha * (value - previous),
+                None => value,
+            };
+            *average = Some(smoothed);
+            smoothed
+        };
+        outlet.gauge(&self.key, smoothed.round() as u64)
+    }
+}
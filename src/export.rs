@@ -0,0 +1,211 @@
+/// Export the numeric fields of any `serde::Serialize` struct as gauges.
+/// Fields are walked at runtime through a minimal `Serializer` so no derive macro is required.
+use std::fmt;
+use std::sync::atomic::Ordering;
+use serde::ser::{self, Impossible, Serialize, SerializeStruct, Serializer};
+use {Encoding, Metric, MetricValue, Packet, SendStats, StatsdOutlet};
+
+impl<S: SendStats> StatsdOutlet<S> {
+
+    /// Report every named numeric field of `value` as a gauge under `key.field_name`, all in as few packets as possible.
+    /// Integers and floats are sent, negative values after a `0` gauge so that statsd does not take them for
+    /// a relative change. Infinite and NaN floats are counted as `malformed()`, fields of any other type
+    /// (strings, booleans, nested structs...) are skipped. Values that are not structs produce no metrics at all.
+    pub fn gauge_fields<T: Serialize>(&self, key: &str, value: &T) {
+        let fields = match value.serialize(StructWalker { fields: Vec::new() }) {
+            Ok(fields) => fields,
+            Err(Skip) => return,
+        };
+        let mut packet = Packet::default();
+        for (field, value) in fields {
+            let key = format!("{}.{}", key, field);
+            match value {
+                Field::Unsigned(value) => self.pack_gauge(&mut packet, &key, value, false),
+                Field::Signed(value) => self.pack_gauge(&mut packet, &key, value, value < 0),
+                Field::Float(value) if !value.is_finite() => { self.malformed.fetch_add(1, Ordering::Relaxed); }
+                Field::Float(value) => self.pack_gauge(&mut packet, &key, value, value < 0.0),
+            }
+        }
+        self.send_packed(packet);
+    }
+
+    fn pack_gauge<V: MetricValue>(&self, packet: &mut Packet, key: &str, value: V, negative: bool) {
+        if !self.sample(key) { return }
+        let mut line = String::new();
+        if negative && self.encoding == Encoding::Statsd {
+            self.encode_line(&mut line, Metric::Gauge { key, value: 0 }, &[]);
+            self.pack(packet, key, &line);
+            line.clear();
+        }
+        self.encode_line(&mut line, Metric::Gauge { key, value }, &[]);
+        self.pack(packet, key, &line);
+    }
+}
+
+/// A numeric field value.
+enum Field {
+    Unsigned(u64),
+    Signed(i64),
+    Float(f64),
+}
+
+/// Returned when a value can not be reported, causing it to be skipped.
+#[derive(Debug)]
+struct Skip;
+
+impl fmt::Display for Skip {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("value can not be reported as a gauge")
+    }
+}
+
+impl ::std::error::Error for Skip {}
+
+impl ser::Error for Skip {
+    fn custom<T: fmt::Display>(_msg: T) -> Self {
+        Skip
+    }
+}
+
+/// Top level serializer, only accepts structs, collecting their numeric fields.
+struct StructWalker {
+    fields: Vec<(&'static str, Field)>,
+}
+
+impl SerializeStruct for StructWalker {
+    type Ok = Vec<(&'static str, Field)>;
+    type Error = Skip;
+
+    fn serialize_field<T: ?Sized + Serialize>(&mut self, field: &'static str, value: &T) -> Result<(), Skip> {
+        if let Ok(value) = value.serialize(FieldValue) {
+            self.fields.push((field, value));
+        }
+        Ok(())
+    }
+
+    fn end(self) -> Result<Self::Ok, Skip> {
+        Ok(self.fields)
+    }
+}
+
+/// Reject anything that is not a struct.
+macro_rules! skip {
+    ($($method: ident($($arg: ty),*) -> $ret: ty;)*) => {
+        $(fn $method(self, $(_: $arg),*) -> Result<$ret, Skip> { Err(Skip) })*
+    }
+}
+
+impl Serializer for StructWalker {
+    type Ok = Vec<(&'static str, Field)>;
+    type Error = Skip;
+    type SerializeSeq = Impossible<Self::Ok, Skip>;
+    type SerializeTuple = Impossible<Self::Ok, Skip>;
+    type SerializeTupleStruct = Impossible<Self::Ok, Skip>;
+    type SerializeTupleVariant = Impossible<Self::Ok, Skip>;
+    type SerializeMap = Impossible<Self::Ok, Skip>;
+    type SerializeStruct = Self;
+    type SerializeStructVariant = Impossible<Self::Ok, Skip>;
+
+    fn serialize_struct(self, _name: &'static str, _len: usize) -> Result<Self, Skip> {
+        Ok(self)
+    }
+
+    fn serialize_some<T: ?Sized + Serialize>(self, value: &T) -> Result<Self::Ok, Skip> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_struct<T: ?Sized + Serialize>(self, _name: &'static str, value: &T) -> Result<Self::Ok, Skip> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: ?Sized + Serialize>(self, _name: &'static str, _index: u32, _variant: &'static str, _value: &T)
+                                                        -> Result<Self::Ok, Skip> {
+        Err(Skip)
+    }
+
+    skip! {
+        serialize_bool(bool) -> Self::Ok;
+        serialize_i8(i8) -> Self::Ok;
+        serialize_i16(i16) -> Self::Ok;
+        serialize_i32(i32) -> Self::Ok;
+        serialize_i64(i64) -> Self::Ok;
+        serialize_u8(u8) -> Self::Ok;
+        serialize_u16(u16) -> Self::Ok;
+        serialize_u32(u32) -> Self::Ok;
+        serialize_u64(u64) -> Self::Ok;
+        serialize_f32(f32) -> Self::Ok;
+        serialize_f64(f64) -> Self::Ok;
+        serialize_char(char) -> Self::Ok;
+        serialize_str(&str) -> Self::Ok;
+        serialize_bytes(&[u8]) -> Self::Ok;
+        serialize_none() -> Self::Ok;
+        serialize_unit() -> Self::Ok;
+        serialize_unit_struct(&'static str) -> Self::Ok;
+        serialize_unit_variant(&'static str, u32, &'static str) -> Self::Ok;
+        serialize_seq(Option<usize>) -> Self::SerializeSeq;
+        serialize_tuple(usize) -> Self::SerializeTuple;
+        serialize_tuple_struct(&'static str, usize) -> Self::SerializeTupleStruct;
+        serialize_tuple_variant(&'static str, u32, &'static str, usize) -> Self::SerializeTupleVariant;
+        serialize_map(Option<usize>) -> Self::SerializeMap;
+        serialize_struct_variant(&'static str, u32, &'static str, usize) -> Self::SerializeStructVariant;
+    }
+}
+
+/// Field serializer, only accepts numbers.
+struct FieldValue;
+
+impl Serializer for FieldValue {
+    type Ok = Field;
+    type Error = Skip;
+    type SerializeSeq = Impossible<Field, Skip>;
+    type SerializeTuple = Impossible<Field, Skip>;
+    type SerializeTupleStruct = Impossible<Field, Skip>;
+    type SerializeTupleVariant = Impossible<Field, Skip>;
+    type SerializeMap = Impossible<Field, Skip>;
+    type SerializeStruct = Impossible<Field, Skip>;
+    type SerializeStructVariant = Impossible<Field, Skip>;
+
+    fn serialize_u8(self, v: u8) -> Result<Field, Skip> { Ok(Field::Unsigned(u64::from(v))) }
+    fn serialize_u16(self, v: u16) -> Result<Field, Skip> { Ok(Field::Unsigned(u64::from(v))) }
+    fn serialize_u32(self, v: u32) -> Result<Field, Skip> { Ok(Field::Unsigned(u64::from(v))) }
+    fn serialize_u64(self, v: u64) -> Result<Field, Skip> { Ok(Field::Unsigned(v)) }
+
+    fn serialize_i8(self, v: i8) -> Result<Field, Skip> { Ok(Field::Signed(i64::from(v))) }
+    fn serialize_i16(self, v: i16) -> Result<Field, Skip> { Ok(Field::Signed(i64::from(v))) }
+    fn serialize_i32(self, v: i32) -> Result<Field, Skip> { Ok(Field::Signed(i64::from(v))) }
+    fn serialize_i64(self, v: i64) -> Result<Field, Skip> { Ok(Field::Signed(v)) }
+
+    fn serialize_f32(self, v: f32) -> Result<Field, Skip> { Ok(Field::Float(f64::from(v))) }
+    fn serialize_f64(self, v: f64) -> Result<Field, Skip> { Ok(Field::Float(v)) }
+
+    fn serialize_some<T: ?Sized + Serialize>(self, value: &T) -> Result<Field, Skip> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_struct<T: ?Sized + Serialize>(self, _name: &'static str, value: &T) -> Result<Field, Skip> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: ?Sized + Serialize>(self, _name: &'static str, _index: u32, _variant: &'static str, _value: &T)
+                                                        -> Result<Field, Skip> {
+        Err(Skip)
+    }
+
+    skip! {
+        serialize_bool(bool) -> Field;
+        serialize_char(char) -> Field;
+        serialize_str(&str) -> Field;
+        serialize_bytes(&[u8]) -> Field;
+        serialize_none() -> Field;
+        serialize_unit() -> Field;
+        serialize_unit_struct(&'static str) -> Field;
+        serialize_unit_variant(&'static str, u32, &'static str) -> Field;
+        serialize_seq(Option<usize>) -> Self::SerializeSeq;
+        serialize_tuple(usize) -> Self::SerializeTuple;
+        serialize_tuple_struct(&'static str, usize) -> Self::SerializeTupleStruct;
+        serialize_tuple_variant(&'static str, u32, &'static str, usize) -> Self::SerializeTupleVariant;
+        serialize_map(Option<usize>) -> Self::SerializeMap;
+        serialize_struct(&'static str, usize) -> Self::SerializeStruct;
+        serialize_struct_variant(&'static str, u32, &'static str, usize) -> Self::SerializeStructVariant;
+    }
+}
@@ -0,0 +1,66 @@
+/// A sink appending metric lines to a file, for setups without a collector.
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Result, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use SendStats;
+
+/// Appends newline-terminated metric lines to `path`.
+/// Once the file would grow past `max_bytes` it is rotated to `path.1`, `path.1` to `path.2` and so on,
+/// keeping at most `keep` rotated files. Every line is written straight to the file without buffering.
+/// Write and rotation errors are returned like send errors are for sockets.
+pub struct FileSink {
+    path: PathBuf,
+    max_bytes: u64,
+    keep: usize,
+    file: Mutex<(File, u64)>,
+}
+
+impl FileSink {
+    /// Open `path` for appending, creating it if needed.
+    pub fn new<P: AsRef<Path>>(path: P, max_bytes: u64, keep: usize) -> Result<FileSink> {
+        let path = path.as_ref().to_path_buf();
+        let file = open_append(&path)?;
+        let written = file.metadata()?.len();
+        Ok(FileSink { path, max_bytes, keep, file: Mutex::new((file, written)) })
+    }
+
+    /// Path of the `index`th rotated file.
+    fn rotated(&self, index: usize) -> PathBuf {
+        let mut path = OsString::from(&self.path);
+        path.push(format!(".{}", index));
+        PathBuf::from(path)
+    }
+
+    fn rotate(&self) -> Result<File> {
+        if self.keep == 0 {
+            fs::remove_file(&self.path)?;
+        } else {
+            let _ = fs::remove_file(self.rotated(self.keep));
+            for index in (1..self.keep).rev() {
+                let _ = fs::rename(self.rotated(index), self.rotated(index + 1));
+            }
+            fs::rename(&self.path, self.rotated(1))?;
+        }
+        open_append(&self.path)
+    }
+}
+
+fn open_append(path: &Path) -> Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+impl SendStats for FileSink {
+    fn send_stats(&self, mut str: String) -> Result<()> {
+        str.push('\n');
+        let len = str.len() as u64;
+        let mut file = self.file.lock().unwrap();
+        if file.1 > 0 && file.1 + len > self.max_bytes {
+            *file = (self.rotate()?, 0);
+        }
+        file.0.write_all(str.as_bytes())?;
+        file.1 += len;
+        Ok(())
+    }
+}
@@ -0,0 +1,25 @@
+/// The statsd line format, usable without a client to check exact bytes or to build custom senders.
+use MetricValue;
+
+/// Append the statsd line `<prefix><key>:<value><suffix>` to `buf`, where `suffix` holds the type
+/// and any sampling rate, e.g. `|c` or `|ms|@0.1`. The value is written without allocating.
+/// Nothing is checked: the value should be valid (see `MetricValue::is_valid`) and the key free of reserved characters.
+pub fn write_metric<V: MetricValue>(buf: &mut String, prefix: &str, key: &str, value: V, suffix: &str) {
+    write_metric_separated(buf, prefix, key, ":", value, suffix)
+}
+
+/// Like `write_metric`, with `separator` between the key and the value instead of `:`.
+pub(crate) fn write_metric_separated<V: MetricValue>(buf: &mut String, prefix: &str, key: &str, separator: &str, value: V, suffix: &str) {
+    buf.push_str(prefix);
+    buf.push_str(key);
+    buf.push_str(separator);
+    value.write_value(buf);
+    buf.push_str(suffix);
+}
+
+/// The statsd line `<prefix><key>:<value><suffix>`, see `write_metric`.
+pub fn format_metric<V: MetricValue>(prefix: &str, key: &str, value: V, suffix: &str) -> String {
+    let mut line = String::new();
+    write_metric(&mut line, prefix, key, value, suffix);
+    line
+}
@@ -0,0 +1,24 @@
+/// Graphite's plaintext protocol, for sending straight to carbon without a statsd server.
+use std::time::{SystemTime, UNIX_EPOCH};
+use {Metric, MetricValue};
+
+/// Append the Graphite line `<prefix>.<key> <value> <timestamp>` for `metric` to `buf`.
+/// The prefix is joined to the key with `.` unless it is empty or already ends with one.
+/// Graphite has neither types nor sampling rates: counts, gauges, timings, histograms and distributions
+/// all become the same `path value timestamp` line, and the server cannot tell them apart.
+pub fn encode_graphite_into<V: MetricValue>(buf: &mut String, metric: &Metric<V>, prefix: &str, timestamp: u64) {
+    buf.push_str(prefix);
+    if !prefix.is_empty() && !prefix.ends_with('.') {
+        buf.push('.');
+    }
+    buf.push_str(metric.key());
+    buf.push(' ');
+    metric.value().write_value(buf);
+    buf.push(' ');
+    timestamp.write_value(buf);
+}
+
+/// Seconds since the Unix epoch, 0 if the clock is set before it.
+pub(crate) fn unix_time() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|elapsed| elapsed.as_secs()).unwrap_or(0)
+}
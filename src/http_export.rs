@@ -0,0 +1,82 @@
+/// Plumbing shared by the exporters posting to HTTP endpoints: connecting, requests and reading back statsd lines.
+use std::io::{Error, ErrorKind, Read, Result, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+/// Split a plain `http://host[:port][/path]` `endpoint` into its host and path, the path being `default_path` if omitted.
+/// Returns an `InvalidInput` error for any other scheme, naming the `service` in the message.
+pub(crate) fn parse_endpoint(endpoint: &str, service: &str, default_path: &str) -> Result<(String, String)> {
+    let location = endpoint.strip_prefix("http://")
+        .ok_or_else(|| Error::new(ErrorKind::InvalidInput, format!("only http:// {} endpoints are supported", service)))?;
+    let (host, path) = match location.find('/') {
+        Some(slash) => location.split_at(slash),
+        None => (location, default_path),
+    };
+    Ok((host.to_string(), path.to_string()))
+}
+
+/// Open a TCP connection to the first address of the `host:port` that accepts one within `timeout`,
+/// with reads and writes on it also bounded by `timeout`.
+fn connect_http(host: &str, timeout: Duration) -> Result<TcpStream> {
+    let mut last_error = Error::new(ErrorKind::AddrNotAvailable, format!("{} did not resolve to any address", host));
+    for address in host.to_socket_addrs()? {
+        match TcpStream::connect_timeout(&address, timeout) {
+            Ok(stream) => {
+                stream.set_read_timeout(Some(timeout))?;
+                stream.set_write_timeout(Some(timeout))?;
+                return Ok(stream)
+            }
+            Err(error) => last_error = error,
+        }
+    }
+    Err(last_error)
+}
+
+/// Send `body` with `method` to `path` on `host` over a new connection, each step bounded by `timeout`.
+/// A response status other than 2xx is an error naming the `service` that refused the metrics.
+pub(crate) fn request(method: &str, host: &str, path: &str, content_type: &str, body: &str, timeout: Duration, service: &str) -> Result<()> {
+    let mut stream = connect_http(host, timeout)?;
+    write!(stream, "{} {} HTTP/1.1\r\nHost: {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+           method, path, host, content_type, body.len(), body)?;
+    let mut response = String::new();
+    stream.read_to_string(&mut response)?;
+    match response.split(' ').nth(1) {
+        Some(status) if status.starts_with('2') => Ok(()),
+        _ => Err(Error::other(format!("{} refused metrics: {}", service, response.lines().next().unwrap_or(""))))
+    }
+}
+
+/// A finite value read back from a statsd line.
+pub(crate) struct StatsdValue<'a> {
+    pub(crate) value: f64,
+    /// The type code, `c`, `g`, `ms`...
+    pub(crate) kind: &'a str,
+    /// Whether the value was written signed, i.e. is a relative change of a gauge.
+    pub(crate) relative: bool,
+    pub(crate) rate: Option<f64>,
+}
+
+impl<'a> StatsdValue<'a> {
+    /// The value scaled back up by its sampling rate, for counts.
+    pub(crate) fn unsampled(&self) -> f64 {
+        match self.rate {
+            Some(rate) if rate > 0.0 => (self.value / rate).round(),
+            _ => self.value,
+        }
+    }
+}
+
+/// Read the statsd `line` (possibly holding many `:`-separated values) into its name and finite typed values.
+/// Returns `None` if the line has no value at all.
+pub(crate) fn parse_line(line: &str) -> Option<(&str, Vec<StatsdValue<'_>>)> {
+    let (name, values) = line.split_once(':')?;
+    let values = values.split(':').filter_map(|value| {
+        let mut fields = value.split('|');
+        let raw = fields.next().unwrap_or("");
+        let number = raw.parse::<f64>().ok().filter(|v| v.is_finite())?;
+        let kind = fields.next()?;
+        let rate = fields.filter_map(|f| f.strip_prefix('@')).filter_map(|r| r.parse::<f64>().ok()).next();
+        Some(StatsdValue { value: number, kind, relative: raw.starts_with(['+', '-']), rate })
+    }).collect();
+    Some((name, values))
+}
@@ -0,0 +1,36 @@
+/// InfluxDB line protocol, for pipelines ingesting it instead of statsd.
+use std::time::{SystemTime, UNIX_EPOCH};
+use {Metric, MetricValue};
+
+/// Append the line `<prefix><key>,<tag>=<value>,... value=<value> <timestamp>` for `metric` to `buf`,
+/// the key being the measurement and `timestamp` in nanoseconds.
+/// Spaces and commas are escaped in the measurement, and spaces, commas and `=` in tag names and values.
+/// Values are written as float fields so that every metric of a measurement has the same field type,
+/// and like Graphite, the line protocol has no metric types nor sampling rates.
+pub fn encode_influx_into<V: MetricValue>(buf: &mut String, metric: &Metric<V>, prefix: &str, tags: &[(&str, &str)], timestamp: u64) {
+    push_escaped(buf, prefix, &[',', ' ']);
+    push_escaped(buf, metric.key(), &[',', ' ']);
+    for &(name, value) in tags {
+        buf.push(',');
+        push_escaped(buf, name, &[',', ' ', '=']);
+        buf.push('=');
+        push_escaped(buf, value, &[',', ' ', '=']);
+    }
+    buf.push_str(" value=");
+    metric.value().write_value(buf);
+    buf.push(' ');
+    timestamp.write_value(buf);
+}
+
+/// Append `str`, with a backslash before each of the `special` characters.
+fn push_escaped(buf: &mut String, str: &str, special: &[char]) {
+    for c in str.chars() {
+        if special.contains(&c) { buf.push('\\') }
+        buf.push(c);
+    }
+}
+
+/// Nanoseconds since the Unix epoch, 0 if the clock is set before it.
+pub(crate) fn unix_time_ns() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|elapsed| elapsed.as_nanos() as u64).unwrap_or(0)
+}
@@ -0,0 +1,59 @@
+/// Protection against a single key dominating a reporting window.
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use {SendStats, StatsdOutlet};
+
+/// Allows at most `max_per_key` emits per key until the next `reset()`, which should be called once per window
+/// (e.g. on every flush). Emits refused are counted.
+pub struct KeyCap {
+    max_per_key: u64,
+    emits: Mutex<HashMap<String, u64>>,
+    dropped: AtomicU64,
+}
+
+impl KeyCap {
+    pub fn new(max_per_key: u64) -> KeyCap {
+        KeyCap { max_per_key, emits: Mutex::new(HashMap::new()), dropped: AtomicU64::new(0) }
+    }
+
+    /// Count an emit for `key`, returning `false` if the key already reached its cap for this window.
+    pub fn allow(&self, key: &str) -> bool {
+        let mut emits = self.emits.lock().unwrap();
+        let allowed = match emits.get_mut(key) {
+            Some(count) if *count >= self.max_per_key => false,
+            Some(count) => { *count += 1; true }
+            None => { emits.insert(key.to_string(), 1); self.max_per_key > 0 }
+        };
+        if !allowed {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+        allowed
+    }
+
+    /// Start a new window, forgetting all per-key counts.
+    pub fn reset(&self) {
+        self.emits.lock().unwrap().clear();
+    }
+
+    /// Total number of emits refused.
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+impl<S: SendStats> StatsdOutlet<S> {
+
+    /// Send at most `key_cap`'s limit of metrics per key between two `flush()`, which resets it.
+    /// Metrics over the limit are reported to the drop logger as `RateLimited`. Clients derived
+    /// with `with_prefix` or `try_clone` are not capped.
+    pub fn with_key_cap(mut self, key_cap: KeyCap) -> StatsdOutlet<S> {
+        self.key_cap = Some(key_cap);
+        self
+    }
+
+    /// Number of metrics not sent because their key reached the cap, see `with_key_cap`.
+    pub fn capped(&self) -> u64 {
+        self.key_cap.as_ref().map_or(0, KeyCap::dropped)
+    }
+}
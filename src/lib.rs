@@ -3,239 +3,3620 @@
 #[cfg(feature="bench")]
 extern crate test;
 
-extern crate time;
+#[cfg(unix)]
+extern crate libc;
 
-use std::net::UdpSocket;
+#[cfg(feature = "serde")]
+extern crate serde;
+
+#[cfg(all(test, feature = "serde"))]
+#[macro_use]
+extern crate serde_derive;
+
+#[cfg(feature = "tracing")]
+extern crate tracing_core;
+#[cfg(feature = "tracing")]
+extern crate tracing_subscriber;
+
+#[cfg(all(test, feature = "tracing"))]
+#[macro_use]
+extern crate tracing;
+
+use std::borrow::Cow;
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
 use std::io::Result;
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+mod pcg32;
+
+mod per_thread;
+pub use per_thread::{ThreadLocalClient, ThreadLocalStats};
+
+mod scale;
+pub use scale::{Overflow, Rounding, Scale};
+
+mod dry_run;
+pub use dry_run::{DryRun, Invalid, Problem};
+
+mod decay;
+pub use decay::DecayingGauge;
+
+mod ema;
+pub use ema::EmaGauge;
+
+mod drop_log;
+pub use drop_log::DropReason;
+
+mod panic_hook;
+pub use panic_hook::install_panic_hook;
+
+mod adaptive;
+pub use adaptive::AdaptiveOutlet;
+
+mod reconnect;
+pub use reconnect::ReconnectingSocket;
+
+mod dedupe;
+pub use dedupe::Deduplicator;
+
+mod file_sink;
+pub use file_sink::FileSink;
+
+mod delta;
+pub use delta::{DeltaCounter, OnReset};
+
+mod percentile;
+pub use percentile::PercentileTracker;
+
+mod cardinality;
+pub use cardinality::CardinalitySet;
+
+mod sanitize;
+pub use sanitize::{Sanitizer, STATSD_RESERVED};
+
+mod tags;
+use tags::{tag_list, valid_tags};
+
+mod sampled;
+
+mod aggregate;
+
+mod retry;
+
+mod dogstatsd;
+
+mod null;
+pub use null::NullSender;
+
+mod tee;
+pub use tee::TeeSender;
+
+mod log_sender;
+pub use log_sender::LogSender;
+
+mod sampler;
+pub use sampler::{HashSampler, Pcg32Sampler, Sampler};
+pub use dogstatsd::{AlertType, EventOptions, EventPriority, ServiceStatus};
+
+mod value;
+pub use value::MetricValue;
+
+mod format;
+pub use format::{format_metric, write_metric};
+use format::write_metric_separated;
+
+mod metric;
+pub use metric::{encode, encode_into, Encoding, Metric, Segment, Suffixes, STATSD_ORDER};
+use metric::{check_order, encode_separated_into};
+
+#[cfg(feature = "graphite")]
+mod graphite;
+#[cfg(feature = "graphite")]
+pub use graphite::encode_graphite_into;
+
+#[cfg(feature = "influx")]
+mod influx;
+#[cfg(feature = "influx")]
+pub use influx::encode_influx_into;
+
+mod timer_guard;
+pub use timer_guard::TimerGuard;
+
+mod key_cap;
+pub use key_cap::KeyCap;
+
+mod middleware;
+pub use middleware::{Layered, Middleware};
+
+mod sharded;
+pub use sharded::{Sharded, ShardedClient};
+
+mod async_sender;
+pub use async_sender::{AsyncSender, SenderThread};
+
+mod builder;
+pub use builder::StatsdClientBuilder;
+
+mod tcp;
+pub use tcp::TcpSender;
+
+#[cfg(unix)]
+mod uds;
+
+#[cfg(feature = "serde")]
+mod export;
+
+#[cfg(feature = "async")]
+mod timed_future;
+#[cfg(feature = "async")]
+pub use timed_future::TimedFuture;
+
+#[cfg(unix)]
+mod cpu_time;
+#[cfg(unix)]
+pub use cpu_time::CpuStartTime;
+
+#[cfg(any(feature = "otlp", feature = "prometheus"))]
+mod http_export;
+
+#[cfg(feature = "otlp")]
+mod otlp;
+#[cfg(feature = "otlp")]
+pub use otlp::OtlpSink;
+
+#[cfg(feature = "prometheus")]
+mod prometheus;
+#[cfg(feature = "prometheus")]
+pub use prometheus::PushgatewaySink;
+
+#[cfg(feature = "tracing")]
+mod span_tags;
+#[cfg(feature = "tracing")]
+pub use span_tags::SpanTags;
+
+thread_local! {
+    // reused to format metric lines without allocating, see `with_line`
+    static LINE: Cell<String> = Cell::new(String::with_capacity(MAX_UDP_PAYLOAD));
+}
+
+/// Use a safe maximum size for UDP to prevent fragmentation.
+const MAX_UDP_PAYLOAD: usize = 576;
+
+pub const FULL_SAMPLING_RATE: f64 = 1.0;
+
+pub trait SendStats: Sized {
+    /// Send a metric line (or many, newline-separated). `WouldBlock` errors mean the line was dropped
+    /// because a queue (e.g. the socket send buffer) was full.
+    fn send_stats(&self, str: String) -> Result<()>;
+
+    /// Send a metric line borrowed from the caller.
+    /// Defaults to copying it for `send_stats`, implementations able to send from a borrowed buffer should override it.
+    fn send_stats_str(&self, str: &str) -> Result<()> {
+        self.send_stats(str.to_string())
+    }
+}
+
+/// Real implementation, send a UDP packet for every stat
+impl SendStats for UdpSocket {
+    fn send_stats(&self, str: String) -> Result<()> {
+        self.send_stats_str(&str)
+    }
+
+    fn send_stats_str(&self, str: &str) -> Result<()> {
+        self.send(str.as_bytes()).map(|_| ())
+    }
+}
+
+/// Send through a borrowed sender, see `StatsdOutlet::with_prefix`.
+impl<S: SendStats> SendStats for &S {
+    fn send_stats(&self, str: String) -> Result<()> {
+        (**self).send_stats(str)
+    }
+
+    fn send_stats_str(&self, str: &str) -> Result<()> {
+        (**self).send_stats_str(str)
+    }
+}
+
+/// A client to send application metrics to a statsd server over UDP.
+/// Multiple instances may be required if different sampling rates or prefix a required within the same application.
+///
+/// All methods reporting metrics take `&self`, and the client is `Send` and `Sync` whenever its sender is,
+/// as for `StatsdClient`: a single client can be stored in a static and shared by every thread.
+/// Its mutable state (counters, batch, aggregated counts, sampler) only uses atomics and locks to keep it so.
+pub struct StatsdOutlet<S: SendStats> {
+    // only taken by `into_inner()`
+    sender: Option<S>,
+    prefix: String,
+    int_rate: u32,
+    float_rate: f64,
+    suffixes: Suffixes,
+    encoding: Encoding,
+    // between keys and values, `:` in statsd
+    separator: String,
+    // order of the parts of statsd lines, if not the standard one
+    segment_order: Option<Vec<Segment>>,
+    malformed: AtomicU64,
+    overflows: AtomicU64,
+    throttled: Mutex<HashMap<String, Instant>>,
+    drop_logger: Option<drop_log::DropLogger>,
+    default_tags: Vec<(String, String)>,
+    // default tags formatted as `name:value,...`
+    default_tag_block: String,
+    // pending newline-separated lines, if batching
+    batch: Option<Mutex<String>>,
+    // sampling generator shared by all threads, if seeded
+    sampler: Option<Box<dyn Sampler + Send + Sync>>,
+    max_payload: usize,
+    // applied to keys, if any
+    sanitizer: Option<Sanitizer>,
+    // emits allowed per key and flush, if capped
+    key_cap: Option<KeyCap>,
+    // count totals pending, if aggregating
+    aggregation: Option<aggregate::Aggregation>,
+    // packets that would have blocked, if retrying
+    retry: Option<retry::RetryQueue>,
+    packets_sent: AtomicU64,
+    bytes_sent: AtomicU64,
+    // hard errors only
+    send_errors: AtomicU64,
+    would_block: AtomicU64,
+}
+
+pub type StatsdClient = StatsdOutlet<UdpSocket>;
+
+impl StatsdClient {
+    /// Create a new `StatsdClient` sending packets to the specified `address`.
+    /// The first resolved address that can be connected to is used, see `connect_udp_first`.
+    /// Sent metric keys will be prepended with `prefix`.
+    /// Subsampling is performed according to `float_rate` where
+    /// - 1.0 is full sampling and
+    /// - 0.0 means _no_ samples will be taken
+    ///
+    /// See crate method `to_int_rate` for more details and a nice table.
+    /// Shorthand for `StatsdClient::builder`, returning an `InvalidInput` error for a rate out of range.
+    pub fn new<A: ToSocketAddrs>(address: A, prefix_str: &str, float_rate: f64) -> Result<StatsdClient> {
+        StatsdClient::builder(address).prefix(prefix_str).sample_rate(float_rate).build()
+    }
+
+    /// Create a new `StatsdClient` sending packets through `udp_socket`, configured by the caller
+    /// (e.g. its send buffer size or local address). The caller is responsible for having connected it
+    /// to the statsd server, as metrics are sent with `UdpSocket::send`; sends otherwise fail and are counted as errors.
+    /// The socket is used as is: unlike with `new`, it blocks when its send buffer is full unless set nonblocking.
+    /// Returns an `InvalidInput` error for a rate out of range.
+    pub fn from_socket(udp_socket: UdpSocket, prefix_str: &str, float_rate: f64) -> Result<StatsdClient> {
+        StatsdOutlet::outlet(udp_socket, prefix_str, float_rate)
+    }
+
+    /// Create a new `StatsdClient` like `new`, but fail if nothing appears to listen at `address`.
+    /// An empty probe datagram is sent, then any ICMP error (e.g. port unreachable) received within `wait` is returned.
+    /// This is best-effort only: UDP gives no delivery guarantee, and firewalls or remote hosts
+    /// commonly drop packets silently, so absence of an error does not mean a server is listening.
+    pub fn new_probed<A: ToSocketAddrs>(address: A, prefix_str: &str, float_rate: f64, wait: Duration) -> Result<StatsdClient> {
+        let udp_socket = connect_udp(address)?;
+        udp_socket.send(&[])?;
+        thread::sleep(wait);
+        if let Some(error) = udp_socket.take_error()? {
+            return Err(error)
+        }
+        StatsdOutlet::outlet(udp_socket, prefix_str, float_rate)
+    }
+
+    /// Create an independent client sending to the same server through a duplicate of the socket,
+    /// without resolving and binding again, e.g. to hand one to each worker thread.
+    /// The prefix and the settings inherited by `with_prefix` are copied; the clone has its own batch and counters,
+    /// and neither the drop logger, the sampler nor the aggregated counts of this client.
+    /// Clones do not share sampling state: without a sampler, sampling uses a generator per thread anyway,
+    /// so two clients on a thread draw from the same sequence whether they are clones or not.
+    pub fn try_clone(&self) -> Result<StatsdClient> {
+        Ok(self.derive(self.sender().try_clone()?, &self.prefix))
+    }
+}
+
+/// Open a nonblocking UDP socket connected to `address`, see `connect_udp_first`.
+fn connect_udp<A: ToSocketAddrs>(address: A) -> Result<UdpSocket> {
+    connect_udp_first(&address.to_socket_addrs()?.collect::<Vec<_>>(), None)
+}
+
+/// Open a nonblocking UDP socket connected to the first of the `targets` that can be connected to, in order.
+/// The socket is bound to `local` if given, to the wildcard address of the target's family otherwise.
+/// Targets of another family than `local` are skipped with an `InvalidInput` error.
+/// If none can be, the error lists every target tried along with its error.
+fn connect_udp_first(targets: &[SocketAddr], local: Option<SocketAddr>) -> Result<UdpSocket> {
+    let mut attempts = Vec::new();
+    let mut kind = ::std::io::ErrorKind::AddrNotAvailable;
+    for target in targets {
+        let local = match local {
+            Some(local) if local.is_ipv6() != target.is_ipv6() => {
+                kind = ::std::io::ErrorKind::InvalidInput;
+                let family = |address: SocketAddr| if address.is_ipv6() { "IPv6" } else { "IPv4" };
+                attempts.push(format!("{} ({} cannot be reached from {} local address {})", target, family(*target), family(local), local));
+                continue
+            }
+            Some(local) => local,
+            None if target.is_ipv6() => SocketAddr::from(([0u16; 8], 0)),
+            None => SocketAddr::from(([0u8; 4], 0)),
+        };
+        let connected = UdpSocket::bind(local) // NB: CLOEXEC by default
+            .and_then(|udp_socket| udp_socket.set_nonblocking(true).map(|_| udp_socket))
+            .and_then(|udp_socket| udp_socket.connect(target).map(|_| udp_socket));
+        match connected {
+            Ok(udp_socket) => return Ok(udp_socket),
+            Err(error) => {
+                kind = error.kind();
+                attempts.push(format!("{} ({})", target, error));
+            }
+        }
+    }
+    if attempts.is_empty() {
+        Err(::std::io::Error::new(kind, "the statsd address resolved to no address"))
+    } else {
+        Err(::std::io::Error::new(kind, format!("could not connect to any statsd address: {}", attempts.join(", "))))
+    }
+}
+
+/// Whether `error` is the transport refusing a packet too large for it. Only known on Unix.
+fn is_too_large(error: &::std::io::Error) -> bool {
+    #[cfg(unix)]
+    return error.raw_os_error() == Some(libc::EMSGSIZE);
+    #[cfg(not(unix))]
+    return false;
+}
+
+/// What to report for a time interval whose stop precedes its start.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Inverted {
+    /// Report nothing.
+    Skip,
+    /// Report a zero interval.
+    Zero,
+}
+
+/// Newline-separated lines sent together, bypassing the batch, see `StatsdOutlet::pack`.
+#[derive(Default)]
+struct Packet {
+    lines: String,
+    // the key of the first line, reported if the packet is lost
+    key: String,
+}
+
+/// Counters of a client's activity since its creation, see `StatsdOutlet::stats`.
+/// With batching, a packet holds many metrics.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct SenderStats {
+    /// Packets handed successfully to the sender.
+    pub packets: u64,
+    /// Bytes in the packets handed successfully to the sender.
+    pub bytes: u64,
+    /// Packets lost to send errors, other than `WouldBlock`.
+    pub errors: u64,
+    /// Packets dropped because sending would have blocked, e.g. with a full socket buffer.
+    pub would_block: u64,
+    /// Metrics dropped as malformed, see `malformed()`.
+    pub malformed: u64,
+    /// Values out of range when scaled or aggregated, see `overflows()`.
+    pub overflows: u64,
+}
+
+/// A point in time from which elapsed time can be determined
+pub struct StartTime (Instant);
+
+impl StartTime {
+    /// The current point in time
+    fn now() -> StartTime {
+        StartTime( Instant::now() )
+    }
+
+    /// The number of milliseconds elapsed between now and this StartTime
+    fn elapsed_ms(self) -> u64 {
+        duration_ms(&self.0.elapsed())
+    }
+}
+
+impl<S: SendStats> StatsdOutlet<S> {
+
+    /// Create a new `StatsdOutlet` handing formatted metrics to any `sender`.
+    /// This applies the same sampling, prefixing and formatting as `StatsdClient::new`
+    /// on top of a custom transport, which only has to implement `SendStats`.
+    /// Sent metric keys will be prepended with `prefix`.
+    /// Subsampling is performed according to `float_rate` where
+    /// - 1.0 is full sampling and
+    /// - 0.0 means _no_ samples will be taken
+    ///
+    /// See crate method `to_int_rate` for more details and a nice table.
+    /// A rate out of this range, or NaN, is an `InvalidInput` error.
+    pub fn outlet(sender: S, prefix_str: &str, float_rate: f64) -> Result<StatsdOutlet<S>> {
+        check_rate(float_rate)?;
+        // normalize -0 so that the rate suffix reads @0
+        let float_rate = float_rate + 0.0;
+        let prefix = prefix_str.to_string();
+        Ok(StatsdOutlet {
+            sender: Some(sender),
+            prefix,
+            int_rate: to_int_rate(float_rate),
+            float_rate,
+            suffixes: Suffixes::new(float_rate),
+            encoding: Encoding::Statsd,
+            separator: ":".to_string(),
+            segment_order: None,
+            malformed: AtomicU64::new(0),
+            overflows: AtomicU64::new(0),
+            throttled: Mutex::new(HashMap::new()),
+            drop_logger: None,
+            default_tags: Vec::new(),
+            default_tag_block: String::new(),
+            batch: None,
+            sampler: None,
+            max_payload: MAX_UDP_PAYLOAD,
+            sanitizer: None,
+            key_cap: None,
+            aggregation: None,
+            retry: None,
+            packets_sent: AtomicU64::new(0),
+            bytes_sent: AtomicU64::new(0),
+            send_errors: AtomicU64::new(0),
+            would_block: AtomicU64::new(0),
+        })
+    }
+
+    /// Report to statsd a count of items.
+    pub fn count<V: MetricValue>(&self, key: &str, value: V) {
+        self.count_reporting(key, value);
+    }
+
+    /// Report to statsd many counts at once, packing their lines into packets of up to the maximum payload size
+    /// instead of sending a packet per count. Each item is sampled on its own, as if reported by `count()`.
+    /// With batching or aggregation enabled, the items are simply reported by `count()`.
+    pub fn count_batch<'k, I: IntoIterator<Item = (&'k str, u64)>>(&self, items: I) {
+        if self.batch.is_some() || self.aggregation.is_some() {
+            for (key, value) in items { self.count(key, value) }
+            return
+        }
+        let mut packet = Packet::default();
+        let mut line = String::new();
+        for (key, value) in items {
+            if !self.sample(key) { continue }
+            line.clear();
+            self.encode_line(&mut line, Metric::Count { key, value }, &[]);
+            self.pack(&mut packet, key, &line);
+        }
+        self.send_packed(packet);
+    }
+
+    /// Report to statsd a count of items, returning `true` if the sample was taken and handed to the sender.
+    /// Send errors are not reflected, only the sampling decision is.
+    pub fn count_reporting<V: MetricValue>(&self, key: &str, value: V) -> bool {
+        self.sample(key) && self.send_count(key, value)
+    }
+
+    /// Add a sampled count to its aggregated total if counts are aggregated, send it otherwise.
+    fn send_count<V: MetricValue>(&self, key: &str, value: V) -> bool {
+        if let Some(ref aggregation) = self.aggregation {
+            if let Some(count) = value.to_count() {
+                self.aggregate(aggregation, key, count, false);
+                return true
+            }
+        }
+        self.send_metric(Metric::Count { key, value })
+    }
+
+    /// Report to statsd a count of items, returning `Ok(true)` if the sample was taken and sent (or batched),
+    /// `Ok(false)` if it was not taken and the send error otherwise. `WouldBlock` means the socket buffer was full.
+    pub fn try_count<V: MetricValue>(&self, key: &str, value: V) -> Result<bool> {
+        if !self.sample(key) { return Ok(false) }
+        self.try_send_metric(Metric::Count { key, value }, &[])
+    }
+
+    /// Report to statsd a non-cumulative (instant) count of items, returning the outcome as for `try_count`.
+    pub fn try_gauge<V: MetricValue>(&self, key: &str, value: V) -> Result<bool> {
+        if !self.sample(key) { return Ok(false) }
+        self.try_send_metric(Metric::Gauge { key, value }, &[])
+    }
+
+    /// Report to statsd a time interval, returning the outcome as for `try_count`.
+    pub fn try_time_interval_ms<V: MetricValue>(&self, key: &str, interval_ms: V) -> Result<bool> {
+        if !self.sample(key) { return Ok(false) }
+        self.try_send_metric(Metric::Timing { key, value: interval_ms }, &[])
+    }
+
+    /// Report to statsd a single item.
+    pub fn increment(&self, key: &str) {
+        self.count(key, 1)
+    }
+
+    /// Report to statsd a single item removed, as a count of `-1`.
+    pub fn decrement(&self, key: &str) {
+        if self.sample(key)  {
+            self.send_count(key, -1i64);
+        }
+    }
+
+    /// Report to statsd a count of items computed by `value`, which is only called if the sample is taken.
+    pub fn count_lazy<F: FnOnce() -> u64>(&self, key: &str, value: F) {
+        if self.sample(key)  {
+            self.send_count(key, value());
+        }
+    }
+
+    /// Report to statsd a count of items already extrapolated from the sampling rate:
+    /// an accepted sample at rate `r` is sent as `round(value / r)` without the `|@r` suffix,
+    /// so that values can be summed naively by servers ignoring sampling rates.
+    /// The estimate is unbiased but noisy, every accepted sample standing for `1 / r` calls.
+    /// With `with_aggregation()`, extrapolated values are totalled apart from other counts and also sent without the rate.
+    /// Returns `true` if the sample was taken.
+    pub fn count_extrapolated(&self, key: &str, value: u64) -> bool {
+        if self.sample(key)  {
+            let scaled = if self.float_rate > 0.0 { (value as f64 / self.float_rate).round() as u64 } else { value };
+            match (&self.aggregation, i64::try_from(scaled)) {
+                (Some(aggregation), Ok(count)) => self.aggregate(aggregation, key, count, true),
+                _ => { self.send_value(key, scaled, "|c"); }
+            }
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Report to statsd a count of items, formatting the metric line into `buf` instead of an internal buffer.
+    /// The buffer is cleared first. With a sender overriding `send_stats_str` (such as `UdpSocket`)
+    /// no allocation is performed as long as `buf` has enough capacity.
+    /// The line is encoded like any other (encoding, separator, segment order and default tags),
+    /// but always sent: counts are not summed by `with_aggregation`, which would need to own the key.
+    pub fn count_into(&self, buf: &mut String, key: &str, value: u64) {
+        if self.sample(key)  {
+            buf.clear();
+            self.encode_line(buf, Metric::Count { key, value }, &[]);
+            let result = match self.batch {
+                Some(ref batch) => self.batch_line(batch, buf),
+                None => self.deliver(buf),
+            };
+            if let Err(ref error) = result {
+                self.send_failed(Some(key), error);
+            }
+        }
+    }
+
+    /// Report to statsd a count of items, at most once per `cooldown` for a given key.
+    /// Sampled out calls do not start a cooldown. Every key throttled is remembered for the life of the client.
+    pub fn count_throttled(&self, key: &str, value: u64, cooldown: Duration) {
+        self.count_throttled_at(key, value, cooldown, Instant::now())
+    }
+
+    fn count_throttled_at(&self, key: &str, value: u64, cooldown: Duration, now: Instant) {
+        if self.sample(key)  {
+            {
+                let mut throttled = self.throttled.lock().unwrap();
+                match throttled.get_mut(key) {
+                    Some(last) if now.saturating_duration_since(*last) < cooldown => return self.log_drop(key, DropReason::RateLimited),
+                    Some(last) => *last = now,
+                    None => { throttled.insert(key.to_string(), now); }
+                }
+            }
+            self.send_count(key, value);
+        }
+    }
+
+    /// Report to statsd a non-cumulative (instant) count of items.
+    /// Negative values are taken by statsd for relative changes, see `gauge_delta`.
+    pub fn gauge<V: MetricValue>(&self, key: &str, value: V) {
+        if self.sample(key)  {
+            self.send_metric(Metric::Gauge { key, value });
+        }
+    }
+
+    /// Report to statsd a non-cumulative (instant) fractional value, e.g. a load average.
+    /// Values render in the shortest form, without a trailing `.0` (`1.0` is sent as `1`).
+    /// Infinite, NaN and negative values (which statsd would take for a relative change) are not sent and are counted as `malformed()`.
+    pub fn gauge_f64(&self, key: &str, value: f64) {
+        if self.check_f64(value) && self.sample(key)  {
+            self.send_metric(Metric::Gauge { key, value });
+        }
+    }
+
+    /// Report to statsd a fractional time interval, for sub-millisecond precision.
+    /// Formatting and rejected values are as for `gauge_f64`.
+    pub fn time_interval_ms_f64(&self, key: &str, interval_ms: f64) {
+        if self.check_f64(interval_ms) && self.sample(key)  {
+            self.send_metric(Metric::Timing { key, value: interval_ms });
+        }
+    }
+
+    /// Whether `value` is finite and not negative, counting it as malformed otherwise.
+    fn check_f64(&self, value: f64) -> bool {
+        // -0.0 passes, it is written as 0
+        if !value.is_finite() || value < 0.0 {
+            self.malformed.fetch_add(1, Ordering::Relaxed);
+            return false
+        }
+        true
+    }
+
+    /// Report to statsd a relative change of a gauge, always signed (`+0` included) so that it is not taken for an absolute value.
+    /// Other encodings have no relative gauges, changes are not sent with them and counted as `malformed()` instead.
+    pub fn gauge_delta(&self, key: &str, delta: i64) {
+        if self.sample(key) && !self.rejects_statsd_lines() {
+            self.send_metric(Metric::Gauge { key, value: value::Signed(delta) });
+        }
+    }
+
+    /// Report to statsd a value to be aggregated as a distribution, like timings but without implying a unit.
+    pub fn histogram(&self, key: &str, value: u64) {
+        if self.sample(key)  {
+            self.send_metric(Metric::Histogram { key, value });
+        }
+    }
+
+    /// Report to statsd a value of a DogStatsD distribution, aggregated by the server across all hosts
+    /// (so that percentiles are global) rather than per host like `histogram`.
+    pub fn distribution<V: MetricValue>(&self, key: &str, value: V) {
+        if self.sample(key)  {
+            self.send_metric(Metric::Distribution { key, value });
+        }
+    }
+
+    /// Report to statsd a member of a set, so that the server counts unique members per interval.
+    /// Members containing `:`, `|` or newlines would corrupt the metric line,
+    /// such members are not sent and are counted as `malformed()` instead.
+    pub fn set(&self, key: &str, member: &str) {
+        if member.contains([':', '|', '\n']) {
+            self.malformed.fetch_add(1, Ordering::Relaxed);
+            return
+        }
+        if self.sample(key)  {
+            self.send( &[key, &self.separator, member, &self.suffixes.set] )
+        }
+    }
+
+    /// Report to statsd a boolean state as a gauge, `1` for true and `0` for false.
+    pub fn flag(&self, key: &str, state: bool) {
+        self.gauge(key, if state { 1 } else { 0 })
+    }
+
+    /// Report to statsd a time interval of items.
+    pub fn time_interval_ms<V: MetricValue>(&self, key: &str, interval_ms: V) {
+        if self.sample(key)  {
+            self.send_metric(Metric::Timing { key, value: interval_ms });
+        }
+    }
+
+    /// Report to statsd a count of items annotated with a `unit` such as `bytes` or `requests`.
+    /// The unit is rendered as a trailing `|#unit:<unit>` block, as conventionally understood by DogStatsD.
+    pub fn count_with_unit(&self, key: &str, value: u64, unit: &str) {
+        if self.sample(key)  {
+            self.send_metric_tagged(Metric::Count { key, value }, &[("unit", unit)]);
+        }
+    }
+
+    /// Report to statsd a non-cumulative (instant) count of items annotated with a `unit`.
+    pub fn gauge_with_unit(&self, key: &str, value: u64, unit: &str) {
+        if self.sample(key)  {
+            self.send_metric_tagged(Metric::Gauge { key, value }, &[("unit", unit)]);
+        }
+    }
+
+    /// Report to statsd a time interval annotated with a `unit`.
+    pub fn time_interval_ms_with_unit(&self, key: &str, interval_ms: u64, unit: &str) {
+        if self.sample(key)  {
+            self.send_metric_tagged(Metric::Timing { key, value: interval_ms }, &[("unit", unit)]);
+        }
+    }
+
+    /// Report to statsd a time interval given as a `Duration`.
+    /// It is truncated to whole milliseconds, so sub-millisecond durations report `0`, and saturates at `u64::MAX` milliseconds.
+    pub fn time_duration(&self, key: &str, duration: Duration) {
+        if self.sample(key)  {
+            self.send_time_ms(key, duration_ms(&duration));
+        }
+    }
+
+    /// Report to statsd many time intervals under the same key.
+    /// Each duration is sampled individually and truncated to whole milliseconds, so sub-millisecond durations report `0`.
+    /// Accepted values are packed as multi-value lines (`key:1|ms:2|ms`) of at most the maximum payload size.
+    pub fn time_durations(&self, key: &str, durations: &[Duration]) {
+        let mut values = String::new();
+        for duration in durations {
+            if !self.sample(key) { continue }
+            let value = format!("{}{}{}", self.separator, duration_ms(duration), self.suffixes.timing);
+            if !values.is_empty() && self.prefix.len() + key.len() + values.len() + value.len() > self.max_payload {
+                self.send( &[key, &values] );
+                values.clear();
+            }
+            values.push_str(&value);
+        }
+        if !values.is_empty() {
+            self.send( &[key, &values] )
+        }
+    }
+
+    /// Report to statsd a pre-bucketed histogram, as one `key.bucket_label` count per non-empty bucket.
+    /// The whole histogram is sampled at once so buckets stay consistent with each other.
+    /// Lines are packed newline-separated into packets of at most the maximum payload size.
+    pub fn histogram_buckets(&self, key: &str, buckets: &[(&str, u64)]) {
+        if !self.sample(key) { return }
+        let mut packet = Packet::default();
+        let mut line = String::new();
+        for &(label, count) in buckets {
+            if count == 0 { continue }
+            let bucket = format!("{}.{}", key, label);
+            line.clear();
+            self.encode_line(&mut line, Metric::Count { key: &bucket, value: count }, &[]);
+            self.pack(&mut packet, key, &line);
+        }
+        self.send_packed(packet);
+    }
+
+    /// Report to statsd the time interval between two recorded instants.
+    /// What happens if `stop` is before `start` is decided by `inverted`.
+    pub fn time_between(&self, key: &str, start: Instant, stop: Instant, inverted: Inverted) {
+        self.time_checked(key, stop.checked_duration_since(start).map(|d| duration_ms(&d)), inverted)
+    }
+
+    /// Report to statsd the time interval between two Unix timestamps in milliseconds.
+    /// What happens if `stop_ms` is before `start_ms` is decided by `inverted`.
+    pub fn time_between_unix_ms(&self, key: &str, start_ms: u64, stop_ms: u64, inverted: Inverted) {
+        self.time_checked(key, stop_ms.checked_sub(start_ms), inverted)
+    }
+
+    fn time_checked(&self, key: &str, interval_ms: Option<u64>, inverted: Inverted) {
+        match (interval_ms, inverted) {
+            (Some(interval_ms), _) => self.time_interval_ms(key, interval_ms),
+            (None, Inverted::Zero) => self.time_interval_ms(key, 0),
+            (None, Inverted::Skip) => {}
+        }
+    }
+
+    /// Query current time to use eventually with `stop_time()`
+    pub fn start_time(&self) -> StartTime {
+        StartTime::now()
+    }
+
+    /// An efficient timer that skips querying for stop time if sample will not be collected.
+    /// Caveat : Random sampling overhead of a few ns will be included in any reported time interval.
+    pub fn stop_time(&self, key: &str, start_time: StartTime) {
+        if self.sample(key)  {
+            self.send_time_ms(key, start_time.elapsed_ms());
+        }
+    }
+
+    /// Run `f` and report the milliseconds it took under `key`, returning its result.
+    /// The time is reported whatever `f` returns, including an `Err` result.
+    /// If `f` panics, nothing is reported.
+    pub fn time_closure<F: FnOnce() -> R, R>(&self, key: &str, f: F) -> R {
+        let start_time = self.start_time();
+        let result = f();
+        self.stop_time(key, start_time);
+        result
+    }
+
+    fn send_time_ms(&self, key: &str, interval_ms: u64) {
+        self.send_metric(Metric::Timing { key, value: interval_ms });
+    }
+
+    /// Consume the client, returning the underlying sender.
+    /// Any batched metrics are flushed first.
+    pub fn into_inner(mut self) -> S {
+        self.flush();
+        self.sender.take().expect("sender is only taken once")
+    }
+
+    fn sender(&self) -> &S {
+        self.sender.as_ref().expect("sender is only taken when consumed")
+    }
+
+    /// Number of metric lines dropped because they had no content besides the prefix,
+    /// because a set member contained reserved characters or because a value could not be represented (e.g. NaN).
+    pub fn malformed(&self) -> u64 {
+        self.malformed.load(Ordering::Relaxed)
+    }
+
+    /// Concatenate text parts into a single buffer and send it over UDP
+    /// Lines without any content would only contain the prefix and are dropped instead.
+    fn send(&self, strings: &[&str]) {
+        self.send_tagged(strings, &[])
+    }
+
+    /// Like `send`, appending the default tags followed by the concatenated `tags` parts in a single `|#` block.
+    fn send_tagged(&self, strings: &[&str], tags: &[&str]) {
+        if strings.iter().all(|s| s.is_empty()) {
+            self.malformed.fetch_add(1, Ordering::Relaxed);
+            return
+        }
+        self.with_line(|str| {
+            self.push_key(str, strings[0]);
+            for s in &strings[1..] { str.push_str(s); }
+            let _ = self.send_line(strings[0], str, tags);
+        })
+    }
+
+    /// Encode and send `metric`, writing the value straight into the line.
+    /// Invalid values are counted as `malformed()` instead, returning `false`. Send errors are counted only.
+    fn send_metric<V: MetricValue>(&self, metric: Metric<V>) -> bool {
+        self.send_metric_tagged(metric, &[])
+    }
+
+    /// Like `send_metric`, with `tags` after the default tags.
+    fn send_metric_tagged<V: MetricValue>(&self, metric: Metric<V>, tags: &[(&str, &str)]) -> bool {
+        self.try_send_metric(metric, tags).unwrap_or(true)
+    }
+
+    /// Like `send_metric_tagged`, returning send errors. Tags invalid in statsd lines are counted as `malformed()` like invalid values.
+    fn try_send_metric<V: MetricValue>(&self, metric: Metric<V>, tags: &[(&str, &str)]) -> Result<bool> {
+        if !metric.value().is_valid() || (self.encoding == Encoding::Statsd && !valid_tags(tags)) {
+            self.malformed.fetch_add(1, Ordering::Relaxed);
+            return Ok(false)
+        }
+        let key = metric.key();
+        self.with_line(|str| {
+            self.encode_line(str, metric, tags);
+            self.send_formatted(key, str).map(|_| true)
+        })
+    }
+
+    /// Append the line for `metric` in the client's encoding to `str`, with its key sanitized
+    /// and `tags` after the default tags.
+    fn encode_line<V: MetricValue>(&self, str: &mut String, metric: Metric<V>, tags: &[(&str, &str)]) {
+        let sanitized = self.sanitized(metric.key());
+        let metric = metric.with_key(&sanitized);
+        match self.encoding {
+            Encoding::Statsd => match self.segment_order {
+                None => {
+                    encode_separated_into(str, &metric, &self.prefix, &self.separator, &self.suffixes);
+                    self.push_tags(str, &[&tag_list(tags)]);
+                }
+                Some(ref order) => for segment in order {
+                    match *segment {
+                        Segment::Prefix => str.push_str(&self.prefix),
+                        Segment::Key => str.push_str(metric.key()),
+                        Segment::Value => {
+                            str.push_str(&self.separator);
+                            metric.value().write_value(str);
+                        }
+                        Segment::TypeSuffix => {
+                            str.push('|');
+                            str.push_str(metric.type_code());
+                        }
+                        Segment::RateSuffix => str.push_str(&self.suffixes.rate),
+                        Segment::Tags => self.push_tags(str, &[&tag_list(tags)]),
+                    }
+                },
+            },
+            #[cfg(feature = "graphite")]
+            Encoding::Graphite => encode_graphite_into(str, &metric, &self.prefix, graphite::unix_time()),
+            #[cfg(feature = "influx")]
+            Encoding::Influx => {
+                let default_tags: Vec<(&str, &str)> = self.default_tags.iter().map(|(name, value)| (name.as_str(), value.as_str())).collect();
+                encode_influx_into(str, &metric, &self.prefix, &[&default_tags[..], tags].concat(), influx::unix_time_ns());
+            }
+        }
+    }
+
+    /// Send `key:value` followed by `suffix`, for suffixes other than the client's (e.g. another sampling rate).
+    /// Invalid values are counted as `malformed()` instead, returning `false`. Send errors are counted only.
+    fn send_value<V: MetricValue>(&self, key: &str, value: V, suffix: &str) -> bool {
+        self.send_value_tagged(key, value, &[suffix], &[])
+    }
+
+    /// Like `send_value`, with the concatenated `suffix` parts and the `tags` parts in the `|#` block as for `send_tagged`.
+    fn send_value_tagged<V: MetricValue>(&self, key: &str, value: V, suffix: &[&str], tags: &[&str]) -> bool {
+        self.try_send_value_tagged(key, value, suffix, tags).unwrap_or(true)
+    }
+
+    /// Like `send_value_tagged`, returning send errors. There must be at least one `suffix` part.
+    fn try_send_value_tagged<V: MetricValue>(&self, key: &str, value: V, suffix: &[&str], tags: &[&str]) -> Result<bool> {
+        if !value.is_valid() {
+            self.malformed.fetch_add(1, Ordering::Relaxed);
+            return Ok(false)
+        }
+        self.with_line(|str| {
+            write_metric_separated(str, &self.prefix, &self.sanitized(key), &self.separator, value, suffix[0]);
+            for s in &suffix[1..] { str.push_str(s); }
+            self.send_line(key, str, tags).map(|_| true)
+        })
+    }
+
+    /// Call `format` with this thread's line buffer, cleared. The line must be sent before `format` returns.
+    /// The buffer is taken out while in use, so a nested call (e.g. from a sender itself reporting metrics)
+    /// or a call while the thread is exiting gets a fresh one.
+    fn with_line<R, F: FnOnce(&mut String) -> R>(&self, format: F) -> R {
+        let mut line = LINE.try_with(Cell::take).unwrap_or_default();
+        line.clear();
+        let result = format(&mut line);
+        let _ = LINE.try_with(|cell| cell.set(line));
+        result
+    }
+
+    /// Append the prefix and `key` to `str`, sanitizing the key if a sanitizer is set.
+    fn push_key(&self, str: &mut String, key: &str) {
+        str.push_str(&self.prefix);
+        str.push_str(&self.sanitized(key));
+    }
+
+    /// The key, sanitized if a sanitizer is set.
+    fn sanitized<'a>(&self, key: &'a str) -> Cow<'a, str> {
+        match self.sanitizer {
+            Some(ref sanitizer) => sanitizer.sanitize(key),
+            None => Cow::Borrowed(key),
+        }
+    }
+
+    /// Append the tags block to a formatted metric `str` and send or batch it.
+    /// Errors are counted and reported to the drop logger under `key`.
+    fn send_line(&self, key: &str, str: &mut String, tags: &[&str]) -> Result<()> {
+        if self.rejects_statsd_lines() {
+            return Ok(())
+        }
+        self.push_tags(str, tags);
+        self.send_formatted(key, str)
+    }
+
+    /// Count a line only expressible in the statsd format (e.g. a set or an event) as `malformed()`
+    /// if the client encodes metrics in another format, returning whether it must be dropped.
+    fn rejects_statsd_lines(&self) -> bool {
+        let rejected = self.encoding != Encoding::Statsd;
+        if rejected {
+            self.malformed.fetch_add(1, Ordering::Relaxed);
+        }
+        rejected
+    }
+
+    /// Append the `|#` block of the default tags followed by the concatenated `tags` parts, if there are any.
+    fn push_tags(&self, str: &mut String, tags: &[&str]) {
+        let has_tags = tags.iter().any(|t| !t.is_empty());
+        if !self.default_tag_block.is_empty() || has_tags {
+            str.push_str("|#");
+            str.push_str(&self.default_tag_block);
+            if !self.default_tag_block.is_empty() && has_tags { str.push(',') }
+            for t in tags { str.push_str(t); }
+        }
+    }
+
+    /// Send or batch the complete line `str`.
+    /// Errors are counted and reported to the drop logger under `key`.
+    fn send_formatted(&self, key: &str, str: &str) -> Result<()> {
+        let result = match self.batch {
+            Some(ref batch) => self.batch_line(batch, str),
+            None => self.deliver(str),
+        };
+        if let Err(ref error) = result {
+            self.send_failed(Some(key), error);
+        }
+        result
+    }
+
+    /// Send a packet of lines packed by the caller, bypassing the batch.
+    fn send_packet(&self, key: &str, packet: String) {
+        if let Err(ref error) = self.deliver(&packet) {
+            self.send_failed(Some(key), error);
+        }
+    }
+
+    /// Append the `line` of metric `key` to `packet`, first sending the packet if the line would not fit in it.
+    /// With batching, the line goes to the batch instead so that it is sent after the metrics reported before it.
+    fn pack(&self, packet: &mut Packet, key: &str, line: &str) {
+        if self.batch.is_some() {
+            let _ = self.send_formatted(key, line);
+            return
+        }
+        if !packet.lines.is_empty() && packet.lines.len() + 1 + line.len() > self.max_payload {
+            self.send_packet(&packet.key, ::std::mem::take(&mut packet.lines));
+        }
+        if packet.lines.is_empty() {
+            packet.key.clear();
+            packet.key.push_str(key);
+        } else {
+            packet.lines.push('\n');
+        }
+        packet.lines.push_str(line);
+    }
+
+    /// Send the rest of a `packet` filled by `pack`, if any.
+    fn send_packed(&self, packet: Packet) {
+        if !packet.lines.is_empty() {
+            self.send_packet(&packet.key, packet.lines)
+        }
+    }
+
+    /// Hand a packet to the sender, or to the retry queue if any, counting the outcome.
+    fn deliver(&self, packet: &str) -> Result<()> {
+        if let Some(ref retry) = self.retry {
+            return self.deliver_queued(retry, packet)
+        }
+        let result = self.sender().send_stats_str(packet);
+        self.count_delivery(packet, &result);
+        result
+    }
+
+    /// Count the `result` of sending `packet`.
+    fn count_delivery(&self, packet: &str, result: &Result<()>) {
+        match *result {
+            Ok(()) => {
+                self.packets_sent.fetch_add(1, Ordering::Relaxed);
+                self.bytes_sent.fetch_add(packet.len() as u64, Ordering::Relaxed);
+            }
+            Err(ref error) if error.kind() == ::std::io::ErrorKind::WouldBlock => { self.would_block.fetch_add(1, Ordering::Relaxed); }
+            Err(_) => { self.send_errors.fetch_add(1, Ordering::Relaxed); }
+        }
+    }
+
+    /// Report a send error to the drop logger, if the `key` of the metric lost is known.
+    /// `WouldBlock` errors mean a queue was full, `EMSGSIZE` that the packet was too large for the transport.
+    fn send_failed(&self, key: Option<&str>, error: &::std::io::Error) {
+        if let Some(key) = key {
+            let reason = match error.kind() {
+                ::std::io::ErrorKind::WouldBlock => DropReason::QueueFull,
+                _ if is_too_large(error) => DropReason::PayloadTooLarge,
+                _ => DropReason::SendError,
+            };
+            self.log_drop(key, reason);
+        }
+    }
+
+    /// Number of sends that failed, other than those that would have blocked which are counted by `would_block()`.
+    /// The same as `stats().errors`.
+    pub fn send_errors(&self) -> u64 {
+        self.send_errors.load(Ordering::Relaxed)
+    }
+
+    /// Number of packets dropped because sending would have blocked, i.e. the socket send buffer was full.
+    /// Unlike other send errors, these signal backpressure: a growing count calls for a larger `SO_SNDBUF` or batching.
+    pub fn would_block(&self) -> u64 {
+        self.would_block.load(Ordering::Relaxed)
+    }
+
+    /// A snapshot of the client's own health counters.
+    pub fn stats(&self) -> SenderStats {
+        SenderStats {
+            packets: self.packets_sent.load(Ordering::Relaxed),
+            bytes: self.bytes_sent.load(Ordering::Relaxed),
+            errors: self.send_errors.load(Ordering::Relaxed),
+            would_block: self.would_block.load(Ordering::Relaxed),
+            malformed: self.malformed.load(Ordering::Relaxed),
+            overflows: self.overflows.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Pack packets up to `bytes` long rather than 576 bytes, the size safe on any network path.
+    /// Larger packets suit the loopback interface or jumbo frames. A metric line longer than that is still sent on its own.
+    pub fn with_max_payload(mut self, bytes: usize) -> StatsdOutlet<S> {
+        self.max_payload = bytes;
+        self
+    }
+
+    /// Draw sampling decisions from a generator seeded with `seed` instead of the clock-seeded per-thread generators,
+    /// so that the samples taken are reproducible. The generator is shared by all threads using this client,
+    /// which makes sampling slower under contention and reproducible only for a given order of calls.
+    pub fn with_seed(self, seed: u64) -> StatsdOutlet<S> {
+        self.with_sampler(sampler::SeededSampler::new(seed))
+    }
+
+    /// Whether the per-thread sampling generators are reseeded in processes forked after their creation, the default,
+    /// so that the children of a pre-fork server do not sample in lockstep. Without reseeding, children make
+    /// the same sampling decisions as their parent would. Either way, replaces any sampler given before.
+    pub fn with_fork_reseeding(mut self, reseed: bool) -> StatsdOutlet<S> {
+        self.sampler = if reseed { None } else { Some(Box::new(sampler::InheritedSampler)) };
+        self
+    }
+
+    /// Make sampling decisions with `sampler` instead of the per-thread generators, e.g. a `HashSampler`.
+    pub fn with_sampler<T: Sampler + Send + Sync + 'static>(mut self, sampler: T) -> StatsdOutlet<S> {
+        self.sampler = Some(Box::new(sampler));
+        self
+    }
+
+    /// Pass every metric key through `sanitizer`, e.g. `Sanitizer::default()` replacing the characters
+    /// reserved by the statsd format with `_` so that a key cannot corrupt its line or smuggle in another metric.
+    /// The prefix is not sanitized. Keys are only copied when a replacement is needed.
+    pub fn with_sanitizer(mut self, sanitizer: Sanitizer) -> StatsdOutlet<S> {
+        self.sanitizer = Some(sanitizer);
+        self
+    }
+
+    /// Encode counts, gauges, timings, histograms and distributions as `encoding` lines instead of statsd lines.
+    /// Each format other than statsd requires its cargo feature, `graphite` or `influx`.
+    /// With `Encoding::Graphite`, lines carry the current Unix time and the prefix is joined to keys with `.`.
+    /// Graphite has no metric types, so counts and gauges become indistinguishable `path value timestamp` lines,
+    /// and no sampling rate: use a rate of 1.0, as sampled counts are not scaled back up.
+    /// Graphite lines carry no tags. With `Encoding::Influx`, keys are measurements carrying the default
+    /// and per-call tags, with the current time in nanoseconds; types and rates are lost as with Graphite.
+    /// In both formats, metrics only expressible as statsd lines (sets, multiple timings, per-call rates,
+    /// events, service checks) are counted as `malformed()` instead of being sent.
+    pub fn with_encoding(mut self, encoding: Encoding) -> StatsdOutlet<S> {
+        self.encoding = encoding;
+        self
+    }
+
+    /// Write `separator` between keys and values instead of `:`, and `prefix_joiner` between the prefix
+    /// (if not empty) and keys, e.g. `"="` and `"/"` for a collector expecting `prefix/key=value|c` lines.
+    /// The joiner becomes part of the prefix, so prefixes appended by `with_prefix` follow it.
+    /// Only statsd lines are affected, see `with_encoding`.
+    pub fn with_separator(mut self, separator: &str, prefix_joiner: &str) -> StatsdOutlet<S> {
+        self.separator = separator.to_string();
+        if !self.prefix.is_empty() {
+            self.prefix.push_str(prefix_joiner);
+        }
+        self
+    }
+
+    /// Write the parts of statsd lines in `order` instead of `STATSD_ORDER`, e.g. with the tags before the rate
+    /// or the prefix after the key, to match a server's parser. The prefix and key must come first, in any order,
+    /// followed by the value, the other segments following in any order: any other `order` is an `InvalidInput` error.
+    /// Only counts, gauges, timings, histograms and distributions follow the order, other lines keep the standard one.
+    pub fn with_segment_order(mut self, order: &[Segment]) -> Result<StatsdOutlet<S>> {
+        check_order(order)?;
+        self.segment_order = if order == STATSD_ORDER { None } else { Some(order.to_vec()) };
+        Ok(self)
+    }
+
+    /// Derive a client sending through this client's sender, with `extra` appended to the prefix.
+    /// The child borrows the sender, so it cannot outlive this client. The sampling rate, default tags,
+    /// maximum payload, sanitizer, encoding, separator, segment order and batching are inherited, but the child keeps its own batch and counters,
+    /// and has neither the drop logger nor the sampler of this client.
+    pub fn with_prefix(&self, extra: &str) -> StatsdOutlet<&S> {
+        self.derive(self.sender(), &format!("{}{}", self.prefix, extra))
+    }
+
+    /// A client sending through `sender` under `prefix`, with the settings inherited by `with_prefix`.
+    fn derive<T: SendStats>(&self, sender: T, prefix: &str) -> StatsdOutlet<T> {
+        let mut child = StatsdOutlet::outlet(sender, prefix, self.float_rate)
+            .expect("rate was already validated")
+            .with_max_payload(self.max_payload);
+        child.default_tags = self.default_tags.clone();
+        child.default_tag_block = self.default_tag_block.clone();
+        child.sanitizer = self.sanitizer.clone();
+        child.encoding = self.encoding;
+        child.separator = self.separator.clone();
+        child.segment_order = self.segment_order.clone();
+        if self.batch.is_some() {
+            child = child.with_batching();
+        }
+        child
+    }
+
+    /// Make the sampling decision for `key` at `int_rate` with the sampler if any.
+    fn accept(&self, int_rate: u32, key: &str) -> bool {
+        match self.sampler {
+            Some(ref sampler) => sampler.accept(int_rate, key),
+            None => accept_sample(int_rate),
+        }
+    }
+
+    /// Send metrics in packets holding as many newline-separated lines as fit in the maximum payload size
+    /// instead of one packet per metric. A packet is sent when the next line would not fit in it
+    /// and on `flush()`, lines longer than the limit are sent on their own.
+    pub fn with_batching(mut self) -> StatsdOutlet<S> {
+        self.batch = Some(Mutex::new(String::with_capacity(self.max_payload)));
+        self
+    }
+
+    /// Send any aggregated counts, then any metrics pending in the batch, then any packets queued for retry.
+    /// Does nothing if neither aggregation, batching nor retrying is enabled, or if nothing is pending.
+    /// Totals and the batch are cleared even if sending them fails.
+    pub fn flush(&self) {
+        let _ = self.try_flush();
+    }
+
+    /// Like `flush`, returning any send error.
+    pub fn try_flush(&self) -> Result<()> {
+        if let Some(ref key_cap) = self.key_cap {
+            key_cap.reset();
+        }
+        let counted = self.flush_counts();
+        let batched = self.flush_batch();
+        self.flush_retries().and(counted).and(batched)
+    }
+
+    /// Send the metrics pending in the batch, if any.
+    fn flush_batch(&self) -> Result<()> {
+        if let Some(ref batch) = self.batch {
+            let mut batch = batch.lock().unwrap();
+            if !batch.is_empty() {
+                let result = self.deliver(&batch);
+                batch.clear();
+                if let Err(ref error) = result {
+                    self.send_failed(None, error);
+                }
+                return result
+            }
+        }
+        Ok(())
+    }
+
+    /// Add `line` to the batch, returning the error of sending the batch if that was needed.
+    fn batch_line(&self, batch: &Mutex<String>, line: &str) -> Result<()> {
+        let mut batch = batch.lock().unwrap();
+        let mut result = Ok(());
+        if !batch.is_empty() && batch.len() + 1 + line.len() > self.max_payload {
+            result = self.deliver(&batch);
+            batch.clear();
+        }
+        if !batch.is_empty() {
+            batch.push('\n');
+        }
+        batch.push_str(line);
+        if batch.len() >= self.max_payload {
+            result = result.and(self.deliver(&batch));
+            batch.clear();
+        }
+        result
+    }
+
+}
+
+impl<S: SendStats> Drop for StatsdOutlet<S> {
+    /// Flush any aggregated or batched metrics. Send errors are counted in `send_errors()` or `would_block()` but cannot be returned.
+    fn drop(&mut self) {
+        if self.sender.is_some() {
+            self.flush();
+        }
+    }
+}
+
+/// Convert a floating point sampling rate to an integer so that a fast integer RNG can be used
+/// Float rate range is between 1.0 (send 100% of the samples) and 0.0 (_no_ samples taken)
+/// .    | float rate | int rate | percentage
+/// ---- | ---------- | -------- | ----
+/// all  | 1.0        | 0x0      | 100%
+/// none | 0.0        | 0xFFFFFFFF | 0%
+/// The rate must have been validated by `check_rate`.
+fn to_int_rate(float_rate: f64) -> u32 {
+    debug_assert!((0.0..=1.0).contains(&float_rate));
+    ((1.0 - float_rate) * u32::MAX as f64) as u32
+}
+
+/// Reject sampling rates outside of 0.0 to 1.0, including NaN.
+fn check_rate(float_rate: f64) -> Result<()> {
+    if (0.0..=1.0).contains(&float_rate) {
+        Ok(())
+    } else {
+        Err(::std::io::Error::new(::std::io::ErrorKind::InvalidInput, format!("sample rate {} is not within 0.0 to 1.0", float_rate)))
+    }
+}
+
+/// Whole milliseconds in `duration`, saturating to `u64::MAX`.
+fn duration_ms(duration: &Duration) -> u64 {
+    u64::try_from(duration.as_millis()).unwrap_or(u64::MAX)
+}
+
+fn accept_sample(int_rate: u32) -> bool {
+    pcg32::random() > int_rate
+}
+
+/// A convenience macro to wrap a block or an expression with a start / stop timer.
+/// Elapsed time is sent to the supplied statsd client after the computation has been performed.
+/// Expression result (if any) is transparently returned.
+#[macro_export]
+macro_rules! time {
+    ($client: expr, $key: expr, $body: block) => ({
+        let start_time = $client.start_time();
+        let result = $body;
+        $client.stop_time($key, start_time);
+        result
+    });
+    ($client: expr, $key: expr, $body: expr) => ({
+        let start_time = $client.start_time();
+        let result = $body;
+        $client.stop_time($key, start_time);
+        result
+    });
+}
+
+
+/// Integrated testing with a live statsd server can be performed according to the instructions in the README.
+#[cfg(test)]
+mod tests {
+
+    use pcg32;
+    use super::{AlertType, EventOptions, EventPriority, AdaptiveOutlet, AsyncSender, CardinalitySet, DecayingGauge, Deduplicator, DeltaCounter, DropReason, DryRun, EmaGauge, FileSink, Inverted, KeyCap, HashSampler, Layered, LogSender, OnReset, Pcg32Sampler, Sampler, Sharded, PercentileTracker, Sanitizer, SenderStats, Invalid, Overflow, Problem, Rounding, Scale, ServiceStatus, StatsdOutlet, TeeSender, ThreadLocalClient};
+    use std::alloc::{GlobalAlloc, Layout, System};
+    use std::borrow::Cow;
+    use std::cell::{Cell, RefCell};
+    use std::net::{SocketAddr, UdpSocket};
+    use std::fs;
+    use std::io::{self, ErrorKind};
+    use std::panic;
+    use std::sync::{Arc, Mutex};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::mpsc::{self, Sender, SyncSender};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    impl super::SendStats for RefCell<Vec<String>> {
+        fn send_stats(&self, str: String) -> io::Result<()> {
+            self.borrow_mut().push(str);
+            Ok(())
+        }
+    }
+
+    fn test_client() -> StatsdOutlet<RefCell<Vec<String>>> {
+        StatsdOutlet::outlet(RefCell::new(Vec::new()), "", super::FULL_SAMPLING_RATE).unwrap()
+    }
+
+    fn test_sampling_client() -> StatsdOutlet<RefCell<Vec<String>>> {
+        StatsdOutlet::outlet(RefCell::new(Vec::new()), "", 0.999).unwrap().with_seed(0x5eed)
+    }
+
+    #[test]
+    fn test_seeded_sampling() {
+        let a = StatsdOutlet::outlet(RefCell::new(Vec::new()), "", 0.5).unwrap().with_seed(42);
+        let b = StatsdOutlet::outlet(RefCell::new(Vec::new()), "", 0.5).unwrap().with_seed(42);
+        let c = StatsdOutlet::outlet(RefCell::new(Vec::new()), "", 0.5).unwrap().with_seed(43);
+        let taken = |statsd: &StatsdOutlet<RefCell<Vec<String>>>| (0..64).map(|i| statsd.count_reporting("k", i)).collect::<Vec<bool>>();
+        let (a, b, c) = (taken(&a), taken(&b), taken(&c));
+        assert_eq!(a, b);
+        assert_ne!(a, c);
+        assert!(a.contains(&true) && a.contains(&false));
+    }
+
+    #[test]
+    fn test_count() {
+        let statsd = test_client(); 
+        statsd.count("bouring", 22);
+        let str = statsd.sender().borrow_mut().pop();
+        assert_eq!(str.unwrap(), "bouring:22|c")
+    }
+
+    #[test]
+    fn test_count_reporting() {
+        let statsd = test_client();
+        assert!(statsd.count_reporting("bouring", 22));
+        assert_eq!(statsd.sender().borrow_mut().pop().unwrap(), "bouring:22|c");
+
+        let statsd = StatsdOutlet::outlet(RefCell::new(Vec::new()), "", 0.0).unwrap();
+        for _ in 0..1000 { assert!(!statsd.count_reporting("bouring", 22)) }
+        assert!(statsd.sender().borrow().is_empty());
+    }
+
+    #[test]
+    fn test_gauge() {
+        let statsd = test_client();
+        statsd.gauge("bearing", 33);
+        let str = statsd.sender().borrow_mut().pop();
+        assert_eq!(str.unwrap(), "bearing:33|g")
+    }
+
+    #[test]
+    fn test_increment_decrement() {
+        let statsd = test_client();
+        statsd.increment("hits");
+        statsd.decrement("hits");
+        assert_eq!(*statsd.sender().borrow(), vec!["hits:1|c", "hits:-1|c"]);
+
+        let statsd = test_sampling_client();
+        while statsd.sender().borrow().is_empty() { statsd.decrement("hits") }
+        assert_eq!(*statsd.sender().borrow(), vec!["hits:-1|c|@0.999"]);
+
+        let statsd = test_client().with_aggregation(Duration::from_secs(3600));
+        statsd.increment("hits");
+        statsd.decrement("hits");
+        assert!(statsd.sender().borrow().is_empty());
+        statsd.flush();
+        assert_eq!(*statsd.sender().borrow(), vec!["hits:0|c"]);
+    }
+
+    #[test]
+    fn test_metric_values() {
+        let statsd = test_client();
+        statsd.count("n", 3_u64);
+        statsd.count("n", -2_i64);
+        statsd.count("n", 4_usize);
+        statsd.count("n", 5);
+        statsd.gauge("g", 0.5_f64);
+        statsd.gauge("g", 2.0_f64);
+        statsd.time_interval_ms("t", 1.25_f64);
+        statsd.time_interval_ms("t", 7_u32);
+        assert!(!statsd.count_reporting("n", f64::NAN));
+        assert_eq!(*statsd.sender().borrow(), vec!["n:3|c", "n:-2|c", "n:4|c", "n:5|c", "g:0.5|g", "g:2|g", "t:1.25|ms", "t:7|ms"]);
+        assert_eq!(statsd.malformed(), 1);
+    }
+
+    #[test]
+    fn test_f64_values() {
+        let statsd = test_client();
+        statsd.gauge_f64("load", 0.73);
+        statsd.gauge_f64("load", 1.0);
+        statsd.gauge_f64("load", -0.0);
+        statsd.time_interval_ms_f64("latency", 0.25);
+        statsd.gauge_f64("load", f64::NAN);
+        statsd.gauge_f64("load", f64::INFINITY);
+        statsd.time_interval_ms_f64("latency", -1.5);
+        assert_eq!(*statsd.sender().borrow(), vec!["load:0.73|g", "load:1|g", "load:0|g", "latency:0.25|ms"]);
+        assert_eq!(statsd.malformed(), 3);
+    }
+
+    #[test]
+    fn test_gauge_delta() {
+        let statsd = test_client();
+        statsd.gauge_delta("g", 5);
+        statsd.gauge_delta("g", -3);
+        statsd.gauge_delta("g", 0);
+        statsd.gauge_delta("g", i64::MIN);
+        assert_eq!(*statsd.sender().borrow(), vec!["g:+5|g", "g:-3|g", "g:+0|g", &format!("g:{}|g", i64::MIN)]);
+    }
+
+    #[test]
+    fn test_histogram() {
+        let statsd = test_client();
+        statsd.histogram("thing", 5);
+        let str = statsd.sender().borrow_mut().pop();
+        assert_eq!(str.unwrap(), "thing:5|h")
+    }
+
+    #[test]
+    fn test_distribution() {
+        let statsd = test_client();
+        statsd.distribution("latency", 5);
+        statsd.distribution("latency", 0.25);
+        statsd.distribution("latency", f64::NAN);
+        assert_eq!(*statsd.sender().borrow(), vec!["latency:5|d", "latency:0.25|d"]);
+        assert_eq!(statsd.malformed(), 1);
+    }
+
+    #[test]
+    fn test_sampled_distribution() {
+        let statsd = test_sampling_client();
+        while statsd.sender().borrow().is_empty() { statsd.distribution("latency", 5) }
+        assert_eq!(statsd.sender().borrow()[0], "latency:5|d|@0.999");
+    }
+
+    #[test]
+    fn test_service_check() {
+        let statsd = StatsdOutlet::outlet(RefCell::new(Vec::new()), "app.", 0.0).unwrap();
+        statsd.service_check("redis.can_connect", ServiceStatus::Ok, None);
+        statsd.service_check("redis.can_connect", ServiceStatus::Critical, Some("timed out\nafter 5s"));
+        statsd.service_check("bad|name", ServiceStatus::Unknown, None);
+        assert_eq!(*statsd.sender().borrow(), vec!["_sc|redis.can_connect|0", "_sc|redis.can_connect|2|m:timed out\\nafter 5s"]);
+        assert_eq!(statsd.malformed(), 1);
+
+        let statsd = test_client().with_default_tags(&[("env", "prod")]);
+        statsd.service_check("db", ServiceStatus::Warning, Some("slow"));
+        assert_eq!(*statsd.sender().borrow(), vec!["_sc|db|1|#env:prod|m:slow"]);
+    }
+
+    #[test]
+    fn test_event() {
+        let statsd = StatsdOutlet::outlet(RefCell::new(Vec::new()), "app.", 0.0).unwrap();
+        statsd.event("deploy", "v1.2 rolled out\nto all hosts", EventOptions::default());
+        statsd.event("déploiement", "ok", EventOptions::default());
+        statsd.event("", "no title", EventOptions::default());
+        assert_eq!(*statsd.sender().borrow(), vec!["_e{6,29}:deploy|v1.2 rolled out\\nto all hosts", "_e{12,2}:déploiement|ok"]);
+        assert_eq!(statsd.malformed(), 1);
+
+        let statsd = test_client().with_default_tags(&[("env", "prod")]);
+        let opts = EventOptions { priority: Some(EventPriority::Low), alert_type: Some(AlertType::Success), tags: &[("version", "1.2")] };
+        statsd.event("deploy", "done", opts);
+        assert_eq!(*statsd.sender().borrow(), vec!["_e{6,4}:deploy|done|p:low|t:success|#env:prod,version:1.2"]);
+    }
+
+    #[test]
+    fn test_integer_formatting() {
+        let statsd = test_client();
+        statsd.count_lazy("a", || u64::MAX);
+        statsd.histogram("b", 0);
+        statsd.count_with_unit("c", 1007, "bytes");
+        statsd.gauge_tagged("d", 120, &[("host", "x")]);
+        statsd.count("e", i64::MIN);
+        statsd.gauge_delta("f", -10);
+        assert_eq!(*statsd.sender().borrow(), vec![
+            "a:18446744073709551615|c", "b:0|h", "c:1007|c|#unit:bytes", "d:120|g|#host:x", "e:-9223372036854775808|c", "f:-10|g"]);
+    }
+
+    #[test]
+    fn test_format_metric() {
+        assert_eq!(super::format_metric("a.", "hits", 5, "|c"), "a.hits:5|c");
+        assert_eq!(super::format_metric("a.", "hits", 5, "|c|@0.1"), "a.hits:5|c|@0.1");
+        assert_eq!(super::format_metric("", "queue", -3, "|g"), "queue:-3|g");
+        assert_eq!(super::format_metric("", "queue", 0.5, "|g|@0.5"), "queue:0.5|g|@0.5");
+        assert_eq!(super::format_metric("a.", "took", 44u64, "|ms"), "a.took:44|ms");
+        assert_eq!(super::format_metric("a.", "took", -0.0, "|ms|@0.999"), "a.took:0|ms|@0.999");
+
+        let mut buf = String::from("x:1|c\n");
+        super::write_metric(&mut buf, "", "y", 2, "|c");
+        assert_eq!(buf, "x:1|c\ny:2|c");
+    }
+
+    #[test]
+    fn test_encode_metrics() {
+        use super::{encode, Metric, Suffixes};
+        let full = Suffixes::new(1.0);
+        let sampled = Suffixes::new(0.5);
+        assert_eq!(encode(&Metric::Count { key: "hits", value: 5 }, "a.", &full), "a.hits:5|c");
+        assert_eq!(encode(&Metric::Gauge { key: "load", value: 0.75 }, "a.", &full), "a.load:0.75|g");
+        assert_eq!(encode(&Metric::Timing { key: "took", value: 44u64 }, "", &sampled), "took:44|ms|@0.5");
+        assert_eq!(encode(&Metric::Histogram { key: "size", value: 3 }, "", &sampled), "size:3|h|@0.5");
+        assert_eq!(encode(&Metric::Distribution { key: "lat", value: 7 }, "", &full), "lat:7|d");
+
+        // clients send exactly what the encoder produces
+        let statsd = StatsdOutlet::outlet(RefCell::new(Vec::new()), "a.", 0.999).unwrap().with_seed(0x5eed);
+        while statsd.sender().borrow().is_empty() { statsd.time_interval_ms("took", 44) }
+        assert_eq!(statsd.sender().borrow()[0], encode(&Metric::Timing { key: "took", value: 44 }, "a.", &Suffixes::new(0.999)));
+        let metric = Metric::Gauge { key: "load", value: 1 };
+        assert_eq!((metric.key(), *metric.value()), ("load", 1));
+        assert_eq!(metric.with_key("other"), Metric::Gauge { key: "other", value: 1 });
+    }
+
+    #[test]
+    fn test_separator() {
+        let statsd = test_client();
+        statsd.count("key", 5);
+        assert_eq!(statsd.sender().borrow()[0], "key:5|c");
+
+        let statsd = StatsdOutlet::outlet(RefCell::new(Vec::new()), "", 1.0).unwrap().with_separator("=", "/");
+        statsd.count("key", 5);
+        assert_eq!(statsd.sender().borrow()[0], "key=5|c");
+
+        let statsd = StatsdOutlet::outlet(RefCell::new(Vec::new()), "prefix", 1.0).unwrap().with_separator("=", "/");
+        statsd.count("key", 5);
+        statsd.set("users", "abc");
+        statsd.time_durations("took", &[Duration::from_millis(1), Duration::from_millis(2)]);
+        statsd.with_prefix("child/").gauge("key", 1);
+        assert_eq!(*statsd.sender().borrow(), ["prefix/key=5|c", "prefix/users=abc|s", "prefix/took=1|ms=2|ms", "prefix/child/key=1|g"]);
+    }
+
+    #[test]
+    fn test_segment_order() {
+        use super::Segment::*;
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let statsd = super::StatsdClient::builder(server.local_addr().unwrap()).prefix(".app")
+            .default_tags(&[("env", "prod")])
+            .segment_order(&[Key, Prefix, Value, Tags, TypeSuffix, RateSuffix])
+            .build().unwrap();
+        statsd.count("hits", 1);
+        let mut received = [0; 64];
+        let len = server.recv(&mut received).unwrap();
+        assert_eq!(&received[..len], b"hits.app:1|#env:prod|c");
+
+        // tags before the sampling rate
+        let statsd = StatsdOutlet::outlet(RefCell::new(Vec::new()), "a.", 0.5).unwrap().with_seed(1)
+            .with_segment_order(&[Prefix, Key, Value, TypeSuffix, Tags, RateSuffix]).unwrap();
+        while statsd.sender().borrow().is_empty() { statsd.count_tagged("hits", 1, &[("host", "x")]) }
+        assert_eq!(statsd.sender().borrow()[0], "a.hits:1|c|#host:x|@0.5");
+
+        let standard = test_client().with_segment_order(&super::STATSD_ORDER).unwrap();
+        standard.count("hits", 1);
+        assert_eq!(standard.sender().borrow()[0], "hits:1|c");
+
+        for order in [&[Prefix, Key, Value, TypeSuffix, RateSuffix][..], &[Prefix, Key, Value, TypeSuffix, RateSuffix, Tags, Tags],
+                      &[Prefix, Value, Key, TypeSuffix, RateSuffix, Tags], &[Prefix, Key, Key, Value, RateSuffix, Tags]] {
+            assert_eq!(test_client().with_segment_order(order).err().unwrap().kind(), ErrorKind::InvalidInput);
+        }
+        let invalid = super::StatsdClient::builder(server.local_addr().unwrap()).segment_order(&[Key, Value]).build();
+        assert_eq!(invalid.err().unwrap().kind(), ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn test_encoding_features() {
+        use super::Encoding;
+        // statsd is always available, other formats only with their feature
+        #[allow(unused_mut)]
+        let mut encodings = vec![(Encoding::Statsd, "a.hits:5|c")];
+        #[cfg(feature = "graphite")]
+        encodings.push((Encoding::Graphite, "a.hits 5 "));
+        #[cfg(feature = "influx")]
+        encodings.push((Encoding::Influx, "a.hits value=5 "));
+        assert_eq!(encodings.len(), 1 + cfg!(feature = "graphite") as usize + cfg!(feature = "influx") as usize);
+        for (encoding, start) in encodings {
+            let statsd = StatsdOutlet::outlet(RefCell::new(Vec::new()), "a.", 1.0).unwrap().with_encoding(encoding);
+            statsd.count("hits", 5);
+            assert!(statsd.sender().borrow()[0].starts_with(start), "{:?}: {}", encoding, statsd.sender().borrow()[0]);
+        }
+        assert_eq!(Encoding::default(), Encoding::Statsd);
+    }
+
+    #[cfg(any(feature = "graphite", feature = "influx"))]
+    #[test]
+    fn test_gauge_delta_encodings() {
+        use super::Encoding;
+        #[allow(unused_mut)]
+        let mut encodings = Vec::new();
+        #[cfg(feature = "graphite")]
+        encodings.push(Encoding::Graphite);
+        #[cfg(feature = "influx")]
+        encodings.push(Encoding::Influx);
+        for encoding in encodings {
+            // relative changes would be read as absolute values
+            let statsd = StatsdOutlet::outlet(RefCell::new(Vec::new()), "", 1.0).unwrap().with_encoding(encoding);
+            statsd.gauge_delta("level", 5);
+            assert!(statsd.sender().borrow().is_empty(), "{:?}", encoding);
+            assert_eq!(statsd.malformed(), 1);
+        }
+    }
+
+    #[cfg(feature = "graphite")]
+    #[test]
+    fn test_graphite_encoding() {
+        use super::{encode_graphite_into, Encoding, Metric};
+        use std::time::{SystemTime, UNIX_EPOCH};
+        let mut line = String::new();
+        encode_graphite_into(&mut line, &Metric::Gauge { key: "load", value: 0.5 }, "", 1700000000);
+        assert_eq!(line, "load 0.5 1700000000");
+
+        let statsd = StatsdOutlet::outlet(RefCell::new(Vec::new()), "prefix", 1.0).unwrap().with_encoding(Encoding::Graphite);
+        let before = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+        statsd.count("key", 5);
+        statsd.gauge("key", 5);
+        let after = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+        let sent = statsd.sender().borrow().clone();
+        assert_eq!(sent.len(), 2);
+        // counts and gauges are the same line in graphite
+        for line in &sent {
+            let mut fields = line.split(' ');
+            assert_eq!(fields.next(), Some("prefix.key"));
+            assert_eq!(fields.next(), Some("5"));
+            let timestamp: u64 = fields.next().unwrap().parse().unwrap();
+            assert!(before <= timestamp && timestamp <= after);
+            assert_eq!(fields.next(), None);
+        }
+
+        // statsd-only metrics are not sent
+        statsd.set("users", "abc123");
+        statsd.count_sampled("key", 5, 1.0);
+        assert_eq!((statsd.sender().borrow().len(), statsd.malformed()), (2, 2));
+    }
+
+    #[cfg(feature = "influx")]
+    #[test]
+    fn test_influx_encoding() {
+        use super::{encode_influx_into, Encoding, Metric};
+        use std::time::{SystemTime, UNIX_EPOCH};
+        let mut line = String::new();
+        encode_influx_into(&mut line, &Metric::Gauge { key: "disk used", value: 0.5 }, "", &[("path,name", "/var/my logs,old")], 1);
+        assert_eq!(line, r"disk\ used,path\,name=/var/my\ logs\,old value=0.5 1");
+
+        let statsd = StatsdOutlet::outlet(RefCell::new(Vec::new()), "app.", 1.0).unwrap()
+            .with_default_tags(&[("env", "prod")])
+            .with_encoding(Encoding::Influx);
+        let before = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_nanos() as u64;
+        statsd.count_tagged("hits", 5, &[("host", "web 1, east"), ("a=b", "c")]);
+        let after = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_nanos() as u64;
+        let sent = statsd.sender().borrow()[0].clone();
+        let (line, timestamp) = sent.rsplit_once(' ').unwrap();
+        assert_eq!(line, r"app.hits,env=prod,host=web\ 1\,\ east,a\=b=c value=5");
+        let timestamp: u64 = timestamp.parse().unwrap();
+        assert!(before <= timestamp && timestamp <= after);
+    }
+
+    #[test]
+    fn test_set() {
+        let statsd = test_client();
+        statsd.set("users", "abc123");
+        statsd.set("users", "a:b");
+        statsd.set("users", "a|b");
+        assert_eq!(*statsd.sender().borrow(), vec!["users:abc123|s"]);
+        assert_eq!(statsd.malformed(), 2);
+    }
+
+    #[test]
+    fn test_tags() {
+        let statsd = test_sampling_client();
+        while statsd.sender().borrow().is_empty() { statsd.count_tagged("hits", 1, &[("env", "prod"), ("az", "b")]) }
+        assert_eq!(statsd.sender().borrow_mut().pop().unwrap(), "hits:1|c|@0.999|#env:prod,az:b");
+        while statsd.sender().borrow().is_empty() { statsd.gauge_tagged("queue", 3, &[]) }
+        assert_eq!(statsd.sender().borrow_mut().pop().unwrap(), "queue:3|g|@0.999");
+
+        let statsd = test_client().with_default_tags(&[("service", "api"), ("bad", "a,b")]);
+        assert_eq!(statsd.malformed(), 1);
+        for tag in &[("env", "prod|dev"), ("env", "#1"), ("new\nline", "x"), ("a,b", "c")] {
+            statsd.count_tagged("hits", 1, &[*tag]);
+        }
+        statsd.event("deploy", "done", EventOptions { tags: &[("version", "1,2")], ..Default::default() });
+        statsd.count_tagged("hits", 1, &[("env", "prod:eu")]);
+        assert_eq!(statsd.malformed(), 6);
+        assert_eq!(*statsd.sender().borrow(), vec!["hits:1|c|#service:api,env:prod:eu"]);
+    }
+
+    #[test]
+    fn test_default_tags() {
+        let statsd = test_client().with_default_tags(&[("service", "api")]);
+        statsd.time_interval_ms_tagged("latency", 7, &[("route", "/")]);
+        statsd.count("hits", 1);
+        statsd.gauge_with_unit("heap", 5, "bytes");
+        assert_eq!(*statsd.sender().borrow(), vec!["latency:7|ms|#service:api,route:/", "hits:1|c|#service:api",
+                                                 "heap:5|g|#service:api,unit:bytes"]);
+    }
+
+    #[test]
+    fn test_batching() {
+        let statsd = test_client().with_batching();
+        statsd.flush();
+        statsd.count("a", 1);
+        statsd.count("b", 2);
+        assert!(statsd.sender().borrow().is_empty());
+        statsd.flush();
+        statsd.flush();
+        assert_eq!(*statsd.sender().borrow(), vec!["a:1|c\nb:2|c"]);
+    }
+
+    #[test]
+    fn test_batching_order() {
+        // relative gauges only make sense after the absolute value they apply to
+        let statsd = test_client().with_batching();
+        statsd.gauge("gauge", 0);
+        statsd.count("hits", 1);
+        statsd.gauge_delta("gauge", 5);
+        statsd.time_interval_ms("took", 2);
+        statsd.gauge_delta("gauge", -1);
+        statsd.flush();
+        assert_eq!(*statsd.sender().borrow(), vec!["gauge:0|g\nhits:1|c\ngauge:+5|g\ntook:2|ms\ngauge:-1|g"]);
+    }
+
+    #[test]
+    fn test_batching_splits() {
+        let statsd = test_client().with_batching();
+        let key = "k".repeat(300);
+        statsd.count(&key, 1);
+        statsd.count(&key, 2);
+        assert_eq!(*statsd.sender().borrow(), vec![format!("{}:1|c", key)]);
+        statsd.count(&"x".repeat(600), 3);
+        statsd.flush();
+        let sent = statsd.sender().borrow();
+        assert_eq!(sent.len(), 3);
+        assert_eq!(sent[1], format!("{}:2|c", key));
+        assert_eq!(sent[2], format!("{}:3|c", "x".repeat(600)));
+    }
+
+    #[test]
+    fn test_batching_flushed_on_drop() {
+        let (sender, receiver) = mpsc::channel();
+        let statsd = StatsdOutlet::outlet(sender, "", 1.0).unwrap().with_batching();
+        statsd.count("a", 1);
+        statsd.flush();
+        statsd.count("b", 2);
+        drop(statsd);
+        assert_eq!(receiver.iter().collect::<Vec<String>>(), vec!["a:1|c", "b:2|c"]);
+
+        let statsd = test_client().with_batching();
+        statsd.count("c", 3);
+        assert_eq!(statsd.into_inner().into_inner(), vec!["c:3|c"]);
+    }
+
+    #[test]
+    fn test_max_payload() {
+        let statsd = test_client().with_max_payload(8900).with_batching();
+        for i in 0..500 { statsd.count("key", i) }
+        statsd.flush();
+        let sent = statsd.sender().borrow();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].lines().count(), 500);
+
+        let statsd = test_client().with_max_payload(4).with_batching();
+        statsd.count("a", 1);
+        statsd.count("b", 2);
+        statsd.flush();
+        assert_eq!(*statsd.sender().borrow(), vec!["a:1|c", "b:2|c"]);
+    }
+
+    #[test]
+    fn test_batching_keeps_order() {
+        let statsd = test_client().with_batching();
+        statsd.gauge("level", 5);
+        statsd.gauge("level", 3);
+        statsd.count_into(&mut String::new(), "hits", 1);
+        statsd.gauge("level", 4);
+        statsd.flush();
+        assert_eq!(*statsd.sender().borrow(), vec!["level:5|g\nlevel:3|g\nhits:1|c\nlevel:4|g"]);
+    }
+
+    #[test]
+    fn test_flag() {
+        let statsd = test_client();
+        statsd.flag("healthy", true);
+        statsd.flag("degraded", false);
+        assert_eq!(*statsd.sender().borrow(), vec!["healthy:1|g", "degraded:0|g"]);
+    }
+
+    #[test]
+    fn test_time() {
+        let statsd = test_client();
+        statsd.time_interval_ms("barry", 44);
+        let str = statsd.sender().borrow_mut().pop();
+        assert_eq!(str.unwrap(), "barry:44|ms")
+    }
+
+    #[test]
+    fn test_sampling_count() {
+        let statsd = test_sampling_client();
+        statsd.count("bouring", 22);
+        let str = statsd.sender().borrow_mut().pop();
+        assert_eq!(str.unwrap(), "bouring:22|c|@0.999")
+    }
+
+    #[test]
+    fn test_smapling_gauge() {
+        let statsd = test_sampling_client();
+        statsd.gauge("bearing", 33);
+        let str = statsd.sender().borrow_mut().pop();
+        assert_eq!(str.unwrap(), "bearing:33|g|@0.999")
+    }
+
+    #[test]
+    fn test_sampling_histogram() {
+        let statsd = test_sampling_client();
+        statsd.histogram("thing", 5);
+        let str = statsd.sender().borrow_mut().pop();
+        assert_eq!(str.unwrap(), "thing:5|h|@0.999")
+    }
+
+    #[test]
+    fn test_sampling_set() {
+        let statsd = test_sampling_client();
+        statsd.set("users", "abc123");
+        let str = statsd.sender().borrow_mut().pop();
+        assert_eq!(str.unwrap(), "users:abc123|s|@0.999")
+    }
+
+    #[test]
+    fn test_sampling_time() {
+        let statsd = test_sampling_client();
+        statsd.time_interval_ms("barry", 44);
+        let str = statsd.sender().borrow_mut().pop();
+        assert_eq!(str.unwrap(), "barry:44|ms|@0.999")
+    }
+
+    #[test]
+    fn test_empty_send() {
+        let statsd = StatsdOutlet::outlet(RefCell::new(Vec::new()), "a.b.", super::FULL_SAMPLING_RATE).unwrap();
+        statsd.send(&[]);
+        statsd.send(&["", ""]);
+        assert!(statsd.sender().borrow().is_empty());
+        assert_eq!(statsd.malformed(), 2);
+    }
+
+    #[test]
+    fn test_scale_up() {
+        let statsd = test_client();
+        statsd.time_interval_scaled("barry", 3, &Scale::up(1000));
+        assert_eq!(statsd.sender().borrow_mut().pop().unwrap(), "barry:3000|ms");
+        statsd.gauge_scaled("bearing", u64::MAX / 2, &Scale::up(3));
+        assert_eq!(statsd.sender().borrow_mut().pop().unwrap(), format!("bearing:{}|g", u64::MAX));
+        assert_eq!(statsd.overflows(), 1);
+    }
+
+    #[test]
+    fn test_scale_overflow() {
+        let statsd = test_client();
+        statsd.count_scaled("big", u64::MAX, &Scale::up(2).with_overflow(Overflow::Saturate(1_000_000)));
+        statsd.count_scaled("big", u64::MAX, &Scale::up(2).with_overflow(Overflow::Drop));
+        statsd.count_scaled("big", u64::MAX, &Scale::up(1).with_overflow(Overflow::Drop));
+        statsd.count_scaled("big", u64::MAX, &Scale::down(2, Rounding::Up).unwrap().with_overflow(Overflow::Drop));
+        assert_eq!(*statsd.sender().borrow(), vec!["big:1000000|c".to_string(), format!("big:{}|c", u64::MAX),
+                                                  format!("big:{}|c", u64::MAX / 2 + 1)]);
+        assert_eq!(statsd.overflows(), 2);
+    }
+
+    #[test]
+    fn test_scale_down() {
+        let statsd = test_client();
+        statsd.count_scaled("bytes", 1536, &Scale::down(1024, Rounding::Down).unwrap());
+        statsd.count_scaled("bytes", 1536, &Scale::down(1024, Rounding::Nearest).unwrap());
+        statsd.count_scaled("bytes", 1025, &Scale::down(1024, Rounding::Up).unwrap());
+        statsd.count_scaled("bytes", 1535, &Scale::down(1024, Rounding::Nearest).unwrap());
+        statsd.gauge_scaled("ratio", u64::MAX, &Scale::new(3, 4, Rounding::Nearest).unwrap());
+        assert_eq!(*statsd.sender().borrow(), vec!["bytes:1|c", "bytes:2|c", "bytes:2|c", "bytes:1|c",
+                                                  &format!("ratio:{}|g", u64::MAX / 4 * 3 + 2)]);
+        assert_eq!(Scale::down(0, Rounding::Up).err().unwrap().kind(), ErrorKind::InvalidInput);
+        assert_eq!(Scale::new(1, 0, Rounding::Down).err().unwrap().kind(), ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn test_dry_run() {
+        let statsd = StatsdOutlet::dry_run("a.b.", super::FULL_SAMPLING_RATE).unwrap();
+        statsd.count("fine", 1);
+        statsd.gauge("bad key", 2);
+        statsd.time_interval_ms("sneaky:key", 3);
+        statsd.count("sneaky|key", 4);
+        statsd.gauge(&"long".repeat(200), 5);
+        statsd.count("new\nline", 6);
+        let invalid = |line: &str, problem| Invalid { line: line.to_string(), problem };
+        assert_eq!(statsd.report(), vec![
+            invalid("a.b.bad key:2|g", Problem::InvalidKey),
+            invalid("a.b.sneaky:key:3|ms", Problem::InvalidValue),
+            invalid("a.b.sneaky|key:4|c", Problem::InvalidKey),
+            invalid(&format!("a.b.{}:5|g", "long".repeat(200)), Problem::PayloadTooLarge),
+            invalid("a.b.new\nline:6|c", Problem::Malformed),
+        ]);
+
+        let statsd = StatsdOutlet::dry_run_sized("", super::FULL_SAMPLING_RATE, 8900).unwrap().with_batching();
+        statsd.gauge(&"long".repeat(200), 5);
+        statsd.gauge(&"long".repeat(2300), 5);
+        statsd.flush();
+        assert_eq!(statsd.report(), vec![invalid(&format!("{}:5|g", "long".repeat(2300)), Problem::PayloadTooLarge)]);
+
+        let statsd = StatsdOutlet::dry_run("", super::FULL_SAMPLING_RATE).unwrap();
+        statsd.time_durations("took", &[Duration::from_millis(1), Duration::from_millis(2)]);
+        statsd.event("déploiement", "ok|done", EventOptions { priority: Some(EventPriority::Low), tags: &[("env", "prod")], ..Default::default() });
+        statsd.service_check("db", ServiceStatus::Critical, Some("down | again"));
+        for line in &["_e{6,2}:deploy|ok|x:1", "_e{9,2}:deploy|ok", "_sc|db|7", "took:1|ms:2|xs"] {
+            super::SendStats::send_stats(statsd.sender(), line.to_string()).unwrap();
+        }
+        assert_eq!(statsd.report(), vec![
+            invalid("_e{6,2}:deploy|ok|x:1", Problem::Malformed),
+            invalid("_e{9,2}:deploy|ok", Problem::Malformed),
+            invalid("_sc|db|7", Problem::InvalidValue),
+            invalid("took:1|ms:2|xs", Problem::Malformed),
+        ]);
+
+        let statsd = StatsdOutlet::outlet(DryRun::default().with_separator("="), "app", 1.0).unwrap().with_separator("=", "/");
+        statsd.count("hits", 1);
+        statsd.time_durations("took", &[Duration::from_millis(1), Duration::from_millis(2)]);
+        statsd.gauge("bad=key", 2);
+        assert_eq!(statsd.report(), vec![invalid("app/bad=key=2|g", Problem::InvalidValue)]);
+
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let statsd = super::StatsdClient::builder(server.local_addr().unwrap()).prefix("a.").max_payload(16).build_dry_run().unwrap();
+        statsd.count("fine", 1);
+        statsd.count("too.long.for.it", 1);
+        assert_eq!(statsd.report(), vec![invalid("a.too.long.for.it:1|c", Problem::PayloadTooLarge)]);
+        assert_eq!(super::StatsdClient::builder("nowhere.invalid:8125").sample_rate(1.5).build_dry_run().err().unwrap().kind(), ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn test_decaying_gauge() {
+        let statsd = test_client();
+        let gauge = DecayingGauge::new("activity", 0.5).unwrap();
+        let start = Instant::now();
+        gauge.touch_at(&statsd, start);
+        assert!(statsd.sender().borrow().is_empty());
+
+        gauge.set_at(&statsd, 1000, start);
+        gauge.touch_at(&statsd, start + Duration::from_secs(1));
+        gauge.touch_at(&statsd, start + Duration::from_secs(3));
+        gauge.touch_at(&statsd, start + Duration::from_millis(3500));
+        assert_eq!(*statsd.sender().borrow(), vec!["activity:1000|g", "activity:500|g", "activity:125|g", "activity:88|g"]);
+
+        assert_eq!(DecayingGauge::new("activity", 1.5).err().unwrap().kind(), ErrorKind::InvalidInput);
+        assert_eq!(DecayingGauge::new("activity", f64::NAN).err().unwrap().kind(), ErrorKind::InvalidInput);
+    }
+
+    impl super::SendStats for Sender<String> {
+        fn send_stats(&self, str: String) -> io::Result<()> {
+            self.send(str).map_err(|_| io::Error::from(ErrorKind::BrokenPipe))
+        }
+    }
+
+    impl super::SendStats for SyncSender<String> {
+        fn send_stats(&self, str: String) -> io::Result<()> {
+            self.send(str).map_err(|_| io::Error::from(ErrorKind::BrokenPipe))
+        }
+    }
+
+    #[test]
+    fn test_tcp() {
+        use std::io::{BufRead, BufReader};
+        let listener = ::std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let statsd = StatsdOutlet::new_tcp(listener.local_addr().unwrap(), "a.", 1.0).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        statsd.count("hits", 1);
+        statsd.gauge("queue", 2);
+        drop(statsd);
+        let lines: Vec<String> = BufReader::new(stream).lines().map(|l| l.unwrap()).collect();
+        assert_eq!(lines, vec!["a.hits:1|c", "a.queue:2|g"]);
+    }
+
+    #[test]
+    fn test_tcp_concurrent_lines() {
+        use std::io::{BufRead, BufReader};
+        let listener = ::std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let statsd = StatsdOutlet::new_tcp(listener.local_addr().unwrap(), "", 1.0).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        let key = "k".repeat(100_000);
+        let reader = thread::spawn(move || BufReader::new(stream).lines().map(|l| l.unwrap()).collect::<Vec<String>>());
+        thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| for _ in 0..10 { statsd.count(&key, 1) });
+            }
+        });
+        drop(statsd);
+        let lines = reader.join().unwrap();
+        assert_eq!(lines.len(), 40);
+        assert!(lines.iter().all(|line| *line == format!("{}:1|c", key)));
+    }
+
+    #[test]
+    fn test_tcp_confirmed() {
+        use std::io::Read;
+        let listener = ::std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let statsd = StatsdOutlet::new_tcp_confirmed(listener.local_addr().unwrap(), "a.", 1.0, Duration::from_secs(10)).unwrap();
+        let (mut stream, _) = listener.accept().unwrap();
+
+        // once confirmed, the line is already waiting in the server's receive buffer
+        statsd.count_confirmed("hits", 1).unwrap();
+        stream.set_nonblocking(true).unwrap();
+        let mut buf = [0; 64];
+        let len = stream.read(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"a.hits:1|c\n");
+        stream.set_nonblocking(false).unwrap();
+
+        // a payload larger than the socket buffers blocks until the server reads it
+        let key = "k".repeat(16 << 20);
+        let reader = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(200));
+            let mut read = Vec::new();
+            stream.read_to_end(&mut read).unwrap();
+            read.len()
+        });
+        let start = ::std::time::Instant::now();
+        statsd.gauge_confirmed(&key, 2).unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(200));
+        drop(statsd);
+        assert_eq!(reader.join().unwrap(), key.len() + "a.:2|g\n".len());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_tcp_confirm_timeout() {
+        use std::os::unix::io::AsRawFd;
+        use super::TcpSender;
+        let set_buffer = |fd, option, bytes: libc::c_int| unsafe {
+            libc::setsockopt(fd, libc::SOL_SOCKET, option, &bytes as *const _ as *const libc::c_void, ::std::mem::size_of::<libc::c_int>() as libc::socklen_t)
+        };
+        let listener = ::std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        set_buffer(listener.as_raw_fd(), libc::SO_RCVBUF, 4096);
+        let stream = ::std::net::TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        set_buffer(stream.as_raw_fd(), libc::SO_SNDBUF, 1 << 20);
+        let (_server, _) = listener.accept().unwrap();
+
+        // the server never reads, so what does not fit its receive buffer is never acknowledged
+        let sender = TcpSender::new(stream).confirm_delivery(Duration::from_millis(100));
+        let start = ::std::time::Instant::now();
+        let error = super::SendStats::send_stats_str(&sender, &"k".repeat(64 << 10)).err().unwrap();
+        assert_eq!(error.kind(), ErrorKind::TimedOut);
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_uds() {
+        use std::os::unix::net::UnixDatagram;
+        let path = ::std::env::temp_dir().join(format!("statsd-test-{}.sock", ::std::process::id()));
+        let _ = fs::remove_file(&path);
+        let agent = UnixDatagram::bind(&path).unwrap();
+        let statsd = StatsdOutlet::new_uds(&path, "a.", 1.0).unwrap();
+        statsd.count("hits", 1);
+        let mut buf = [0; 64];
+        let len = agent.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"a.hits:1|c");
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_async_sender() {
+        let (sender, receiver) = mpsc::channel();
+        let statsd = StatsdOutlet::outlet(AsyncSender::new(sender).unwrap(), "", 1.0).unwrap();
+        statsd.count("a", 1);
+        statsd.gauge("b", 2);
+        assert_eq!(receiver.recv_timeout(Duration::from_secs(5)).unwrap(), "a:1|c");
+        assert_eq!(receiver.recv_timeout(Duration::from_secs(5)).unwrap(), "b:2|g");
+        statsd.count("c", 3);
+        drop(statsd);
+        assert_eq!(receiver.iter().collect::<Vec<String>>(), vec!["c:3|c"]);
+    }
+
+    /// Reports the name and nice value of the thread sending each line instead of the line.
+    struct ThreadReporter(mpsc::Sender<(String, i32)>);
+
+    impl super::SendStats for ThreadReporter {
+        fn send_stats(&self, _str: String) -> io::Result<()> {
+            let name = thread::current().name().unwrap_or("").to_string();
+            self.0.send((name, nice())).map_err(|_| io::Error::from(ErrorKind::BrokenPipe))
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    fn nice() -> i32 {
+        unsafe { ::libc::getpriority(::libc::PRIO_PROCESS as _, ::libc::syscall(::libc::SYS_gettid) as ::libc::id_t) }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn nice() -> i32 {
+        0
+    }
+
+    #[test]
+    fn test_sender_thread() {
+        let (sender, receiver) = mpsc::channel();
+        let statsd = StatsdOutlet::outlet(AsyncSender::new(ThreadReporter(sender)).unwrap(), "", 1.0).unwrap();
+        statsd.count("a", 1);
+        assert_eq!(receiver.recv_timeout(Duration::from_secs(5)).unwrap(), ("statsd-sender".to_string(), nice()));
+
+        let (sender, receiver) = mpsc::channel();
+        let thread = super::SenderThread::default().name("metrics-out").low_priority(true);
+        let statsd = StatsdOutlet::outlet(AsyncSender::bounded_on(ThreadReporter(sender), 16, thread).unwrap(), "", 1.0).unwrap();
+        statsd.count("a", 1);
+        let (name, sender_nice) = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(name, "metrics-out");
+        if cfg!(target_os = "linux") {
+            assert_eq!(sender_nice, (nice() + 10).min(19));
+        }
+
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let statsd = super::StatsdClient::builder(server.local_addr().unwrap()).prefix("a.")
+            .sender_thread(super::SenderThread::default().name("udp-out"))
+            .build_async(16).unwrap();
+        statsd.count("hits", 1);
+        let mut received = [0; 64];
+        let len = server.recv(&mut received).unwrap();
+        assert_eq!(&received[..len], b"a.hits:1|c");
+    }
+
+    #[test]
+    fn test_async_sender_bounded() {
+        // a rendezvous channel blocks the worker until the test receives
+        let (sender, receiver) = mpsc::sync_channel(0);
+        let statsd = StatsdOutlet::outlet(AsyncSender::bounded(sender, 2).unwrap(), "", 1.0).unwrap();
+        for i in 0..10 { statsd.count("a", i) }
+        let dropped = statsd.dropped();
+        assert!((7..=8).contains(&dropped), "{}", dropped);
+        let received: Vec<String> = receiver.iter().take(10 - dropped as usize).collect();
+        assert_eq!(received[0], "a:0|c");
+        drop(statsd);
+        assert!(receiver.recv().is_err());
+    }
+
+    #[test]
+    fn test_channel_outlet() {
+        let (sender, receiver) = mpsc::channel();
+        let statsd = StatsdOutlet::outlet(sender, "a.b.", 0.999).unwrap();
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let udp = super::StatsdClient::new(server.local_addr().unwrap(), "a.b.", 0.999).unwrap();
+        while !statsd.count_reporting("bouring", 22) {}
+        while !udp.count_reporting("bouring", 22) {}
+
+        let mut buf = [0; 64];
+        let len = server.recv(&mut buf).unwrap();
+        assert_eq!(receiver.try_recv().unwrap(), "a.b.bouring:22|c|@0.999");
+        assert_eq!(&buf[..len], b"a.b.bouring:22|c|@0.999");
+    }
+
+    #[test]
+    fn test_units() {
+        let statsd = test_client();
+        statsd.count_with_unit("sent", 22, "bytes");
+        statsd.gauge_with_unit("free", 33, "bytes");
+        statsd.time_interval_ms_with_unit("barry", 44, "milliseconds");
+        assert_eq!(*statsd.sender().borrow(), vec!["sent:22|c|#unit:bytes", "free:33|g|#unit:bytes", "barry:44|ms|#unit:milliseconds"]);
+
+        let statsd = test_sampling_client();
+        statsd.count_with_unit("sent", 22, "bytes");
+        assert_eq!(statsd.sender().borrow_mut().pop().unwrap(), "sent:22|c|@0.999|#unit:bytes");
+    }
+
+    #[test]
+    fn test_into_inner() {
+        let statsd = test_client();
+        statsd.count("bouring", 22);
+        statsd.gauge("bearing", 33);
+        assert_eq!(statsd.into_inner().into_inner(), vec!["bouring:22|c", "bearing:33|g"]);
+    }
+
+    #[test]
+    fn test_time_durations() {
+        let statsd = test_client();
+        statsd.time_durations("barry", &[Duration::from_millis(44), Duration::from_micros(999), Duration::from_secs(2)]);
+        assert_eq!(*statsd.sender().borrow(), vec!["barry:44|ms:0|ms:2000|ms"]);
+
+        let statsd = test_sampling_client();
+        statsd.time_durations("barry", &[Duration::from_millis(44); 2]);
+        assert_eq!(statsd.sender().borrow_mut().pop().unwrap(), "barry:44|ms|@0.999:44|ms|@0.999");
+    }
+
+    #[test]
+    fn test_time_durations_packing() {
+        let statsd = StatsdOutlet::outlet(RefCell::new(Vec::new()), "a.b.", super::FULL_SAMPLING_RATE).unwrap();
+        statsd.time_durations("barry", &[Duration::from_millis(1234); 100]);
+        let packets = statsd.sender().borrow();
+        assert_eq!(packets.len(), 2);
+        assert!(packets.iter().all(|p| p.len() <= super::MAX_UDP_PAYLOAD && p.starts_with("a.b.barry:1234|ms:")));
+        let values: usize = packets.iter().map(|p| p.matches("|ms").count()).sum();
+        assert_eq!(values, 100);
+    }
+
+    struct Failing(ErrorKind);
+
+    impl super::SendStats for Failing {
+        fn send_stats(&self, _str: String) -> io::Result<()> {
+            Err(io::Error::from(self.0))
+        }
+    }
+
+    #[test]
+    fn test_send_errors() {
+        let drops = Arc::new(Mutex::new(Vec::new()));
+        let logged = drops.clone();
+        let statsd = StatsdOutlet::outlet(Failing(ErrorKind::WouldBlock), "", 1.0).unwrap()
+            .with_drop_logger(move |key, reason| logged.lock().unwrap().push((key.to_string(), reason)));
+        assert_eq!(statsd.try_count("full", 1).unwrap_err().kind(), ErrorKind::WouldBlock);
+        statsd.count("full", 1);
+        assert!(statsd.count_reporting("full", 1));
+        assert_eq!((statsd.send_errors(), statsd.would_block()), (0, 3));
+        assert_eq!((statsd.stats().errors, statsd.stats().would_block), (0, 3));
+        assert_eq!(drops.lock().unwrap()[0], ("full".to_string(), DropReason::QueueFull));
+
+        let statsd = StatsdOutlet::outlet(Failing(ErrorKind::ConnectionRefused), "", 1.0).unwrap().with_batching();
+        assert!(statsd.try_gauge("g", 1).unwrap());
+        assert_eq!(statsd.try_flush().unwrap_err().kind(), ErrorKind::ConnectionRefused);
+        assert!(statsd.try_flush().is_ok());
+        assert_eq!(statsd.send_errors(), 1);
+
+        let statsd = test_client();
+        assert!(statsd.try_time_interval_ms("t", 1).unwrap());
+        assert!(!StatsdOutlet::outlet(Failing(ErrorKind::Other), "", 0.0).unwrap().try_count("c", 1).unwrap());
+    }
+
+    #[test]
+    fn test_with_prefix() {
+        let statsd = StatsdOutlet::outlet(RefCell::new(Vec::new()), "app.", 1.0).unwrap().with_default_tags(&[("env", "prod")]);
+        {
+            let db = statsd.with_prefix("db.");
+            db.count("queries", 2);
+            db.with_prefix("pool.").gauge("size", 4);
+        }
+        statsd.count("requests", 1);
+        assert_eq!(*statsd.sender().borrow(), vec!["app.db.queries:2|c|#env:prod", "app.db.pool.size:4|g|#env:prod", "app.requests:1|c|#env:prod"]);
+    }
+
+    #[test]
+    fn test_sanitized_keys() {
+        let statsd = test_client().with_sanitizer(Sanitizer::default());
+        statsd.count("a:b", 1);
+        statsd.gauge("a|b", 2);
+        statsd.time_interval_ms("a@b", 3);
+        statsd.count("a\nb:1|c", 4);
+        statsd.set("a:b", "x");
+        statsd.histogram_buckets("a|b", &[("le_1", 5)]);
+        assert_eq!(*statsd.sender().borrow(), vec!["a_b:1|c", "a_b:2|g", "a_b:3|ms", "a_b_1_c:4|c", "a_b:x|s", "a_b.le_1:5|c"]);
+        assert!(!statsd.sender().borrow().iter().any(|line| line.contains('\n')));
+
+        let statsd = test_client();
+        statsd.count("a:b", 1);
+        assert_eq!(*statsd.sender().borrow(), vec!["a:b:1|c"]);
+    }
+
+    #[test]
+    fn test_per_call_rate() {
+        let statsd = test_client().with_seed(0x5eed);
+        for _ in 0..1000 { statsd.count_sampled("k", 5, 0.1) }
+        let sent = statsd.sender().borrow().len();
+        assert!((50..150).contains(&sent), "{}", sent);
+        assert!(statsd.sender().borrow().iter().all(|line| line == "k:5|c|@0.1"));
+
+        let statsd = test_sampling_client();
+        statsd.count_sampled("k", 5, 1.0);
+        statsd.gauge_sampled("g", 2, 1.0);
+        statsd.time_interval_ms_sampled("t", 3, 1.0);
+        statsd.count_sampled("never", 1, 0.0);
+        statsd.count_sampled("bad", 1, f64::NAN);
+        statsd.count_sampled("bad", 1, 1.5);
+        assert_eq!(*statsd.sender().borrow(), vec!["k:5|c", "g:2|g", "t:3|ms"]);
+        assert_eq!(statsd.malformed(), 2);
+    }
+
+    #[test]
+    fn test_aggregation() {
+        let statsd = test_client().with_aggregation(Duration::from_secs(3600));
+        for _ in 0..10 { statsd.count("a", 1) }
+        statsd.count("b", -2);
+        statsd.count("b", 5u8);
+        statsd.count("f", 0.5);
+        statsd.gauge("g", 1);
+        statsd.gauge("g", 2);
+        statsd.time_interval_ms("t", 3);
+        statsd.time_interval_ms("t", 3);
+        assert_eq!(*statsd.sender().borrow(), vec!["f:0.5|c", "g:1|g", "g:2|g", "t:3|ms", "t:3|ms"]);
+        statsd.sender().borrow_mut().clear();
+        statsd.flush();
+        assert_eq!(*statsd.sender().borrow(), vec!["a:10|c", "b:3|c"]);
+        statsd.flush();
+        assert_eq!(statsd.sender().borrow().len(), 2);
+
+        let statsd = test_client().with_aggregation(Duration::from_secs(3600));
+        statsd.count("big", i64::MAX);
+        statsd.count("big", 1);
+        statsd.count("small", i64::MIN);
+        statsd.count("small", -1);
+        assert_eq!(statsd.overflows(), 2);
+        statsd.flush();
+        assert_eq!(*statsd.sender().borrow(), vec![format!("big:{}|c", i64::MAX), format!("small:{}|c", i64::MIN)]);
+
+        let statsd = test_client().with_aggregation(Duration::from_secs(0));
+        statsd.count("a", 1);
+        statsd.count("a", 2);
+        assert_eq!(*statsd.sender().borrow(), vec!["a:1|c", "a:2|c"]);
+    }
+
+    #[test]
+    fn test_concurrent_aggregation_flushes() {
+        let statsd = Arc::new(StatsdOutlet::outlet(Mutex::new(Vec::new()), "", 1.0).unwrap().with_aggregation(Duration::from_secs(3600)));
+        let done = Arc::new(AtomicBool::new(false));
+        let flusher = {
+            let (statsd, done) = (statsd.clone(), done.clone());
+            thread::spawn(move || while !done.load(Ordering::Relaxed) { statsd.flush() })
+        };
+        let counters: Vec<_> = (0..4).map(|t| {
+            let statsd = statsd.clone();
+            thread::spawn(move || for i in 0..10_000 { statsd.count(["a", "b", "c"][(i + t) % 3], 1) })
+        }).collect();
+        for counter in counters { counter.join().unwrap() }
+        done.store(true, Ordering::Relaxed);
+        flusher.join().unwrap();
+        statsd.flush();
+
+        // every count is sent exactly once, whichever flush drained it
+        let lines = statsd.sender().lock().unwrap();
+        let total: i64 = lines.iter().map(|line| line.split([':', '|']).nth(1).unwrap().parse::<i64>().unwrap()).sum();
+        assert_eq!(total, 40_000);
+    }
+
+    #[test]
+    fn test_flush_on_distinct_keys() {
+        let statsd = test_client().with_aggregation(Duration::from_secs(3600)).flush_on_distinct_keys(3);
+        statsd.count("a", 1);
+        statsd.count("b", 1);
+        statsd.count("a", 1);
+        statsd.count("b", 1);
+        assert!(statsd.sender().borrow().is_empty());
+        statsd.count("c", 1);
+        assert_eq!(*statsd.sender().borrow(), vec!["a:2|c", "b:2|c", "c:1|c"]);
+        statsd.count("d", 1);
+        assert_eq!(statsd.sender().borrow().len(), 3);
+    }
+
+    #[test]
+    fn test_emit_snapshot() {
+        let statsd = test_client().with_aggregation(Duration::from_secs(3600));
+        statsd.emit_snapshot();
+        assert!(statsd.sender().borrow().is_empty());
+        statsd.count("a", 2);
+        statsd.count("b", -3);
+        statsd.emit_snapshot();
+        assert_eq!(*statsd.sender().borrow(), vec!["a:2|g\nb:0|g\nb:-3|g"]);
+
+        // the snapshot did not reset the totals, the flush does
+        statsd.count("a", 1);
+        statsd.emit_snapshot();
+        assert_eq!(statsd.sender().borrow()[1], "a:3|g\nb:0|g\nb:-3|g");
+        statsd.sender().borrow_mut().clear();
+        statsd.flush();
+        assert_eq!(*statsd.sender().borrow(), vec!["a:3|c", "b:-3|c"]);
+        statsd.sender().borrow_mut().clear();
+        statsd.emit_snapshot();
+        assert!(statsd.sender().borrow().is_empty());
+
+        let statsd = test_client().with_aggregation(Duration::from_secs(3600)).with_batching();
+        statsd.count("a", 2);
+        statsd.gauge("level", 1);
+        statsd.emit_snapshot();
+        statsd.gauge("level", 2);
+        assert!(statsd.sender().borrow().is_empty());
+        statsd.flush();
+        assert_eq!(*statsd.sender().borrow(), vec!["level:1|g\na:2|g\nlevel:2|g\na:2|c"]);
+    }
+
+    #[test]
+    fn test_aggregation_flushed_on_drop() {
+        let (sender, receiver) = mpsc::channel();
+        let statsd = StatsdOutlet::outlet(sender, "", 1.0).unwrap().with_aggregation(Duration::from_secs(3600));
+        statsd.count("a", 4);
+        statsd.count("a", 4);
+        drop(statsd);
+        assert_eq!(receiver.iter().collect::<Vec<String>>(), vec!["a:8|c"]);
+    }
+
+    #[test]
+    fn test_disabled() {
+        let statsd = super::StatsdClient::disabled();
+        statsd.count("a", 1);
+        statsd.gauge("b", 2);
+        statsd.time_interval_ms("c", 3);
+        assert_eq!(statsd.stats(), SenderStats::default());
+
+        let statsd: StatsdOutlet<Option<RefCell<Vec<String>>>> = StatsdOutlet::outlet(None, "", 1.0).unwrap();
+        statsd.count("a", 1);
+        assert!(statsd.into_inner().is_none());
+        let statsd = StatsdOutlet::outlet(Some(RefCell::new(Vec::new())), "", 1.0).unwrap();
+        statsd.count("a", 1);
+        assert_eq!(statsd.into_inner().unwrap().into_inner(), vec!["a:1|c"]);
+    }
+
+    #[test]
+    fn test_tee() {
+        let statsd = StatsdOutlet::outlet(TeeSender::new(RefCell::new(Vec::new()), RefCell::new(Vec::new())), "a.", 1.0).unwrap();
+        statsd.count("hits", 1);
+        statsd.gauge("queue", 2);
+        let (first, second) = statsd.into_inner().into_inner();
+        assert_eq!(first.into_inner(), vec!["a.hits:1|c", "a.queue:2|g"]);
+        assert_eq!(second.into_inner(), vec!["a.hits:1|c", "a.queue:2|g"]);
+
+        let statsd = StatsdOutlet::outlet(TeeSender::new(Failing(ErrorKind::ConnectionRefused), RefCell::new(Vec::new())), "", 1.0).unwrap();
+        assert!(statsd.try_count("hits", 1).is_err());
+        assert_eq!(statsd.into_inner().into_inner().1.into_inner(), vec!["hits:1|c"]);
+    }
+
+    #[test]
+    fn test_log_sender() {
+        let statsd = StatsdOutlet::outlet(LogSender::new(Vec::new(), "statsd"), "a.", 1.0).unwrap().with_batching();
+        statsd.count("hits", 1);
+        statsd.gauge("queue", 2);
+        let log = statsd.into_inner().into_inner();
+        assert_eq!(String::from_utf8(log).unwrap(), "statsd: a.hits:1|c\nstatsd: a.queue:2|g\n");
+    }
+
+    #[test]
+    fn test_samplers() {
+        assert!((0..1000).all(|_| Pcg32Sampler.accept(super::to_int_rate(1.0), "k")));
+        assert!((0..1000).all(|_| !Pcg32Sampler.accept(super::to_int_rate(0.0), "k")));
+        let statsd = StatsdOutlet::outlet(RefCell::new(Vec::new()), "", 0.5).unwrap().with_sampler(Pcg32Sampler);
+        for _ in 0..10_000 { statsd.count("k", 1) }
+        assert!((4_000..6_000).contains(&statsd.sender().borrow().len()));
+        let statsd = StatsdOutlet::outlet(RefCell::new(Vec::new()), "", 0.5).unwrap().with_fork_reseeding(false);
+        for _ in 0..10_000 { statsd.count("k", 1) }
+        assert!((4_000..6_000).contains(&statsd.sender().borrow().len()));
+
+        let half = super::to_int_rate(0.5);
+        let keys: Vec<String> = (0..1000).map(|i| format!("key{}", i)).collect();
+        let kept: Vec<bool> = keys.iter().map(|key| HashSampler.accept(half, key)).collect();
+        assert!(keys.iter().zip(&kept).all(|(key, &kept)| (0..10).all(|_| HashSampler.accept(half, key) == kept)));
+        assert!((400..600).contains(&kept.iter().filter(|&&kept| kept).count()));
+        let tenth = super::to_int_rate(0.1);
+        assert!(keys.iter().zip(&kept).all(|(key, &kept)| kept || !HashSampler.accept(tenth, key)));
+
+        let statsd = StatsdOutlet::outlet(RefCell::new(Vec::new()), "", 0.5).unwrap().with_sampler(HashSampler);
+        for key in &keys { for _ in 0..3 { statsd.count(key, 1) } }
+        let lines = statsd.sender().borrow();
+        assert_eq!(lines.len(), 3 * kept.iter().filter(|&&kept| kept).count());
+        assert!(lines.iter().all(|line| line.ends_with("|c|@0.5")));
+
+        // decisions do not depend on the process, the upper half of the hash of "a" being 0x82a2a958
+        assert!(HashSampler.accept(half, "a"));
+        assert!(!HashSampler.accept(super::to_int_rate(0.25), "a"));
+    }
+
+    #[test]
+    fn test_stats() {
+        let statsd = test_client();
+        statsd.count("a", 1);
+        statsd.gauge("bb", 2);
+        statsd.gauge_f64("c", f64::NAN);
+        assert_eq!(statsd.stats(), SenderStats { packets: 2, bytes: 11, malformed: 1, ..SenderStats::default() });
+
+        let statsd = StatsdOutlet::outlet(Failing(ErrorKind::ConnectionRefused), "", 1.0).unwrap().with_batching();
+        statsd.count("a", 1);
+        statsd.count("b", 1);
+        statsd.flush();
+        assert_eq!(statsd.stats(), SenderStats { errors: 1, ..SenderStats::default() });
+        let statsd = StatsdOutlet::outlet(Failing(ErrorKind::WouldBlock), "", 1.0).unwrap();
+        statsd.count("a", 1);
+        assert_eq!(statsd.stats(), SenderStats { would_block: 1, ..SenderStats::default() });
+    }
+
+    #[test]
+    fn test_would_block() {
+        let statsd = StatsdOutlet::outlet(Failing(ErrorKind::WouldBlock), "", 1.0).unwrap();
+        statsd.count("a", 1);
+        statsd.gauge("b", 2);
+        assert_eq!((statsd.would_block(), statsd.stats().errors), (2, 0));
+
+        let statsd = StatsdOutlet::outlet(Failing(ErrorKind::ConnectionRefused), "", 1.0).unwrap();
+        statsd.count("a", 1);
+        assert_eq!((statsd.would_block(), statsd.stats().errors), (0, 1));
+    }
+
+    /// Blocks while closed, then accepts every packet.
+    struct Gate {
+        open: AtomicBool,
+        sent: Mutex<Vec<String>>,
+    }
+
+    impl super::SendStats for Gate {
+        fn send_stats(&self, str: String) -> io::Result<()> {
+            if !self.open.load(Ordering::Relaxed) {
+                return Err(io::Error::from(ErrorKind::WouldBlock))
+            }
+            self.sent.lock().unwrap().push(str);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_retry_queue() {
+        let statsd = StatsdOutlet::outlet(Gate { open: AtomicBool::new(false), sent: Mutex::new(Vec::new()) }, "", 1.0).unwrap()
+            .with_retry_queue(2);
+        assert!(statsd.try_count("a", 1).is_ok());
+        assert_eq!((statsd.queued_retries(), statsd.would_block()), (1, 0));
+        statsd.sender().open.store(true, Ordering::Relaxed);
+        statsd.count("b", 1);
+        assert_eq!(*statsd.sender().sent.lock().unwrap(), ["a:1|c", "b:1|c"]);
+        assert_eq!((statsd.queued_retries(), statsd.stats().packets), (0, 2));
+
+        // sustained blocking keeps the newest packets only
+        statsd.sender().open.store(false, Ordering::Relaxed);
+        for value in 0..5 { statsd.count("c", value) }
+        assert_eq!((statsd.queued_retries(), statsd.would_block()), (2, 3));
+        assert_eq!(statsd.try_flush().unwrap_err().kind(), ErrorKind::WouldBlock);
+        statsd.sender().open.store(true, Ordering::Relaxed);
+        statsd.try_flush().unwrap();
+        assert_eq!(statsd.sender().sent.lock().unwrap()[2..], ["c:3|c", "c:4|c"]);
+        assert_eq!(statsd.queued_retries(), 0);
+    }
+
+    impl super::SendStats for Mutex<Vec<String>> {
+        fn send_stats(&self, str: String) -> io::Result<()> {
+            self.lock().unwrap().push(str);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_panic_hook() {
+        let statsd = Arc::new(StatsdOutlet::outlet(Mutex::new(Vec::new()), "a.b.", super::FULL_SAMPLING_RATE).unwrap());
+        super::install_panic_hook(statsd.clone(), "panic");
+        assert!(panic::catch_unwind(|| panic!("boom")).is_err());
+        // back to the default hook
+        drop(panic::take_hook());
+        assert!(statsd.sender().lock().unwrap().contains(&"a.b.panic:1|c".to_string()));
+
+        // buffered metrics are flushed along with the panic count
+        let statsd = Arc::new(StatsdOutlet::outlet(Mutex::new(Vec::new()), "", super::FULL_SAMPLING_RATE).unwrap()
+            .with_batching().with_aggregation(Duration::from_secs(3600)));
+        statsd.gauge("before", 1);
+        statsd.count("hits", 2);
+        super::install_panic_hook(statsd.clone(), "panic");
+        assert!(panic::catch_unwind(|| panic!("boom")).is_err());
+        drop(panic::take_hook());
+        let sent = statsd.sender().lock().unwrap().join("\n");
+        for line in ["panic:1|c", "before:1|g", "hits:2|c"] {
+            assert!(sent.lines().any(|l| l == line), "{} not in {:?}", line, sent);
+        }
+
+        // a panic while the batch is locked does not deadlock the hook
+        struct PanicOnce(AtomicBool, Mutex<Vec<String>>);
+        impl super::SendStats for PanicOnce {
+            fn send_stats(&self, str: String) -> io::Result<()> {
+                if !self.0.swap(true, Ordering::Relaxed) { panic!("send failed") }
+                self.1.lock().unwrap().push(str);
+                Ok(())
+            }
+        }
+        let statsd = Arc::new(StatsdOutlet::outlet(PanicOnce(AtomicBool::new(false), Mutex::new(Vec::new())), "", 1.0).unwrap().with_batching());
+        statsd.count("batched", 1);
+        super::install_panic_hook(statsd.clone(), "panic");
+        assert!(panic::catch_unwind(panic::AssertUnwindSafe(|| statsd.flush())).is_err());
+        drop(panic::take_hook());
+        assert_eq!(*statsd.sender().1.lock().unwrap(), vec!["panic:1|c"]);
+        // the batch lock was poisoned by the panic, flushing it again on drop would panic
+        ::std::mem::forget(statsd);
+    }
+
+    #[test]
+    fn test_adaptive_rate() {
+        let statsd = AdaptiveOutlet::outlet(RefCell::new(Vec::new()), "", 100).unwrap();
+        let start = Instant::now();
+        for _ in 0..10_000 { statsd.emit("hot", 1, "|c", start) }
+        assert_eq!(statsd.effective_rate(), 1.0);
+
+        for _ in 0..1000 { statsd.emit("hot", 1, "|c", start + Duration::from_secs(1)) }
+        assert_eq!(statsd.effective_rate(), 0.01);
+
+        statsd.emit("cold", 1, "|c", start + Duration::from_secs(3));
+        assert_eq!(statsd.effective_rate(), 0.2);
+
+        statsd.emit("cold", 1, "|c", start + Duration::from_secs(5));
+        assert_eq!(statsd.effective_rate(), 1.0);
+
+        let sent = statsd.into_inner().into_inner();
+        assert_eq!(sent.iter().filter(|s| *s == "hot:1|c").count(), 10_000);
+        let sampled = sent.iter().filter(|s| *s == "hot:1|c|@0.01").count();
+        assert!(sampled > 0 && sampled < 50, "{} samples at 1%", sampled);
+        assert_eq!(sent.last().unwrap(), "cold:1|c");
+    }
+
+    /// Signals `entered` on each send, then blocks until a `release` is received.
+    struct Blocking {
+        entered: Mutex<Sender<()>>,
+        release: Mutex<mpsc::Receiver<()>>,
+    }
+
+    impl super::SendStats for Blocking {
+        fn send_stats(&self, _: String) -> io::Result<()> {
+            self.entered.lock().unwrap().send(()).unwrap();
+            self.release.lock().unwrap().recv().unwrap();
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_adaptive_sends_unlocked() {
+        let (entered, on_enter) = mpsc::channel();
+        let (release, on_release) = mpsc::channel();
+        let sender = Blocking { entered: Mutex::new(entered), release: Mutex::new(on_release) };
+        let statsd = Arc::new(AdaptiveOutlet::outlet(sender, "", 100).unwrap());
+        let emitter = {
+            let statsd = statsd.clone();
+            thread::spawn(move || statsd.count("slow", 1))
+        };
+        on_enter.recv().unwrap();
+
+        // other callers go on while a send is in progress
+        let (done, on_done) = mpsc::channel();
+        let reader = {
+            let statsd = statsd.clone();
+            thread::spawn(move || done.send(statsd.effective_rate()).unwrap())
+        };
+        let rate = on_done.recv_timeout(Duration::from_secs(5));
+        release.send(()).unwrap();
+        emitter.join().unwrap();
+        reader.join().unwrap();
+        assert_eq!(rate, Ok(1.0));
+    }
+
+    #[test]
+    fn test_reconnect_after_send_error() {
+        let closed = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let address = closed.local_addr().unwrap();
+        drop(closed);
+        let statsd = StatsdOutlet::reconnecting(address, "", super::FULL_SAMPLING_RATE, Duration::from_secs(0)).unwrap();
+        for _ in 0..100 {
+            statsd.count("bouring", 22);
+            if statsd.recreations() > 0 { break }
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert!(statsd.recreations() > 0);
+    }
+
+    #[test]
+    fn test_socket_addr_constructors() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let address: SocketAddr = server.local_addr().unwrap();
+        StatsdOutlet::new_async(address, "", 1.0, 8).unwrap().count("async", 1);
+        super::ShardedClient::sharded(&[address], "", 1.0).unwrap().count("sharded", 1);
+        StatsdOutlet::reconnecting(address, "", 1.0, Duration::from_secs(1)).unwrap().count("reconnecting", 1);
+        StatsdOutlet::outlet(super::ReconnectingSocket::new(("127.0.0.1", address.port()), Duration::from_secs(1)).unwrap(), "", 1.0).unwrap()
+            .count("tuple", 1);
+        let mut received = Vec::new();
+        let mut buf = [0; 64];
+        for _ in 0..4 {
+            let len = server.recv(&mut buf).unwrap();
+            received.push(String::from_utf8_lossy(&buf[..len]).into_owned());
+        }
+        received.sort();
+        assert_eq!(received, vec!["async:1|c", "reconnecting:1|c", "sharded:1|c", "tuple:1|c"]);
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn test_time_future() {
+        use std::future::Future;
+        use std::pin::pin;
+        use std::task::{Context, Poll, Waker};
+
+        struct Delay(Instant);
+        impl Future for Delay {
+            type Output = u32;
+            fn poll(self: ::std::pin::Pin<&mut Self>, _cx: &mut Context) -> Poll<u32> {
+                if Instant::now() >= self.0 { Poll::Ready(42) } else { Poll::Pending }
+            }
+        }
+
+        let statsd = test_client();
+        let mut future = pin!(statsd.time_future("berry", Delay(Instant::now() + Duration::from_millis(20))));
+        let mut cx = Context::from_waker(Waker::noop());
+        let output = loop {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut cx) { break output }
+            thread::sleep(Duration::from_millis(1));
+        };
+        assert_eq!(output, 42);
+        let line = statsd.sender().borrow_mut().pop().unwrap();
+        let ms: u64 = line.trim_start_matches("berry:").trim_end_matches("|ms").parse().unwrap();
+        assert!(ms >= 20, "{}", line);
+    }
+
+    #[test]
+    fn test_count_once() {
+        let statsd = test_client();
+        let dedupe = Deduplicator::new(Duration::from_secs(10), 2);
+        let start = Instant::now();
+        assert!(dedupe.count_once_at(&statsd, "retried", 1, "req-1", start));
+        assert!(!dedupe.count_once_at(&statsd, "retried", 1, "req-1", start + Duration::from_secs(5)));
+        assert!(dedupe.count_once_at(&statsd, "retried", 1, "req-1", start + Duration::from_secs(10)));
+        assert!(dedupe.count_once_at(&statsd, "retried", 1, "req-2", start + Duration::from_secs(11)));
+        assert!(!dedupe.count_once_at(&statsd, "retried", 1, "req-2", start + Duration::from_secs(12)));
+        assert_eq!(statsd.sender().borrow().len(), 3);
+    }
+
+    #[test]
+    fn test_count_once_capacity() {
+        let statsd = test_client();
+        let dedupe = Deduplicator::new(Duration::from_secs(10), 2);
+        let start = Instant::now();
+        for token in &["a", "b", "c", "a"] {
+            assert!(dedupe.count_once_at(&statsd, "retried", 1, token, start));
+        }
+        assert!(!dedupe.count_once_at(&statsd, "retried", 1, "c", start));
+        assert_eq!(statsd.sender().borrow().len(), 4);
+    }
+
+    #[test]
+    fn test_count_throttled() {
+        let statsd = test_client();
+        let cooldown = Duration::from_secs(10);
+        let start = Instant::now();
+        statsd.count_throttled_at("spike", 1, cooldown, start);
+        statsd.count_throttled_at("spike", 2, cooldown, start + Duration::from_secs(1));
+        statsd.count_throttled_at("other", 3, cooldown, start + Duration::from_secs(2));
+        statsd.count_throttled_at("spike", 4, cooldown, start + Duration::from_secs(10));
+        statsd.count_throttled_at("spike", 5, cooldown, start + Duration::from_secs(19));
+        assert_eq!(*statsd.sender().borrow(), vec!["spike:1|c", "other:3|c", "spike:4|c"]);
+
+        let statsd = StatsdOutlet::outlet(RefCell::new(Vec::new()), "", 0.0).unwrap();
+        statsd.count_throttled_at("spike", 1, cooldown, start);
+        assert!(statsd.throttled.lock().unwrap().is_empty());
+
+        let statsd = test_client().with_aggregation(Duration::from_secs(3600));
+        statsd.count_throttled_at("spike", 1, cooldown, start);
+        statsd.count_throttled_at("spike", 2, cooldown, start + Duration::from_secs(1));
+        statsd.count_throttled_at("spike", 4, cooldown, start + Duration::from_secs(10));
+        assert!(statsd.sender().borrow().is_empty());
+        statsd.flush();
+        assert_eq!(*statsd.sender().borrow(), vec!["spike:5|c"]);
+    }
+
+    #[test]
+    fn test_file_sink_rotation() {
+        let dir = ::std::env::temp_dir().join(format!("statsd-file-sink-{}", ::std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("metrics.log");
+        let statsd = StatsdOutlet::outlet(FileSink::new(&path, 24, 2).unwrap(), "", super::FULL_SAMPLING_RATE).unwrap();
+        for i in 0..7 { statsd.count("bouring", i) }
+        drop(statsd);
+
+        let read = |name: &str| fs::read_to_string(dir.join(name)).unwrap();
+        assert_eq!(read("metrics.log"), "bouring:6|c\n");
+        assert_eq!(read("metrics.log.1"), "bouring:4|c\nbouring:5|c\n");
+        assert_eq!(read("metrics.log.2"), "bouring:2|c\nbouring:3|c\n");
+        assert!(!dir.join("metrics.log.3").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_delta_counter() {
+        let statsd = test_client();
+        let delta = DeltaCounter::new(OnReset::EmitCurrent);
+        delta.record_absolute(&statsd, "rx", 100);
+        delta.record_absolute(&statsd, "rx", 150);
+        delta.record_absolute(&statsd, "rx", 150);
+        delta.record_absolute(&statsd, "tx", 7);
+        delta.record_absolute(&statsd, "rx", 20);
+        delta.record_absolute(&statsd, "rx", 25);
+        assert_eq!(*statsd.sender().borrow(), vec!["rx:50|c", "rx:0|c", "rx:20|c", "rx:5|c"]);
+    }
+
+    #[test]
+    fn test_delta_counter_reset_to_zero() {
+        let statsd = test_client();
+        let delta = DeltaCounter::new(OnReset::EmitZero);
+        delta.record_absolute(&statsd, "rx", 100);
+        delta.record_absolute(&statsd, "rx", 20);
+        delta.record_absolute(&statsd, "rx", 25);
+        assert_eq!(*statsd.sender().borrow(), vec!["rx:0|c", "rx:5|c"]);
+    }
+
+    struct CountingAlloc;
+
+    thread_local! {
+        static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+    }
+
+    unsafe impl GlobalAlloc for CountingAlloc {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            let _ = ALLOCATIONS.try_with(|a| a.set(a.get() + 1));
+            System.alloc(layout)
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            System.dealloc(ptr, layout)
+        }
+    }
+
+    #[global_allocator]
+    static ALLOCATOR: CountingAlloc = CountingAlloc;
+
+    #[test]
+    fn test_count_into() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let statsd = super::StatsdClient::new(server.local_addr().unwrap(), "a.b.", 1.0).unwrap();
+        let mut buf = String::with_capacity(64);
+        statsd.count_into(&mut buf, "bouring", 1);
+
+        let before = ALLOCATIONS.with(|a| a.get());
+        statsd.count_into(&mut buf, "bouring", 22);
+        assert_eq!(ALLOCATIONS.with(|a| a.get()), before);
+        assert_eq!(buf, "a.b.bouring:22|c");
+
+        let mut received = [0; 64];
+        server.recv(&mut received).unwrap();
+        let len = server.recv(&mut received).unwrap();
+        assert_eq!(&received[..len], b"a.b.bouring:22|c");
+
+        let statsd = StatsdOutlet::outlet(RefCell::new(Vec::new()), "a", 1.0).unwrap().with_separator("=", "/").with_aggregation(Duration::from_secs(60));
+        statsd.count_into(&mut buf, "hits", 3);
+        assert_eq!(*statsd.sender().borrow(), vec!["a/hits=3|c"]);
+
+        #[cfg(feature = "graphite")]
+        {
+            let statsd = StatsdOutlet::outlet(RefCell::new(Vec::new()), "a", 1.0).unwrap().with_encoding(super::Encoding::Graphite);
+            statsd.count_into(&mut buf, "hits", 3);
+            assert!(buf.starts_with("a.hits 3 "), "{}", buf);
+        }
+    }
+
+    #[test]
+    fn test_builder() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let statsd = super::StatsdClient::builder(server.local_addr().unwrap())
+            .prefix("app.")
+            .sample_rate(1.0)
+            .max_payload(64)
+            .default_tags(&[("env", "prod")])
+            .batching(true)
+            .build().unwrap();
+        statsd.count("a", 1);
+        statsd.gauge("b", 2);
+        statsd.flush();
+        let mut received = [0; 64];
+        let len = server.recv(&mut received).unwrap();
+        assert_eq!(&received[..len], b"app.a:1|c|#env:prod\napp.b:2|g|#env:prod");
+
+        let address = server.local_addr().unwrap().to_string();
+        assert_eq!(super::StatsdClient::builder(&address).sample_rate(1.5).build().err().unwrap().kind(), ErrorKind::InvalidInput);
+        assert!(super::StatsdClient::new(&address, "", 2.0).is_err());
+    }
+
+    #[test]
+    fn test_from_socket() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let udp_socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        udp_socket.connect(server.local_addr().unwrap()).unwrap();
+        let local = udp_socket.local_addr().unwrap();
+        let statsd = super::StatsdClient::from_socket(udp_socket, "a.", 1.0).unwrap();
+        statsd.count("hits", 1);
+        let mut received = [0; 64];
+        let (len, from) = server.recv_from(&mut received).unwrap();
+        assert_eq!((&received[..len], from), (&b"a.hits:1|c"[..], local));
+
+        let unconnected = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let statsd = super::StatsdClient::from_socket(unconnected, "", 1.0).unwrap();
+        statsd.count("hits", 1);
+        assert_eq!(statsd.stats().errors, 1);
+    }
+
+    #[test]
+    fn test_send_sync() {
+        // compile-time checks that clients can be shared across threads
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<super::StatsdClient>();
+        assert_send_sync::<StatsdOutlet<super::NullSender>>();
+        assert_send_sync::<StatsdOutlet<AsyncSender>>();
+        assert_send_sync::<Sharded<UdpSocket>>();
+        assert_send_sync::<AdaptiveOutlet<UdpSocket>>();
+    }
+
+    #[test]
+    fn test_try_clone() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let statsd = super::StatsdClient::new(server.local_addr().unwrap(), "a.", 1.0).unwrap().with_default_tags(&[("env", "prod")]);
+        let clone = statsd.try_clone().unwrap();
+        let sent = thread::spawn(move || { clone.count("from_clone", 1); clone.stats() }).join().unwrap();
+        statsd.count("from_original", 1);
+        assert_eq!((sent.packets, statsd.stats().packets), (1, 1));
+        let mut received = [0; 64];
+        let len = server.recv(&mut received).unwrap();
+        assert_eq!(&received[..len], b"a.from_clone:1|c|#env:prod");
+        let len = server.recv(&mut received).unwrap();
+        assert_eq!(&received[..len], b"a.from_original:1|c|#env:prod");
+    }
+
+    #[test]
+    fn test_bind_address() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let local = "127.0.0.1:0".parse().unwrap();
+        let statsd = super::StatsdClient::builder(server.local_addr().unwrap()).bind_address(local).build().unwrap();
+        statsd.count("hits", 1);
+        let mut received = [0; 64];
+        let (len, from) = server.recv_from(&mut received).unwrap();
+        assert_eq!(&received[..len], b"hits:1|c");
+        assert!(from.ip().is_loopback());
+
+        let error = super::StatsdClient::builder(server.local_addr().unwrap()).bind_address("[::1]:0".parse().unwrap()).build().err().unwrap();
+        assert_eq!(error.kind(), ErrorKind::InvalidInput);
+        assert!(error.to_string().contains("IPv4 cannot be reached from IPv6 local address [::1]:0"), "{}", error);
+    }
+
+    #[test]
+    fn test_ipv6() {
+        // not every environment has an IPv6 loopback
+        let server = match UdpSocket::bind("[::1]:0") {
+            Ok(server) => server,
+            Err(_) => return,
+        };
+        let statsd = super::StatsdClient::new(server.local_addr().unwrap(), "a.", 1.0).unwrap();
+        statsd.count("hits", 1);
+        let mut received = [0; 64];
+        let len = server.recv(&mut received).unwrap();
+        assert_eq!(&received[..len], b"a.hits:1|c");
+    }
+
+    #[test]
+    fn test_resolution() {
+        assert!(super::StatsdClient::new("localhost:8125", "", 1.0).is_ok());
+        let addresses: &[SocketAddr] = &["127.0.0.1:8125".parse().unwrap(), "127.0.0.2:8125".parse().unwrap()];
+        let statsd = super::StatsdClient::new(addresses, "", 1.0).unwrap();
+        assert_eq!(statsd.sender().peer_addr().unwrap(), addresses[0]);
+
+        let error = super::StatsdClient::new("statsd.invalid:8125", "", 1.0).err().unwrap();
+        assert!(!error.to_string().is_empty());
+        let error = super::StatsdClient::new(&[][..] as &[SocketAddr], "", 1.0).err().unwrap();
+        assert_eq!(error.kind(), ErrorKind::AddrNotAvailable);
+        assert_eq!(error.to_string(), "the statsd address resolved to no address");
+    }
+
+    #[test]
+    fn test_invalid_rates() {
+        for &rate in &[1.5, -0.1, f64::NAN, f64::INFINITY] {
+            let error = StatsdOutlet::outlet(RefCell::new(Vec::new()), "", rate).err().unwrap();
+            assert_eq!(error.kind(), ErrorKind::InvalidInput);
+            assert!(super::StatsdClient::new("127.0.0.1:8125", "", rate).is_err());
+        }
+        assert!(StatsdOutlet::outlet(RefCell::new(Vec::new()), "", 0.0).is_ok());
+        assert!(StatsdOutlet::outlet(RefCell::new(Vec::new()), "", -0.0).is_ok());
+    }
 
-mod pcg32;
+    #[test]
+    fn test_reused_line_buffer() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let statsd = super::StatsdClient::new(server.local_addr().unwrap(), "a.b.", 1.0).unwrap();
+        statsd.count("bouring", 1);
 
-/// Use a safe maximum size for UDP to prevent fragmentation.
-const MAX_UDP_PAYLOAD: usize = 576;
+        let before = ALLOCATIONS.with(|a| a.get());
+        statsd.count("bouring", 22);
+        statsd.gauge("level", -3);
+        statsd.time_interval_ms("took", 4);
+        assert_eq!(ALLOCATIONS.with(|a| a.get()), before);
 
-pub const FULL_SAMPLING_RATE: f64 = 1.0;
+        let mut received = [0; 64];
+        let sent: Vec<Vec<u8>> = (0..4).map(|_| { let len = server.recv(&mut received).unwrap(); received[..len].to_vec() }).collect();
+        assert_eq!(sent, vec![b"a.b.bouring:1|c".to_vec(), b"a.b.bouring:22|c".to_vec(), b"a.b.level:-3|g".to_vec(), b"a.b.took:4|ms".to_vec()]);
+    }
 
-pub trait SendStats: Sized {
-    fn send_stats(&self, str: String);
-}
+    #[test]
+    fn test_nested_line_buffer() {
+        struct Nested(StatsdOutlet<RefCell<Vec<String>>>);
+        impl super::SendStats for Nested {
+            fn send_stats(&self, str: String) -> io::Result<()> {
+                self.0.count("inner", 1);
+                self.0.sender().borrow_mut().push(str);
+                Ok(())
+            }
+        }
+        let statsd = StatsdOutlet::outlet(Nested(test_client()), "", 1.0).unwrap();
+        statsd.count("outer", 2);
+        assert_eq!(*statsd.sender().0.sender().borrow(), vec!["inner:1|c", "outer:2|c"]);
+    }
 
-/// Real implementation, send a UDP packet for every stat
-impl SendStats for UdpSocket {
-    fn send_stats(&self, str: String) {
-        match self.send(str.as_bytes()) {
-            Ok(_) => {}, // TODO count packets sent for batch reporting
-            _ => {}// TODO count send errors for batch reporting
+    #[test]
+    fn test_percentiles_exact() {
+        let statsd = test_client();
+        let tracker = PercentileTracker::new(&[50, 95, 99], 1000).unwrap();
+        for v in (1..=1000).rev() { tracker.observe("latency", v) }
+        tracker.observe("single", 7);
+        tracker.flush(&statsd);
+        let mut sent = statsd.sender().borrow().clone();
+        sent.sort();
+        assert_eq!(sent, vec!["latency.p50:500|g", "latency.p95:950|g", "latency.p99:990|g",
+                              "single.p50:7|g", "single.p95:7|g", "single.p99:7|g"]);
+
+        tracker.flush(&statsd);
+        assert_eq!(statsd.sender().borrow().len(), 6);
+
+        assert_eq!(PercentileTracker::new(&[50, 0], 1000).err().unwrap().kind(), ErrorKind::InvalidInput);
+        assert_eq!(PercentileTracker::new(&[101], 1000).err().unwrap().kind(), ErrorKind::InvalidInput);
+        assert_eq!(PercentileTracker::new(&[50], 0).err().unwrap().kind(), ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn test_percentiles_reservoir() {
+        let statsd = test_client();
+        let tracker = PercentileTracker::new(&[50, 95], 500).unwrap();
+        for v in 1..=100_000 { tracker.observe("latency", v) }
+        tracker.flush(&statsd);
+        let value = |line: &str| -> u64 { line.split([':', '|']).nth(1).unwrap().parse().unwrap() };
+        let sent = statsd.sender().borrow();
+        let p50 = value(sent.iter().find(|l| l.starts_with("latency.p50")).unwrap());
+        let p95 = value(sent.iter().find(|l| l.starts_with("latency.p95")).unwrap());
+        assert!((40_000..60_000).contains(&p50), "p50 {}", p50);
+        assert!((90_000..99_000).contains(&p95), "p95 {}", p95);
+    }
+
+    #[test]
+    fn test_cardinality_estimate() {
+        let statsd = test_client();
+        let set = CardinalitySet::new(12).unwrap();
+        for round in 0..3 {
+            for user in 0..10_000 { set.add("users", &format!("user-{}", user)) }
+            set.add("small", &round);
         }
+        set.flush(&statsd);
+        let value = |line: &str| -> u64 { line.split([':', '|']).nth(1).unwrap().parse().unwrap() };
+        let sent = statsd.sender().borrow();
+        let users = value(sent.iter().find(|l| l.starts_with("users.unique:")).unwrap());
+        // 1.04 / sqrt(4096) is about 1.6%, allow three standard errors
+        assert!((9_500..=10_500).contains(&users), "users {}", users);
+        assert!(sent.contains(&"small.unique:3|g".to_string()), "{:?}", sent);
+        drop(sent);
+
+        set.flush(&statsd);
+        assert_eq!(statsd.sender().borrow().len(), 2);
+
+        assert_eq!(CardinalitySet::new(3).err().unwrap().kind(), ErrorKind::InvalidInput);
+        assert_eq!(CardinalitySet::new(17).err().unwrap().kind(), ErrorKind::InvalidInput);
     }
-}
 
-/// A client to send application metrics to a statsd server over UDP.
-/// Multiple instances may be required if different sampling rates or prefix a required within the same application.
-pub struct StatsdOutlet<S: SendStats> {
-    sender: S,
-    prefix: String,
-    int_rate: u32,
-    gauge_suffix: String,
-    count_suffix: String,
-    time_suffix: String
-}
+    #[test]
+    fn test_default_sanitizer() {
+        let sanitizer = Sanitizer::default();
+        assert_eq!(sanitizer.sanitize("a:b|c@d\ne"), "a_b_c_d_e");
+        assert!(matches!(sanitizer.sanitize("a.b c/d"), Cow::Borrowed("a.b c/d")));
+    }
 
-pub type StatsdClient = StatsdOutlet<UdpSocket>;
+    #[test]
+    fn test_custom_sanitizer() {
+        let graphite = Sanitizer::new(&[' ', '/', ':', '|', '@', '\n'], '-');
+        assert_eq!(graphite.sanitize("disk /var:used"), "disk--var-used");
+        let dogstatsd = Sanitizer::new(&['|', '@', '\n', ',', '#'], '_');
+        assert_eq!(dogstatsd.sanitize("cache:hits,#x"), "cache:hits__x");
+    }
 
-impl StatsdClient {
-    /// Create a new `StatsdClient` sending packets to the specified `address`.
-    /// Sent metric keys will be prepended with `prefix`.
-    /// Subsampling is performed according to `float_rate` where
-    /// - 1.0 is full sampling and
-    /// - 0.0 means _no_ samples will be taken
-    /// See crate method `to_int_rate` for more details and a nice table
-    pub fn new(address: &str, prefix_str: &str, float_rate: f64) -> Result<StatsdClient> {
-        let udp_socket = UdpSocket::bind("0.0.0.0:0")?; // NB: CLOEXEC by default
-        udp_socket.set_nonblocking(true)?;
-        udp_socket.connect(address)?;
-        StatsdOutlet::outlet(udp_socket, prefix_str, float_rate)
+    #[cfg(feature = "tracing")]
+    #[test]
+    fn test_span_tags() {
+        use tracing_subscriber::layer::SubscriberExt;
+
+        let subscriber = tracing_subscriber::registry().with(super::SpanTags::new(&["route", "method"]));
+        let statsd = test_client();
+        tracing::subscriber::with_default(subscriber, || {
+            statsd.count_traced("hits", 1);
+            let outer = info_span!("request", route = "/users", user = 42);
+            let _outer = outer.enter();
+            let inner = info_span!("handler", method = "GET", route = "/users/:id");
+            inner.in_scope(|| statsd.time_interval_ms_traced("barry", 44));
+            statsd.gauge_traced("bearing", 33);
+        });
+        assert_eq!(*statsd.sender().borrow(), vec!["hits:1|c", "barry:44|ms|#method:GET,route:/users/:id", "bearing:33|g|#route:/users"]);
     }
-}
 
-/// A point in time from which elapsed time can be determined
-pub struct StartTime (u64);
+    #[test]
+    fn test_probe_closed_port() {
+        let closed = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let address = closed.local_addr().unwrap().to_string();
+        drop(closed);
+        let error = super::StatsdClient::new_probed(&address, "", 1.0, Duration::from_millis(50)).err().unwrap();
+        assert_eq!(error.kind(), ErrorKind::ConnectionRefused);
 
-impl StartTime {
-    /// The number of milliseconds elapsed between now and this StartTime
-    fn elapsed_ms(self) -> u64 {
-        (time::precise_time_ns() - self.0) / 1_000_000
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        assert!(super::StatsdClient::new_probed(server.local_addr().unwrap(), "", 1.0, Duration::from_millis(50)).is_ok());
     }
-}
 
-impl<S: SendStats> StatsdOutlet<S> {
+    /// Accept a single HTTP request on `listener`, answer with `status` and return the request body.
+    #[cfg(any(feature = "otlp", feature = "prometheus"))]
+    fn mock_http_request(listener: ::std::net::TcpListener, status: &'static str) -> thread::JoinHandle<String> {
+        thread::spawn(move || mock_http_exchange(listener, status).join().unwrap().1)
+    }
 
-    /// Create a new `StatsdClient` sending packets to the specified `address`.
-    /// Sent metric keys will be prepended with `prefix`.
-    /// Subsampling is performed according to `float_rate` where
-    /// - 1.0 is full sampling and
-    /// - 0.0 means _no_ samples will be taken
-    /// See crate method `to_int_rate` for more details and a nice table
-    fn outlet(sender: S, prefix_str: &str, float_rate: f64) -> Result<StatsdOutlet<S>> {
-        assert!(float_rate <= 1.0 && float_rate >= 0.0);
-        let prefix = prefix_str.to_string();
-        let rate_suffix = if float_rate < 1.0 { format!("|@{}", float_rate)} else { "".to_string() };
-        Ok(StatsdOutlet {
-            sender,
-            prefix,
-            int_rate: to_int_rate(float_rate),
-            time_suffix: format!("|ms{}", rate_suffix),
-            gauge_suffix: format!("|g{}", rate_suffix),
-            count_suffix: format!("|c{}", rate_suffix)
+    /// Accept a single HTTP request on `listener`, answer with `status` and return the request line and body.
+    #[cfg(any(feature = "otlp", feature = "prometheus"))]
+    fn mock_http_exchange(listener: ::std::net::TcpListener, status: &'static str) -> thread::JoinHandle<(String, String)> {
+        use std::io::{BufRead, BufReader, Read, Write};
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut request = String::new();
+            reader.read_line(&mut request).unwrap();
+            let mut length = 0;
+            loop {
+                let mut header = String::new();
+                reader.read_line(&mut header).unwrap();
+                if header == "\r\n" { break }
+                if let Some(value) = header.to_lowercase().strip_prefix("content-length:") {
+                    length = value.trim().parse().unwrap();
+                }
+            }
+            let mut body = vec![0; length];
+            reader.read_exact(&mut body).unwrap();
+            write!(reader.get_mut(), "HTTP/1.1 {}\r\nContent-Length: 0\r\n\r\n", status).unwrap();
+            (request.trim_end().to_string(), String::from_utf8(body).unwrap())
         })
     }
 
-    /// Report to statsd a count of items.
-    pub fn count(&self, key: &str, value: u64) {
-        if accept_sample(self.int_rate)  {
-            let count = &value.to_string();
-            self.send( &[key, ":", count, &self.count_suffix] )
-        }
+    #[cfg(feature = "otlp")]
+    #[test]
+    fn test_otlp_mapping() {
+        let listener = ::std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let endpoint = format!("http://{}/v1/metrics", listener.local_addr().unwrap());
+        let receiver = mock_http_request(listener, "200 OK");
+        let statsd = StatsdOutlet::otlp(&endpoint, "a.", super::FULL_SAMPLING_RATE, 100).unwrap();
+        statsd.count("hits", 20);
+        statsd.gauge("queue", 7);
+        statsd.time_interval_ms("latency", 44);
+        statsd.push().unwrap();
+        let body = receiver.join().unwrap();
+        assert!(body.starts_with(r#"{"resourceMetrics":[{"resource":{},"scopeMetrics":[{"scope":{"name":"statsd_client"},"metrics":[{"#), "{}", body);
+        assert!(body.contains(r#"{"name":"a.hits","sum":{"aggregationTemporality":1,"isMonotonic":false,"dataPoints":[{"asInt":"20","timeUnixNano":""#));
+        assert!(body.contains(r#"{"name":"a.queue","gauge":{"dataPoints":[{"asInt":"7","timeUnixNano":""#));
+        assert!(body.contains(r#"{"name":"a.latency","unit":"ms","histogram":{"aggregationTemporality":1,"dataPoints":[{"count":"1","sum":44,"min":44,"max":44,"bucketCounts":["1"],"explicitBounds":[],"timeUnixNano":""#));
+        assert!(body.ends_with("]}]}]}"));
+        assert!(statsd.push().is_ok());
     }
 
-    /// Report to statsd a non-cumulative (instant) count of items.
-    pub fn gauge(&self, key: &str, value: u64) {
-        if accept_sample(self.int_rate)  {
-            let count = &value.to_string();
-            self.send( &[key, ":", count, &self.gauge_suffix] )
-        }
+    #[cfg(feature = "otlp")]
+    #[test]
+    fn test_otlp_values() {
+        let listener = ::std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        let receiver = mock_http_request(listener, "200 OK");
+        let statsd = StatsdOutlet::otlp(&endpoint, "", super::FULL_SAMPLING_RATE, 100).unwrap();
+        statsd.gauge_f64("load", 0.25);
+        statsd.gauge("queue", 7);
+        statsd.gauge_delta("queue", -10);
+        statsd.gauge_delta("depth", 3);
+        statsd.decrement("hits");
+        statsd.push().unwrap();
+        let body = receiver.join().unwrap();
+        assert!(body.contains(r#"{"name":"load","gauge":{"dataPoints":[{"asDouble":0.25,"#), "{}", body);
+        assert!(body.contains(r#"{"name":"queue","gauge":{"dataPoints":[{"asInt":"7","#));
+        assert!(body.contains(r#"{"name":"queue","gauge":{"dataPoints":[{"asInt":"-3","#));
+        assert!(body.contains(r#"{"name":"depth","gauge":{"dataPoints":[{"asInt":"3","#));
+        assert!(body.contains(r#"{"name":"hits","sum":{"aggregationTemporality":1,"isMonotonic":false,"dataPoints":[{"asInt":"-1","#));
     }
 
-    /// Report to statsd a time interval of items.
-    pub fn time_interval_ms(&self, key: &str, interval_ms: u64) {
-        if accept_sample(self.int_rate)  {
-            self.send_time_ms(key, interval_ms);
-        }
+    #[cfg(feature = "otlp")]
+    #[test]
+    fn test_otlp_timeout() {
+        // a collector accepting the connection but never answering
+        let listener = ::std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        let sink = super::OtlpSink::new(&endpoint, 100).unwrap().timeout(Duration::from_millis(100));
+        let statsd = StatsdOutlet::outlet(sink, "", 1.0).unwrap();
+        statsd.count("hits", 1);
+        let start = Instant::now();
+        assert!(statsd.push().is_err());
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert_eq!(statsd.into_inner().errors(), 1);
+        drop(listener);
     }
 
-    /// Query current time to use eventually with `stop_time()`
-    pub fn start_time(&self) -> StartTime {
-        StartTime( time::precise_time_ns() )
+    #[cfg(feature = "otlp")]
+    #[test]
+    fn test_otlp_batch_and_errors() {
+        let listener = ::std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        let receiver = mock_http_request(listener, "503 Service Unavailable");
+        let statsd = StatsdOutlet::otlp(&endpoint, "", 0.5, 2).unwrap();
+        while !statsd.count_reporting("hits", 10) {}
+        while !statsd.count_reporting("hits", 1) {}
+        let body = receiver.join().unwrap();
+        assert!(body.contains(r#""asInt":"20""#) && body.contains(r#""asInt":"2""#), "{}", body);
+        assert_eq!(statsd.into_inner().errors(), 1);
+        assert!(super::OtlpSink::new("https://collector:4318", 1).is_err());
     }
 
-    /// An efficient timer that skips querying for stop time if sample will not be collected.
-    /// Caveat : Random sampling overhead of a few ns will be included in any reported time interval.
-    pub fn stop_time(&self, key: &str, start_time: StartTime) {
-        if accept_sample(self.int_rate)  {
-            self.send_time_ms(key, start_time.elapsed_ms());
-        }
+    #[cfg(feature = "prometheus")]
+    #[test]
+    fn test_prometheus_exposition() {
+        let listener = ::std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        let receiver = mock_http_request(listener, "200 OK");
+        let statsd = StatsdOutlet::prometheus(&endpoint, "batch", "app.", super::FULL_SAMPLING_RATE).unwrap();
+        statsd.count("hits", 20);
+        statsd.count("hits", 2);
+        statsd.gauge("queue-depth", 7);
+        statsd.time_interval_ms("latency", 44);
+        statsd.time_interval_ms("latency", 6);
+        statsd.push().unwrap();
+        assert_eq!(receiver.join().unwrap(), "# TYPE app_hits counter\napp_hits 22\n\
+            # TYPE app_latency summary\napp_latency_sum 50\napp_latency_count 2\n\
+            # TYPE app_queue_depth gauge\napp_queue_depth 7\n");
     }
 
-    fn send_time_ms(&self, key: &str, interval_ms: u64) {
-        let value = &interval_ms.to_string();
-        self.send( &[key, ":", value, &self.time_suffix] )
+    #[cfg(feature = "prometheus")]
+    #[test]
+    fn test_prometheus_errors() {
+        let listener = ::std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let endpoint = format!("http://{}/", listener.local_addr().unwrap());
+        let receiver = mock_http_request(listener, "400 Bad Request");
+        let statsd = StatsdOutlet::prometheus(&endpoint, "batch", "", 0.5).unwrap();
+        assert!(statsd.push().is_ok());
+        while !statsd.count_reporting("hits", 1) {}
+        assert!(statsd.push().is_err());
+        assert_eq!(receiver.join().unwrap(), "# TYPE hits counter\nhits 2\n");
+        assert_eq!(statsd.sender().errors(), 1);
+        assert!(super::PushgatewaySink::new("https://gateway:9091", "batch").is_err());
+
+        // a pushgateway accepting the connection but never answering
+        let listener = ::std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        let sink = super::PushgatewaySink::new(&endpoint, "batch").unwrap().timeout(Duration::from_millis(100));
+        let statsd = StatsdOutlet::outlet(sink, "", 1.0).unwrap();
+        statsd.count("hits", 1);
+        let start = Instant::now();
+        assert!(statsd.push().is_err());
+        assert!(start.elapsed() < Duration::from_secs(5));
+        drop(listener);
     }
 
-    /// Concatenate text parts into a single buffer and send it over UDP
-    fn send(&self, strings: &[&str]) {
-        let mut str = String::with_capacity(MAX_UDP_PAYLOAD);
-        str.push_str(&self.prefix);
-        for s in strings { str.push_str(s); }
-        self.sender.send_stats(str)
+    #[cfg(feature = "prometheus")]
+    #[test]
+    fn test_prometheus_values() {
+        let listener = ::std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let endpoint = format!("http://{}/base", listener.local_addr().unwrap());
+        let exchange = mock_http_exchange(listener, "200 OK");
+        let statsd = StatsdOutlet::prometheus(&endpoint, "nightly batch/1", "", super::FULL_SAMPLING_RATE).unwrap();
+        statsd.gauge_f64("load", 0.25);
+        statsd.gauge("queue", 7);
+        statsd.gauge_delta("queue", -10);
+        statsd.gauge_delta("depth", 3);
+        statsd.count("hits", 3);
+        statsd.decrement("hits");
+        statsd.time_interval_ms("latency", 1);
+        statsd.push().unwrap();
+        let (request, body) = exchange.join().unwrap();
+        assert_eq!(request, "PUT /base/metrics/job/nightly%20batch%2F1 HTTP/1.1");
+        assert_eq!(body, "# TYPE depth gauge\ndepth 3\n\
+            # TYPE hits counter\nhits 3\n\
+            # TYPE latency summary\nlatency_sum 1\nlatency_count 1\n\
+            # TYPE load gauge\nload 0.25\n\
+            # TYPE queue gauge\nqueue -3\n");
     }
 
-}
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_cpu_time() {
+        let statsd = test_client();
+        let start = statsd.start_cpu_time();
+        thread::sleep(Duration::from_millis(100));
+        let busy = Instant::now();
+        let mut spins = 0_u64;
+        while busy.elapsed() < Duration::from_millis(50) { spins = ::std::hint::black_box(spins + 1) }
+        statsd.stop_cpu_time("cpu", start);
+        let sent = statsd.sender().borrow();
+        let cpu_ms: u64 = sent[0].trim_start_matches("cpu:").trim_end_matches("|ms").parse().unwrap();
+        assert!((10..=60).contains(&cpu_ms), "{}", cpu_ms);
+    }
 
-/// Convert a floating point sampling rate to an integer so that a fast integer RNG can be used
-/// Float rate range is between 1.0 (send 100% of the samples) and 0.0 (_no_ samples taken)
-/// .    | float rate | int rate | percentage
-/// ---- | ---------- | -------- | ----
-/// all  | 1.0        | 0x0      | 100%
-/// none | 0.0        | 0xFFFFFFFF | 0%
-fn to_int_rate(float_rate: f64) -> u32 {
-    assert!(float_rate <= 1.0 && float_rate >= 0.0);
-    ((1.0 - float_rate) * ::std::u32::MAX as f64) as u32
-}
+    #[test]
+    fn test_timer_guard() {
+        let statsd = test_client();
+        {
+            let _timer = statsd.scoped_time("scope");
+            thread::sleep(Duration::from_millis(5));
+        }
+        let scoped = |statsd: &StatsdOutlet<RefCell<Vec<String>>>, fail: bool| -> Result<(), ()> {
+            let _timer = statsd.scoped_time("early");
+            if fail { return Err(()) }
+            Ok(())
+        };
+        assert!(scoped(&statsd, true).is_err());
+        let sent = statsd.sender().borrow();
+        assert_eq!(sent.len(), 2);
+        let ms: u64 = sent[0].trim_start_matches("scope:").trim_end_matches("|ms").parse().unwrap();
+        assert!(ms >= 5);
+        assert!(sent[1].starts_with("early:") && sent[1].ends_with("|ms"));
+    }
 
-fn accept_sample(int_rate: u32) -> bool {
-    pcg32::random() > int_rate
-}
+    #[test]
+    fn test_timer_guard_panic() {
+        let statsd = StatsdOutlet::outlet(Mutex::new(Vec::new()), "", 1.0).unwrap();
+        let result = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+            let _timer = statsd.scoped_time("panicky");
+            panic!("boom");
+        }));
+        assert!(result.is_err());
+        assert_eq!(statsd.sender().lock().unwrap().len(), 1);
+    }
 
-/// A convenience macro to wrap a block or an expression with a start / stop timer.
-/// Elapsed time is sent to the supplied statsd client after the computation has been performed.
-/// Expression result (if any) is transparently returned.
-#[macro_export]
-macro_rules! time {
-    ($client: expr, $key: expr, $body: block) => (
-        let start_time = $client.start_time();
-        $body
-        $client.stop_time($key, start_time);
-    );
-}
+    #[test]
+    fn test_time_duration() {
+        let statsd = test_client();
+        statsd.time_duration("barry", Duration::from_millis(44));
+        statsd.time_duration("barry", Duration::from_micros(999));
+        statsd.time_duration("barry", Duration::MAX);
+        assert_eq!(*statsd.sender().borrow(), vec!["barry:44|ms", "barry:0|ms", &format!("barry:{}|ms", u64::MAX)]);
+    }
+
+    #[test]
+    fn test_time_between() {
+        let statsd = test_client();
+        let start = Instant::now();
+        statsd.time_between("barry", start, start + Duration::from_millis(44), Inverted::Skip);
+        statsd.time_between_unix_ms("berry", 1_500_000_000_000, 1_500_000_000_055, Inverted::Skip);
+        assert_eq!(*statsd.sender().borrow(), vec!["barry:44|ms", "berry:55|ms"]);
+    }
 
+    #[test]
+    fn test_time_between_inverted() {
+        let statsd = test_client();
+        let start = Instant::now();
+        statsd.time_between("barry", start + Duration::from_millis(44), start, Inverted::Skip);
+        statsd.time_between_unix_ms("berry", 1_500_000_000_055, 1_500_000_000_000, Inverted::Skip);
+        assert!(statsd.sender().borrow().is_empty());
+        statsd.time_between("barry", start + Duration::from_millis(44), start, Inverted::Zero);
+        statsd.time_between_unix_ms("berry", 1_500_000_000_055, 1_500_000_000_000, Inverted::Zero);
+        assert_eq!(*statsd.sender().borrow(), vec!["barry:0|ms", "berry:0|ms"]);
+    }
 
-/// Integrated testing with a live statsd server can be performed according to the instructions in the README.
-#[cfg(test)]
-mod tests {
+    #[test]
+    fn test_ema_gauge() {
+        let statsd = test_client();
+        let gauge = EmaGauge::new("load", 0.5).unwrap();
+        for value in &[100, 200, 200, 0, 50] { gauge.observe(&statsd, *value) }
+        assert_eq!(*statsd.sender().borrow(), vec!["load:100|g", "load:150|g", "load:175|g", "load:88|g", "load:69|g"]);
 
-    use pcg32;
-    use super::StatsdOutlet;
-    use std::cell::RefCell;
+        assert_eq!(EmaGauge::new("load", -0.1).err().unwrap().kind(), ErrorKind::InvalidInput);
+    }
 
-    impl super::SendStats for RefCell<Vec<String>> {
-        fn send_stats(&self, str: String) {
-            self.borrow_mut().push(str);
+    #[test]
+    fn test_key_cap() {
+        let statsd = test_client();
+        let cap = KeyCap::new(2);
+        for _ in 0..5 {
+            if cap.allow("hot") { statsd.count("hot", 1) }
+            if cap.allow("cold") { statsd.count("cold", 1) }
+            if cap.allow("hot") { statsd.count("hot", 1) }
+            cap.reset();
         }
-    }
+        let sent = statsd.sender().borrow();
+        assert_eq!(sent.iter().filter(|s| *s == "hot:1|c").count(), 10);
+        assert_eq!(sent.iter().filter(|s| *s == "cold:1|c").count(), 5);
 
-    fn test_client() -> StatsdOutlet<RefCell<Vec<String>>> {
-        StatsdOutlet::outlet(RefCell::new(Vec::new()), "", super::FULL_SAMPLING_RATE).unwrap()
+        for _ in 0..5 { cap.allow("hot"); }
+        assert!(!cap.allow("hot"));
+        assert_eq!(cap.dropped(), 4);
+
+        let drops = Arc::new(Mutex::new(Vec::new()));
+        let logged = drops.clone();
+        let statsd = test_client().with_key_cap(KeyCap::new(2))
+            .with_drop_logger(move |key, reason| logged.lock().unwrap().push((key.to_string(), reason)));
+        for _ in 0..3 {
+            statsd.count("hot", 1);
+            statsd.gauge("hot", 2);
+            statsd.count("cold", 1);
+        }
+        statsd.flush();
+        statsd.time_interval_ms("hot", 3);
+        assert_eq!(*statsd.sender().borrow(), vec!["hot:1|c", "hot:2|g", "cold:1|c", "cold:1|c", "hot:3|ms"]);
+        assert_eq!(statsd.capped(), 5);
+        let reasons: Vec<DropReason> = drops.lock().unwrap().iter().map(|&(_, reason)| reason).collect();
+        assert_eq!(reasons, vec![DropReason::RateLimited; 5]);
     }
 
-    fn test_sampling_client() -> StatsdOutlet<RefCell<Vec<String>>> {
-        StatsdOutlet::outlet(RefCell::new(Vec::new()), "", 0.999).unwrap()
+    #[test]
+    fn test_middleware_order() {
+        let redact = |line: String| Some(line.replace("secret", "xxx"));
+        let drop_secrets = |line: String| if line.contains("secret") { None } else { Some(line) };
+        let sender = Layered::new(redact, Layered::new(drop_secrets, RefCell::new(Vec::new())));
+        let statsd = StatsdOutlet::outlet(sender, "", 1.0).unwrap();
+        statsd.count("secret.key", 1);
+        statsd.count("plain", 2);
+        assert_eq!(statsd.into_inner().into_inner().into_inner().into_inner(), vec!["xxx.key:1|c", "plain:2|c"]);
+
+        let sender = Layered::new(drop_secrets, Layered::new(redact, RefCell::new(Vec::new())));
+        let statsd = StatsdOutlet::outlet(sender, "", 1.0).unwrap();
+        statsd.count("secret.key", 1);
+        statsd.count("plain", 2);
+        assert_eq!(statsd.into_inner().into_inner().into_inner().into_inner(), vec!["plain:2|c"]);
+
+        let sender = Layered::new(drop_secrets, Layered::new(redact, RefCell::new(Vec::new())));
+        let statsd = StatsdOutlet::outlet(sender, "", 1.0).unwrap().with_batching();
+        statsd.count("plain", 1);
+        statsd.count("secret.key", 2);
+        statsd.count("plain", 3);
+        statsd.flush();
+        statsd.count("secret.key", 4);
+        statsd.flush();
+        assert_eq!(statsd.into_inner().into_inner().into_inner().into_inner(), vec!["plain:1|c\nplain:3|c"]);
     }
 
     #[test]
-    fn test_count() {
-        let statsd = test_client(); 
-        statsd.count("bouring", 22);
-        let str = statsd.sender.borrow_mut().pop();
-        assert_eq!(str.unwrap(), "bouring:22|c")
+    fn test_count_lazy() {
+        let statsd = StatsdOutlet::outlet(RefCell::new(Vec::new()), "", 0.0).unwrap();
+        let calls = Cell::new(0);
+        for _ in 0..100 { statsd.count_lazy("key", || { calls.set(calls.get() + 1); 1 }) }
+        assert_eq!(calls.get(), 0);
+        assert!(statsd.sender().borrow().is_empty());
+
+        let statsd = test_client();
+        statsd.count_lazy("key", || { calls.set(calls.get() + 1); 42 });
+        assert_eq!(calls.get(), 1);
+        assert_eq!(*statsd.sender().borrow(), vec!["key:42|c"]);
+
+        let statsd = test_client().with_aggregation(Duration::from_secs(3600));
+        statsd.count_lazy("key", || 2);
+        statsd.count_lazy("key", || 3);
+        assert!(statsd.sender().borrow().is_empty());
+        statsd.flush();
+        assert_eq!(*statsd.sender().borrow(), vec!["key:5|c"]);
     }
 
     #[test]
-    fn test_gauge() {
+    fn test_count_extrapolated() {
+        let statsd = StatsdOutlet::outlet(RefCell::new(Vec::new()), "", 0.1).unwrap();
+        while !statsd.count_extrapolated("key", 1) {}
+        while !statsd.count_extrapolated("key", 3) {}
+        assert_eq!(*statsd.sender().borrow(), vec!["key:10|c", "key:30|c"]);
+
         let statsd = test_client();
-        statsd.gauge("bearing", 33);
-        let str = statsd.sender.borrow_mut().pop();
-        assert_eq!(str.unwrap(), "bearing:33|g")
+        statsd.count_extrapolated("key", 7);
+        assert_eq!(*statsd.sender().borrow(), vec!["key:7|c"]);
+
+        // totalled apart from the counts sent with the rate
+        let statsd = StatsdOutlet::outlet(RefCell::new(Vec::new()), "", 0.1).unwrap().with_aggregation(Duration::from_secs(3600));
+        while !statsd.count_extrapolated("key", 1) {}
+        while !statsd.count_extrapolated("key", 3) {}
+        while !statsd.count_reporting("key", 2) {}
+        assert!(statsd.sender().borrow().is_empty());
+        statsd.flush();
+        assert_eq!(*statsd.sender().borrow(), vec!["key:2|c|@0.1", "key:40|c"]);
     }
 
     #[test]
-    fn test_time() {
+    fn test_histogram_buckets() {
+        let statsd = StatsdOutlet::outlet(RefCell::new(Vec::new()), "app.", 1.0).unwrap();
+        statsd.histogram_buckets("latency", &[("le_10", 3), ("le_100", 0), ("le_inf", 1)]);
+        assert_eq!(*statsd.sender().borrow(), vec!["app.latency.le_10:3|c\napp.latency.le_inf:1|c"]);
+
         let statsd = test_client();
-        statsd.time_interval_ms("barry", 44);
-        let str = statsd.sender.borrow_mut().pop();
-        assert_eq!(str.unwrap(), "barry:44|ms")
+        let labels: Vec<String> = (0..100).map(|i| format!("bucket_{}", i)).collect();
+        let buckets: Vec<(&str, u64)> = labels.iter().map(|l| (l.as_str(), 1)).collect();
+        statsd.histogram_buckets("h", &buckets);
+        let packets = statsd.sender().borrow();
+        assert!(packets.len() > 1);
+        assert!(packets.iter().all(|p| p.len() <= super::MAX_UDP_PAYLOAD));
+        let lines: Vec<&str> = packets.iter().flat_map(|p| p.split('\n')).collect();
+        assert_eq!(lines.len(), 100);
+        assert_eq!(lines[99], "h.bucket_99:1|c");
+
+        let statsd = test_client();
+        statsd.histogram_buckets("empty", &[("a", 0)]);
+        assert!(statsd.sender().borrow().is_empty());
+
+        let statsd = test_client().with_batching();
+        statsd.count("hits", 1);
+        statsd.histogram_buckets("latency", &[("le_10", 3), ("le_inf", 1)]);
+        statsd.count("hits", 2);
+        assert!(statsd.sender().borrow().is_empty());
+        statsd.flush();
+        assert_eq!(*statsd.sender().borrow(), vec!["hits:1|c\nlatency.le_10:3|c\nlatency.le_inf:1|c\nhits:2|c"]);
+
+        let statsd = StatsdOutlet::outlet(RefCell::new(Vec::new()), "app", 1.0).unwrap().with_separator("=", "/");
+        statsd.histogram_buckets("latency", &[("le_10", 3)]);
+        assert_eq!(*statsd.sender().borrow(), vec!["app/latency.le_10=3|c"]);
+
+        #[cfg(feature = "graphite")]
+        {
+            let statsd = StatsdOutlet::outlet(RefCell::new(Vec::new()), "app", 1.0).unwrap().with_encoding(super::Encoding::Graphite);
+            statsd.histogram_buckets("latency", &[("le_10", 3)]);
+            assert!(statsd.sender().borrow()[0].starts_with("app.latency.le_10 3 "));
+            assert_eq!(statsd.malformed(), 0);
+        }
     }
 
     #[test]
-    fn test_sampling_count() {
-        let statsd = test_sampling_client();
-        statsd.count("bouring", 22);
-        let str = statsd.sender.borrow_mut().pop();
-        assert_eq!(str.unwrap(), "bouring:22|c|@0.999")
+    fn test_sharded_routing() {
+        let shards = (0..4).map(|_| RefCell::new(Vec::new())).collect();
+        let statsd = StatsdOutlet::outlet(Sharded::new(shards), "app.", 1.0).unwrap();
+        for round in 0..3 {
+            for i in 0..4000 { statsd.count(&format!("key{}", i), round) }
+        }
+        let sends = statsd.sender().shard_sends();
+        assert_eq!(sends.iter().sum::<u64>(), 12_000);
+        assert!(sends.iter().all(|&n| (2_400..3_600).contains(&n)), "{:?}", sends);
+
+        let expected = statsd.sender().shard_of("app.key7");
+        let shards = statsd.into_inner().into_inner();
+        for (index, shard) in shards.iter().enumerate() {
+            let lines = shard.borrow();
+            assert_eq!(lines.iter().filter(|l| l.starts_with("app.key7:")).count(), if index == expected { 3 } else { 0 });
+        }
     }
 
     #[test]
-    fn test_smapling_gauge() {
-        let statsd = test_sampling_client();
-        statsd.gauge("bearing", 33);
-        let str = statsd.sender.borrow_mut().pop();
-        assert_eq!(str.unwrap(), "bearing:33|g|@0.999")
+    fn test_sharded_packets() {
+        let shards = (0..4).map(|_| RefCell::new(Vec::new())).collect();
+        let sharded = Sharded::new(shards).with_separator("=");
+        let keys = ["a", "b", "c", "d", "e", "f", "g", "h"];
+        let expected: Vec<usize> = keys.iter().map(|key| sharded.shard_of(key)).collect();
+        // FNV-1a routing, the same in every build
+        assert_eq!(sharded.shard_of("app.key7"), (super::sampler::key_hash("app.key7") % 4) as usize);
+
+        let statsd = StatsdOutlet::outlet(sharded, "", 1.0).unwrap().with_separator("=", ".").with_batching();
+        for key in &keys { statsd.count(key, 1) }
+        statsd.flush();
+        assert_eq!(statsd.sender().shard_sends().iter().sum::<u64>(), 8);
+        let shards = statsd.into_inner().into_inner();
+        for (index, shard) in shards.iter().enumerate() {
+            let lines: Vec<String> = shard.borrow().iter().flat_map(|p| p.split('\n').map(str::to_string)).collect();
+            let wanted: Vec<String> = keys.iter().zip(&expected).filter(|&(_, &s)| s == index).map(|(key, _)| format!("{}=1|c", key)).collect();
+            assert_eq!(lines, wanted);
+            assert!(shard.borrow().len() <= 1);
+        }
     }
 
+    #[cfg(any(feature = "graphite", feature = "influx"))]
     #[test]
-    fn test_sampling_time() {
-        let statsd = test_sampling_client();
-        statsd.time_interval_ms("barry", 44);
-        let str = statsd.sender.borrow_mut().pop();
-        assert_eq!(str.unwrap(), "barry:44|ms|@0.999")
+    fn test_sharded_encodings() {
+        use super::Encoding;
+        #[allow(unused_mut)]
+        let mut encodings = Vec::new();
+        #[cfg(feature = "graphite")]
+        encodings.push((Encoding::Graphite, "cache.hits", "app.cache.hits"));
+        #[cfg(feature = "influx")]
+        encodings.push((Encoding::Influx, "cache hits", "app.cache\\ hits"));
+        for (encoding, key, shard_key) in encodings {
+            let shards = (0..4).map(|_| RefCell::new(Vec::new())).collect();
+            let sharded = Sharded::new(shards).with_encoding(encoding);
+            let expected = sharded.shard_of(shard_key);
+            let statsd = StatsdOutlet::outlet(sharded, "app.", 1.0).unwrap().with_encoding(encoding).with_default_tags(&[("env", "prod")]);
+            // the value, timestamp and tags do not move the key to another shard
+            for value in 0..20 { statsd.gauge(key, value) }
+            let shards = statsd.into_inner().into_inner();
+            for (index, shard) in shards.iter().enumerate() {
+                assert_eq!(shard.borrow().len(), if index == expected { 20 } else { 0 }, "{:?}", encoding);
+            }
+        }
+    }
+
+    #[test]
+    fn test_drop_logger() {
+        let drops = Arc::new(Mutex::new(Vec::new()));
+        let logged = drops.clone();
+        let statsd = StatsdOutlet::outlet(RefCell::new(Vec::new()), "", 0.0).unwrap()
+            .with_drop_logger(move |key, reason| logged.lock().unwrap().push((key.to_string(), reason)));
+        statsd.count("sampled", 1);
+        statsd.time_interval_ms("timed", 1);
+        assert_eq!(*drops.lock().unwrap(), vec![("sampled".to_string(), DropReason::SampledOut),
+                                                ("timed".to_string(), DropReason::SampledOut)]);
+
+        let logged = drops.clone();
+        let statsd = test_client().with_drop_logger(move |key, reason| logged.lock().unwrap().push((key.to_string(), reason)));
+        drops.lock().unwrap().clear();
+        statsd.count_throttled("throttled", 1, Duration::from_secs(60));
+        statsd.count_throttled("throttled", 1, Duration::from_secs(60));
+        assert_eq!(*drops.lock().unwrap(), vec![("throttled".to_string(), DropReason::RateLimited)]);
+        assert_eq!(statsd.sender().borrow().len(), 1);
+
+        for (kind, reason) in [(ErrorKind::WouldBlock, DropReason::QueueFull), (ErrorKind::ConnectionRefused, DropReason::SendError)] {
+            let logged = drops.clone();
+            let statsd = StatsdOutlet::outlet(Failing(kind), "", 1.0).unwrap()
+                .with_drop_logger(move |key, reason| logged.lock().unwrap().push((key.to_string(), reason)));
+            drops.lock().unwrap().clear();
+            statsd.count("failed", 1);
+            assert_eq!(*drops.lock().unwrap(), vec![("failed".to_string(), reason)]);
+        }
+
+        #[cfg(unix)]
+        {
+            let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+            let logged = drops.clone();
+            let statsd = super::StatsdClient::new(server.local_addr().unwrap(), "", 1.0).unwrap()
+                .with_drop_logger(move |key, reason| logged.lock().unwrap().push((key.to_string(), reason)));
+            drops.lock().unwrap().clear();
+            let key = "k".repeat(70_000);
+            statsd.count(&key, 1);
+            assert_eq!(*drops.lock().unwrap(), vec![(key, DropReason::PayloadTooLarge)]);
+        }
     }
 
     #[test]
@@ -248,10 +3629,106 @@ mod tests {
             };
             println!("{}", sum);
         });
-        let str = statsd.sender.borrow_mut().pop();
+        let str = statsd.sender().borrow_mut().pop();
         assert!(str.unwrap().starts_with("berry"))
     }
 
+    #[test]
+    fn test_time_macro_value() {
+        let statsd = test_client();
+        let answer = time!(statsd, "answer", 6 * 7);
+        let block = time!(statsd, "block", { let mut s = String::from("mov"); s.push_str("ed"); s });
+        assert_eq!(answer, 42);
+        assert_eq!(block, "moved");
+        let sent = statsd.sender().borrow();
+        assert!(sent[0].starts_with("answer:") && sent[1].starts_with("block:"));
+    }
+
+    #[test]
+    fn test_count_batch() {
+        let statsd = StatsdOutlet::outlet(RefCell::new(Vec::new()), "a.", 1.0).unwrap().with_max_payload(24);
+        statsd.count_batch(vec![("x", 1), ("y", 2), ("z", 3)]);
+        statsd.count_batch(vec![("longer", 1), ("longest", 2)]);
+        assert_eq!(*statsd.sender().borrow(), ["a.x:1|c\na.y:2|c\na.z:3|c", "a.longer:1|c", "a.longest:2|c"]);
+        assert_eq!(statsd.stats().packets, 3);
+
+        let statsd = StatsdOutlet::outlet(RefCell::new(Vec::new()), "", 0.0).unwrap();
+        statsd.count_batch(vec![("x", 1)]);
+        assert!(statsd.sender().borrow().is_empty());
+    }
+
+    #[test]
+    fn test_time_closure() {
+        let statsd = test_client();
+        let answer = statsd.time_closure("answer", || 6 * 7);
+        let failed: Result<(), ErrorKind> = statsd.time_closure("failed", || Err(ErrorKind::Other));
+        assert_eq!((answer, failed), (42, Err(ErrorKind::Other)));
+        let sent = statsd.sender().borrow();
+        assert!(sent[0].starts_with("answer:") && sent[0].ends_with("|ms"));
+        assert!(sent[1].starts_with("failed:") && sent[1].ends_with("|ms"));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_gauge_fields() {
+        #[derive(Serialize)]
+        struct Stats {
+            hits: u64,
+            misses: u32,
+            delta: i64,
+            load: f64,
+            ratio: f32,
+            drift: f64,
+            broken: f64,
+            name: &'static str,
+        }
+        let statsd = test_client();
+        statsd.gauge_fields("cache", &Stats { hits: 12, misses: 3, delta: -1, load: 0.5, ratio: 0.25, drift: -1.5, broken: f64::NAN, name: "lru" });
+        assert_eq!(*statsd.sender().borrow(), vec!["cache.hits:12|g\ncache.misses:3|g\ncache.delta:0|g\ncache.delta:-1|g\n\
+            cache.load:0.5|g\ncache.ratio:0.25|g\ncache.drift:0|g\ncache.drift:-1.5|g"]);
+        assert_eq!(statsd.malformed(), 1);
+
+        let statsd = test_client();
+        statsd.gauge_fields("scalar", &5);
+        assert!(statsd.sender().borrow().is_empty());
+
+        let statsd = test_client().with_batching();
+        statsd.count("hits", 1);
+        statsd.gauge_fields("cache", &Stats { hits: 12, misses: 3, delta: -1, load: 0.5, ratio: 0.25, drift: 1.5, broken: 1.0, name: "lru" });
+        statsd.count("hits", 2);
+        assert!(statsd.sender().borrow().is_empty());
+        statsd.flush();
+        assert_eq!(*statsd.sender().borrow(), vec!["hits:1|c\ncache.hits:12|g\ncache.misses:3|g\ncache.delta:0|g\ncache.delta:-1|g\n\
+            cache.load:0.5|g\ncache.ratio:0.25|g\ncache.drift:1.5|g\ncache.broken:1|g\nhits:2|c"]);
+    }
+
+    #[test]
+    fn test_socket_per_thread() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let client = ThreadLocalClient::new(server.local_addr().unwrap(), "a.", 1.0).unwrap();
+        client.count("main", 1);
+        thread::scope(|s| {
+            for _ in 0..3 {
+                s.spawn(|| { client.count("worker", 1); client.count("worker", 1) });
+            }
+        });
+        let stats = client.stats();
+        assert_eq!(stats.sockets, 4);
+        assert_eq!(stats.socket_errors, 0);
+        // whether or not the workers' outlets were dropped yet, their counters are summed
+        assert_eq!(stats.sent, SenderStats { packets: 7, bytes: 10 + 6 * 12, ..SenderStats::default() });
+
+        let mut buf = [0; 64];
+        let mut received = Vec::new();
+        for _ in 0..7 {
+            let len = server.recv(&mut buf).unwrap();
+            received.push(String::from_utf8_lossy(&buf[..len]).into_owned());
+        }
+        received.sort();
+        assert_eq!(received[0], "a.main:1|c");
+        assert!(received[1..].iter().all(|r| r == "a.worker:1|c"));
+    }
+
     #[test]
     fn basic_behavior_of_pcg32() {
         let mut v = Vec::new();
@@ -260,6 +3737,46 @@ mod tests {
         for w in v.windows(2) { assert_ne!(w[0], w[1]) }
     }
 
+    #[test]
+    fn fresh_pcg32_streams_diverge() {
+        let mut a = pcg32::Pcg32::new(pcg32::seed());
+        let mut b = pcg32::Pcg32::new(pcg32::seed());
+        assert_ne!(a.next_u32(), b.next_u32());
+
+        let seeds: Vec<u64> = (0..4).map(|_| thread::spawn(pcg32::seed)).map(|t| t.join().unwrap()).collect();
+        for (i, seed) in seeds.iter().enumerate() { assert!(!seeds[i + 1..].contains(seed)) }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn pcg32_reseeded_after_fork() {
+        use std::io::{Read, Write};
+        use std::os::unix::net::UnixStream;
+        // the sequences drawn from `random` by a forked child and its parent
+        fn fork_sequences(random: fn() -> u32) -> (Vec<u8>, Vec<u8>) {
+            random();
+            let (mut parent_end, mut child_end) = UnixStream::pair().unwrap();
+            let pid = unsafe { ::libc::fork() };
+            assert!(pid >= 0);
+            if pid == 0 {
+                let child: Vec<u8> = (0..8).flat_map(|_| random().to_le_bytes().to_vec()).collect();
+                let _ = child_end.write_all(&child);
+                unsafe { ::libc::_exit(0) }
+            }
+            drop(child_end);
+            let parent: Vec<u8> = (0..8).flat_map(|_| random().to_le_bytes().to_vec()).collect();
+            let mut child = Vec::new();
+            parent_end.read_to_end(&mut child).unwrap();
+            unsafe { ::libc::waitpid(pid, ::std::ptr::null_mut(), 0) };
+            assert_eq!(child.len(), parent.len());
+            (parent, child)
+        }
+        let (parent, child) = fork_sequences(pcg32::random);
+        assert_ne!(child, parent);
+        let (parent, child) = fork_sequences(pcg32::random_inherited);
+        assert_eq!(child, parent);
+    }
+
     fn validate_rate_distribution(rate: f64) {
         let variance = rate * (1.0 - rate); // variance of the Bernoulli distribution
         let sampling = super::to_int_rate(rate);
@@ -297,18 +3814,45 @@ mod tests {
 /// Rough results on T460S, nightly 1.19.0:
 /// - PCG32 random sampling go/no-go takes ~6ns/measure
 /// - Assembling the string to send takes ~173ns/measure (measured with udp_socket.send() commented out)
+///   Lines are now formatted into a reused per-thread buffer without allocating, see `time_bench_formatting_only`.
 /// - Sending the packet takes ~4000ns/measure
 ///
 /// Setting #[cold] on send() method had no apparent effect.
 ///
 /// The moral of the story is : if you need to spend less time doing metrics, send less packets.
-/// The first thing to optimize would be to send multiple measures per packet.
-/// Also, sending asynchronously would minimize work thread jitter which might be desirable in interactive apps.
+/// The first thing to optimize would be to send multiple measures per packet, which `with_batching()` does.
+/// Also, sending asynchronously (see `AsyncSender`) minimizes work thread jitter which might be desirable in interactive apps.
 /// If performance is still a problem (really?), maybe attack packet formatting?
 #[cfg(feature="bench")]
 mod bench {
 
     use test::Bencher;
+    use std::sync::Barrier;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::thread;
+
+    /// Time 1000 calls to `send` on each of four workers spawned once, so that only sending is measured.
+    fn bench_four_workers<F: Fn() + Sync>(b: &mut Bencher, send: F) {
+        let start = Barrier::new(5);
+        let done = Barrier::new(5);
+        let stop = AtomicBool::new(false);
+        thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| loop {
+                    start.wait();
+                    if stop.load(Ordering::Relaxed) { break }
+                    for _ in 0..1000 { send() }
+                    done.wait();
+                });
+            }
+            // a first round outside of the timing, opening per-thread sockets
+            start.wait();
+            done.wait();
+            b.iter(|| { start.wait(); done.wait() });
+            stop.store(true, Ordering::Relaxed);
+            start.wait();
+        });
+    }
 
     #[bench]
     fn time_bench_ten_percent(b: &mut Bencher) {
@@ -334,10 +3878,40 @@ mod bench {
         b.iter(|| statsd.time_interval_ms("barry", 44));
     }
 
+    #[bench]
+    fn time_bench_formatting_only(b: &mut Bencher) {
+        let statsd = super::StatsdOutlet::outlet(super::NullSender, "a.b.c", 1.0).unwrap();
+        b.iter(|| statsd.time_interval_ms("barry", 44));
+    }
+
+    #[bench]
+    fn time_bench_formatting_only_tagged(b: &mut Bencher) {
+        let statsd = super::StatsdOutlet::outlet(super::NullSender, "a.b.c", 1.0).unwrap();
+        b.iter(|| statsd.count_with_unit("barry", 44, "bytes"));
+    }
+
+    #[bench]
+    fn time_bench_full_sampling_batched(b: &mut Bencher) {
+        let statsd = super::StatsdClient::new("localhost:8125", "a.b.c", 1.0).unwrap().with_batching();
+        b.iter(|| statsd.time_interval_ms("barry", 44));
+    }
+
     #[bench]
     fn time_bench_never(b: &mut Bencher) {
         let statsd = super::StatsdClient::new("localhost:8125", "a.b.c", 0.0).unwrap();
         b.iter(|| statsd.time_interval_ms("barry", 44));
     }
 
+    #[bench]
+    fn time_bench_shared_socket_four_threads(b: &mut Bencher) {
+        let statsd = super::StatsdClient::new("localhost:8125", "a.b.c", 1.0).unwrap();
+        bench_four_workers(b, || statsd.time_interval_ms("barry", 44));
+    }
+
+    #[bench]
+    fn time_bench_thread_local_four_threads(b: &mut Bencher) {
+        let statsd = super::ThreadLocalClient::new("localhost:8125", "a.b.c", 1.0).unwrap();
+        bench_four_workers(b, || statsd.time_interval_ms("barry", 44));
+    }
+
 }
\ No newline at end of file
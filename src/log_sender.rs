@@ -0,0 +1,44 @@
+/// A sender writing metric lines as log lines, to see what a client sends when diagnosing missing metrics.
+use std::io::{self, Result, Stderr, Write};
+use std::sync::Mutex;
+use SendStats;
+
+/// Writes every metric line as `target: line` to a writer, standard error by default.
+/// Batched packets are split so that each metric gets its own log line.
+pub struct LogSender<W: Write = Stderr> {
+    target: String,
+    out: Mutex<W>,
+}
+
+impl LogSender<Stderr> {
+    /// Log metrics to standard error, labeled with `target`.
+    pub fn stderr(target: &str) -> LogSender<Stderr> {
+        LogSender::new(io::stderr(), target)
+    }
+}
+
+impl<W: Write> LogSender<W> {
+    /// Log metrics to `out`, labeled with `target`.
+    pub fn new(out: W, target: &str) -> LogSender<W> {
+        LogSender { target: target.to_string(), out: Mutex::new(out) }
+    }
+
+    /// The writer, e.g. to inspect a captured buffer.
+    pub fn into_inner(self) -> W {
+        self.out.into_inner().unwrap()
+    }
+}
+
+impl<W: Write> SendStats for LogSender<W> {
+    fn send_stats(&self, str: String) -> Result<()> {
+        self.send_stats_str(&str)
+    }
+
+    fn send_stats_str(&self, str: &str) -> Result<()> {
+        let mut out = self.out.lock().unwrap();
+        for line in str.split('\n') {
+            writeln!(out, "{}: {}", self.target, line)?;
+        }
+        out.flush()
+    }
+}
@@ -0,0 +1,166 @@
+/// Metrics as values, decoupling what is sent from how it is encoded.
+use format::write_metric_separated;
+use std::io::{Error, ErrorKind, Result};
+use MetricValue;
+
+/// A single metric measurement.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Metric<'a, V: MetricValue> {
+    /// A number of items, summed by the server.
+    Count { key: &'a str, value: V },
+    /// An instant value, the server keeping the latest.
+    Gauge { key: &'a str, value: V },
+    /// A time interval in milliseconds.
+    Timing { key: &'a str, value: V },
+    /// A value aggregated into a distribution per host.
+    Histogram { key: &'a str, value: V },
+    /// A value aggregated into a distribution across all hosts (DogStatsD).
+    Distribution { key: &'a str, value: V },
+}
+
+impl<'a, V: MetricValue> Metric<'a, V> {
+    /// The key of the metric.
+    pub fn key(&self) -> &'a str {
+        match *self {
+            Metric::Count { key, .. } | Metric::Gauge { key, .. } | Metric::Timing { key, .. }
+            | Metric::Histogram { key, .. } | Metric::Distribution { key, .. } => key,
+        }
+    }
+
+    /// The value of the metric.
+    pub fn value(&self) -> &V {
+        match *self {
+            Metric::Count { ref value, .. } | Metric::Gauge { ref value, .. } | Metric::Timing { ref value, .. }
+            | Metric::Histogram { ref value, .. } | Metric::Distribution { ref value, .. } => value,
+        }
+    }
+
+    /// The statsd type of the metric, e.g. `c` for a count.
+    pub(crate) fn type_code(&self) -> &'static str {
+        match *self {
+            Metric::Count { .. } => "c",
+            Metric::Gauge { .. } => "g",
+            Metric::Timing { .. } => "ms",
+            Metric::Histogram { .. } => "h",
+            Metric::Distribution { .. } => "d",
+        }
+    }
+
+    /// The same metric under another key.
+    pub fn with_key<'b>(self, key: &'b str) -> Metric<'b, V> where 'a: 'b {
+        match self {
+            Metric::Count { value, .. } => Metric::Count { key, value },
+            Metric::Gauge { value, .. } => Metric::Gauge { key, value },
+            Metric::Timing { value, .. } => Metric::Timing { key, value },
+            Metric::Histogram { value, .. } => Metric::Histogram { key, value },
+            Metric::Distribution { value, .. } => Metric::Distribution { key, value },
+        }
+    }
+}
+
+/// The type and sampling rate suffixes appended to statsd lines for each type of metric.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Suffixes {
+    pub(crate) count: String,
+    pub(crate) gauge: String,
+    pub(crate) timing: String,
+    pub(crate) histogram: String,
+    pub(crate) distribution: String,
+    pub(crate) set: String,
+    // the sampling rate alone, empty at full sampling
+    pub(crate) rate: String,
+}
+
+impl Suffixes {
+    /// Suffixes for metrics sampled at `float_rate`, which is only written if less than 1.0.
+    pub fn new(float_rate: f64) -> Suffixes {
+        let rate_suffix = if float_rate < 1.0 { format!("|@{}", float_rate)} else { "".to_string() };
+        Suffixes {
+            count: format!("|c{}", rate_suffix),
+            gauge: format!("|g{}", rate_suffix),
+            timing: format!("|ms{}", rate_suffix),
+            histogram: format!("|h{}", rate_suffix),
+            distribution: format!("|d{}", rate_suffix),
+            set: format!("|s{}", rate_suffix),
+            rate: rate_suffix,
+        }
+    }
+
+    /// The suffix for `metric`.
+    pub fn of<V: MetricValue>(&self, metric: &Metric<V>) -> &str {
+        match *metric {
+            Metric::Count { .. } => &self.count,
+            Metric::Gauge { .. } => &self.gauge,
+            Metric::Timing { .. } => &self.timing,
+            Metric::Histogram { .. } => &self.histogram,
+            Metric::Distribution { .. } => &self.distribution,
+        }
+    }
+}
+
+/// The statsd line for `metric`, see `encode_into`.
+pub fn encode<V: MetricValue>(metric: &Metric<V>, prefix: &str, suffixes: &Suffixes) -> String {
+    let mut line = String::new();
+    encode_into(&mut line, metric, prefix, suffixes);
+    line
+}
+
+/// Append the statsd line for `metric` to `buf`, its key prepended with `prefix` and followed by the suffix for its type.
+pub fn encode_into<V: MetricValue>(buf: &mut String, metric: &Metric<V>, prefix: &str, suffixes: &Suffixes) {
+    encode_separated_into(buf, metric, prefix, ":", suffixes)
+}
+
+/// Like `encode_into`, with `separator` between the key and the value instead of `:`.
+pub(crate) fn encode_separated_into<V: MetricValue>(buf: &mut String, metric: &Metric<V>, prefix: &str, separator: &str, suffixes: &Suffixes) {
+    write_metric_separated(buf, prefix, metric.key(), separator, metric.value(), suffixes.of(metric))
+}
+
+/// The line format of sent metrics, see `StatsdOutlet::with_encoding`.
+/// Formats other than statsd each require their cargo feature, `graphite` or `influx`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Encoding {
+    /// statsd lines, `key:value|type|@rate|#tags`.
+    #[default]
+    Statsd,
+    /// Graphite plaintext lines, `path value timestamp`, see `encode_graphite_into`.
+    #[cfg(feature = "graphite")]
+    Graphite,
+    /// InfluxDB line protocol, `measurement,tag=value value=<value> timestamp`, see `encode_influx_into`.
+    #[cfg(feature = "influx")]
+    Influx,
+}
+
+/// A part of a statsd line, see `StatsdOutlet::with_segment_order`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Segment {
+    /// The client's prefix.
+    Prefix,
+    /// The metric key.
+    Key,
+    /// The separator (`:` unless configured) followed by the value.
+    Value,
+    /// The type, e.g. `|c`.
+    TypeSuffix,
+    /// The sampling rate, e.g. `|@0.5`, absent at full sampling.
+    RateSuffix,
+    /// The default and per-call tags, e.g. `|#env:prod`, absent without tags.
+    Tags,
+}
+
+/// The standard statsd order, `<prefix><key>:<value>|<type>|@<rate>|#<tags>`.
+pub const STATSD_ORDER: [Segment; 6] = [Segment::Prefix, Segment::Key, Segment::Value, Segment::TypeSuffix, Segment::RateSuffix, Segment::Tags];
+
+/// Check that `order` holds every segment exactly once, with the prefix and key (in any order) first
+/// and the value right after them, so that servers can still split the name from the value.
+/// The `|`-delimited type, rate and tags may then come in any order.
+pub(crate) fn check_order(order: &[Segment]) -> Result<()> {
+    let valid = order.len() == STATSD_ORDER.len()
+        && STATSD_ORDER.iter().all(|segment| order.contains(segment))
+        && order[..2].contains(&Segment::Prefix) && order[..2].contains(&Segment::Key)
+        && order[2] == Segment::Value;
+    if valid {
+        Ok(())
+    } else {
+        Err(Error::new(ErrorKind::InvalidInput, format!("invalid statsd segment order {:?}", order)))
+    }
+}
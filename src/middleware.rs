@@ -0,0 +1,57 @@
+/// Composition of cross-cutting policies (tagging, redaction, rate limiting...) over any sender.
+use std::io::Result;
+use SendStats;
+
+/// A step on the emit path, seeing every metric line before the sender it wraps.
+pub trait Middleware {
+    /// Return the line to forward, possibly modified, or `None` to drop it.
+    fn process(&self, line: String) -> Option<String>;
+}
+
+impl<F: Fn(String) -> Option<String>> Middleware for F {
+    fn process(&self, line: String) -> Option<String> {
+        self(line)
+    }
+}
+
+/// A sender passing lines through `middleware` before handing them to `inner`.
+/// Packets of batched lines are split so that the middleware sees one line at a time, the lines it keeps
+/// going on as a single packet. Layers stack by nesting, the outermost layer sees lines first:
+/// `Layered::new(redact, Layered::new(limit, socket))`.
+pub struct Layered<M, S> {
+    middleware: M,
+    inner: S,
+}
+
+impl<M: Middleware, S: SendStats> Layered<M, S> {
+    pub fn new(middleware: M, inner: S) -> Layered<M, S> {
+        Layered { middleware, inner }
+    }
+
+    /// The wrapped sender.
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+impl<M: Middleware, S: SendStats> SendStats for Layered<M, S> {
+    fn send_stats(&self, str: String) -> Result<()> {
+        if !str.contains('\n') {
+            return match self.middleware.process(str) {
+                Some(line) => self.inner.send_stats(line),
+                None => Ok(()),
+            }
+        }
+        let mut packet = String::with_capacity(str.len());
+        for line in str.split('\n') {
+            if let Some(line) = self.middleware.process(line.to_string()) {
+                if !packet.is_empty() { packet.push('\n') }
+                packet.push_str(&line);
+            }
+        }
+        if packet.is_empty() {
+            return Ok(())
+        }
+        self.inner.send_stats(packet)
+    }
+}
@@ -0,0 +1,45 @@
+/// Senders discarding metrics, for builds or configurations where metrics are irrelevant.
+use std::io::Result;
+use {SendStats, StatsdClient, StatsdOutlet};
+
+/// Discards every metric. Metrics are still sampled and formatted, but never leave the process.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NullSender;
+
+impl SendStats for NullSender {
+    fn send_stats(&self, _str: String) -> Result<()> {
+        Ok(())
+    }
+
+    fn send_stats_str(&self, _str: &str) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Send through the sender if there is one, discard metrics otherwise.
+/// This lets a single `StatsdOutlet<Option<S>>` variable hold either an enabled or a disabled client.
+/// Discarded metrics still count as sent in `stats()`.
+impl<S: SendStats> SendStats for Option<S> {
+    fn send_stats(&self, str: String) -> Result<()> {
+        match *self {
+            Some(ref sender) => sender.send_stats(str),
+            None => Ok(()),
+        }
+    }
+
+    fn send_stats_str(&self, str: &str) -> Result<()> {
+        match *self {
+            Some(ref sender) => sender.send_stats_str(str),
+            None => Ok(()),
+        }
+    }
+}
+
+impl StatsdClient {
+    /// Create a client discarding every metric, without opening any socket.
+    /// Its sampling rate is 0 so that metrics are rejected before being formatted.
+    /// Use a `StatsdOutlet<Option<UdpSocket>>` instead to decide at runtime whether metrics are sent.
+    pub fn disabled() -> StatsdOutlet<NullSender> {
+        StatsdOutlet::outlet(NullSender, "", 0.0).expect("0 is a valid rate")
+    }
+}
@@ -0,0 +1,193 @@
+/// Export to an OpenTelemetry collector using OTLP/HTTP with JSON encoding.
+/// Counts map to delta sums, not monotonic since statsd counts can be decremented, gauges to gauges
+/// and timings (or histograms and distributions) to single-point delta histograms.
+/// Relative `+`/`-` gauges are applied to the last value of the gauge, starting from 0.
+/// Only plain `http://` endpoints are supported.
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::io::Result;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use http_export::{parse_endpoint, parse_line, request};
+use {SendStats, StatsdOutlet};
+
+/// OTLP `AGGREGATION_TEMPORALITY_DELTA`
+const DELTA: u8 = 1;
+
+/// Time allowed to connect to the collector, then for each read and write of a post.
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+
+enum Kind {
+    Sum,
+    Gauge,
+    Histogram,
+    Timing,
+}
+
+struct Point {
+    name: String,
+    kind: Kind,
+    value: f64,
+    time_ns: u128,
+}
+
+/// A sink accumulating metrics and posting them to an OTLP/HTTP collector
+/// every `batch_size` metrics, on `push()` and when dropped.
+/// Sampled counts are scaled back by their rate since OTLP has no notion of sampling.
+/// Metric types without an OTLP equivalent are ignored.
+pub struct OtlpSink {
+    host: String,
+    path: String,
+    batch_size: usize,
+    timeout: Duration,
+    pending: Mutex<Vec<Point>>,
+    gauges: Mutex<HashMap<String, f64>>,
+    errors: AtomicU64,
+}
+
+impl OtlpSink {
+    /// Post metrics to `endpoint`, e.g. `http://localhost:4318/v1/metrics`.
+    /// The path defaults to `/v1/metrics` if omitted.
+    pub fn new(endpoint: &str, batch_size: usize) -> Result<OtlpSink> {
+        let (host, path) = parse_endpoint(endpoint, "OTLP", "/v1/metrics")?;
+        Ok(OtlpSink {
+            host,
+            path,
+            batch_size: batch_size.max(1),
+            timeout: DEFAULT_TIMEOUT,
+            pending: Mutex::new(Vec::new()),
+            gauges: Mutex::new(HashMap::new()),
+            errors: AtomicU64::new(0),
+        })
+    }
+
+    /// Bound connecting to the collector and each read or write of a post by `timeout`, 5 seconds by default.
+    pub fn timeout(mut self, timeout: Duration) -> OtlpSink {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Number of batches that could not be delivered.
+    pub fn errors(&self) -> u64 {
+        self.errors.load(Ordering::Relaxed)
+    }
+
+    /// Post all pending metrics to the collector. Nothing is posted if there are none.
+    /// Pending metrics are discarded even if delivery fails.
+    pub fn push(&self) -> Result<()> {
+        let points = ::std::mem::take(&mut *self.pending.lock().unwrap());
+        if points.is_empty() { return Ok(()) }
+        let result = request("POST", &self.host, &self.path, "application/json", &encode(&points), self.timeout, "OTLP collector");
+        if result.is_err() {
+            self.errors.fetch_add(1, Ordering::Relaxed);
+        }
+        result
+    }
+
+}
+
+impl SendStats for OtlpSink {
+    fn send_stats(&self, str: String) -> Result<()> {
+        let time_ns = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos()).unwrap_or(0);
+        let full = {
+            let mut pending = self.pending.lock().unwrap();
+            let mut gauges = self.gauges.lock().unwrap();
+            for line in str.lines() {
+                parse(line, time_ns, &mut pending, &mut gauges);
+            }
+            pending.len() >= self.batch_size
+        };
+        if full { self.push() } else { Ok(()) }
+    }
+}
+
+impl Drop for OtlpSink {
+    fn drop(&mut self) {
+        let _ = self.push();
+    }
+}
+
+impl StatsdOutlet<OtlpSink> {
+    /// Create a client exporting to the OTLP/HTTP `endpoint`, posting every `batch_size` metrics.
+    pub fn otlp(endpoint: &str, prefix_str: &str, float_rate: f64, batch_size: usize) -> Result<StatsdOutlet<OtlpSink>> {
+        StatsdOutlet::outlet(OtlpSink::new(endpoint, batch_size)?, prefix_str, float_rate)
+    }
+
+    /// Post all pending metrics to the collector.
+    pub fn push(&self) -> Result<()> {
+        self.sender().push()
+    }
+}
+
+/// Read the statsd `line` (possibly holding many `:`-separated values) into points,
+/// keeping the last value of every gauge in `gauges` to apply relative gauges to.
+fn parse(line: &str, time_ns: u128, points: &mut Vec<Point>, gauges: &mut HashMap<String, f64>) {
+    let (name, values) = match parse_line(line) {
+        Some(parsed) => parsed,
+        None => return,
+    };
+    for value in values {
+        let (kind, value) = match value.kind {
+            "c" => (Kind::Sum, value.unsampled()),
+            "g" => {
+                let last = gauges.entry(name.to_string()).or_insert(0.0);
+                if value.relative { *last += value.value } else { *last = value.value }
+                (Kind::Gauge, *last)
+            }
+            "ms" => (Kind::Timing, value.value),
+            "h" | "d" => (Kind::Histogram, value.value),
+            _ => continue,
+        };
+        points.push(Point { name: name.to_string(), kind, value, time_ns });
+    }
+}
+
+fn encode(points: &[Point]) -> String {
+    let mut json = String::from(r#"{"resourceMetrics":[{"resource":{},"scopeMetrics":[{"scope":{"name":"statsd_client"},"metrics":["#);
+    for (i, point) in points.iter().enumerate() {
+        if i > 0 { json.push(',') }
+        json.push_str(r#"{"name":"#);
+        push_json_str(&mut json, &point.name);
+        let _ = match point.kind {
+            Kind::Sum => write!(json,
+                r#","sum":{{"aggregationTemporality":{},"isMonotonic":false,"dataPoints":[{{{},"timeUnixNano":"{}"}}]}}}}"#,
+                DELTA, NumberValue(point.value), point.time_ns),
+            Kind::Gauge => write!(json,
+                r#","gauge":{{"dataPoints":[{{{},"timeUnixNano":"{}"}}]}}}}"#,
+                NumberValue(point.value), point.time_ns),
+            Kind::Histogram | Kind::Timing => write!(json,
+                r#"{}"histogram":{{"aggregationTemporality":{},"dataPoints":[{{"count":"1","sum":{},"min":{},"max":{},"bucketCounts":["1"],"explicitBounds":[],"timeUnixNano":"{}"}}]}}}}"#,
+                if let Kind::Timing = point.kind { r#","unit":"ms","# } else { "," },
+                DELTA, point.value, point.value, point.value, point.time_ns),
+        };
+    }
+    json.push_str("]}]}]}");
+    json
+}
+
+/// The value of a number data point, `asInt` if it is an integer `i64` can hold, `asDouble` otherwise.
+struct NumberValue(f64);
+
+impl ::std::fmt::Display for NumberValue {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        if self.0.fract() == 0.0 && self.0.abs() < i64::MAX as f64 {
+            write!(f, r#""asInt":"{}""#, self.0 as i64)
+        } else {
+            write!(f, r#""asDouble":{}"#, self.0)
+        }
+    }
+}
+
+fn push_json_str(json: &mut String, str: &str) {
+    json.push('"');
+    for c in str.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            c if (c as u32) < 0x20 => json.push_str(&format!("\\u{:04x}", c as u32)),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+}
@@ -0,0 +1,39 @@
+/// Opt-in reporting of process panics as a counter.
+use std::panic;
+use std::sync::Arc;
+use {Metric, SendStats, StatsdOutlet};
+
+/// Install a panic hook counting every panic under `key` through `client` before running the previous hook.
+/// The count is handed straight to the sender, bypassing batching and aggregation, then pending metrics
+/// are flushed since the process may not live to do it. The flush is skipped if the batch, aggregated
+/// counts or retry queue are locked, e.g. by the panicking thread itself.
+/// Reporting is best-effort: sampling still applies, and a sender panicking from within the hook
+/// would abort the process, so only use senders that can not panic.
+pub fn install_panic_hook<S: SendStats + Send + Sync + 'static>(client: Arc<StatsdOutlet<S>>, key: &str) {
+    let key = key.to_string();
+    let previous = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        client.count_panic(&key);
+        previous(info);
+    }));
+}
+
+impl<S: SendStats> StatsdOutlet<S> {
+
+    /// Send a count of 1 under `key` right away, then flush unless that could block on a lock.
+    fn count_panic(&self, key: &str) {
+        if self.sample(key) {
+            let mut line = String::new();
+            self.encode_line(&mut line, Metric::Count { key, value: 1 }, &[]);
+            let result = self.sender().send_stats_str(&line);
+            self.count_delivery(&line, &result);
+        }
+        let unlocked = self.batch.as_ref().is_none_or(|batch| batch.try_lock().is_ok())
+            && self.aggregation.as_ref().is_none_or(|aggregation| aggregation.is_unlocked())
+            && self.retry.as_ref().is_none_or(|retry| retry.is_unlocked());
+        // skipped whenever a lock is held, as waiting on one held by this very thread would never end
+        if unlocked {
+            self.flush();
+        }
+    }
+}
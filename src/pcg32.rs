@@ -1,29 +1,95 @@
 /// PCG32 random number generation for fast sampling
 // TODO use https://github.com/codahale/pcg instead?
 use std::cell::RefCell;
-use time;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::process;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
 
-fn seed() -> u64 {
-    let seed = 5573589319906701683_u64;
+/// Distinguishes generators seeded within the same clock tick on the same thread.
+static SEED_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Bumped in the child process after every fork, so that generators inherited from the parent get reseeded.
+static FORK_GENERATION: AtomicU64 = AtomicU64::new(0);
+
+/// Mix the wall clock, the process and current thread's IDs and a process-wide counter
+/// so that threads (or forked processes) starting simultaneously do not share a seed.
+pub fn seed() -> u64 {
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos() as u64).unwrap_or(0);
+    let mut hasher = DefaultHasher::new();
+    process::id().hash(&mut hasher);
+    thread::current().id().hash(&mut hasher);
+    SEED_COUNTER.fetch_add(1, Ordering::Relaxed).hash(&mut hasher);
+    let seed = 5573589319906701683_u64 ^ hasher.finish();
     let seed = seed.wrapping_mul(6364136223846793005)
         .wrapping_add(1442695040888963407)
-        .wrapping_add(time::precise_time_ns());
+        .wrapping_add(nanos);
     seed.wrapping_mul(6364136223846793005)
         .wrapping_add(1442695040888963407)
 }
 
-pub fn random() -> u32 {
-    thread_local! {
-        static PCG32_STATE: RefCell<u64> = RefCell::new(seed());
+/// A single PCG32 stream.
+pub struct Pcg32 {
+    state: u64,
+}
+
+impl Pcg32 {
+    pub fn new(seed: u64) -> Pcg32 {
+        Pcg32 { state: seed }
     }
 
-    PCG32_STATE.with(|state| {
-        let oldstate: u64 = *state.borrow();
+    pub fn next_u32(&mut self) -> u32 {
+        let oldstate = self.state;
         // XXX could generate the increment from the thread ID
-        *state.borrow_mut() = oldstate.wrapping_mul(6364136223846793005)
+        self.state = oldstate.wrapping_mul(6364136223846793005)
             .wrapping_add(1442695040888963407);
         ((((oldstate >> 18) ^ oldstate) >> 27) as u32)
             .rotate_right((oldstate >> 59) as u32)
+    }
+}
+
+/// Register a `fork()` handler bumping `FORK_GENERATION` in the child, once per process.
+#[cfg(unix)]
+fn watch_forks() {
+    use std::sync::Once;
+    unsafe extern "C" fn forked() {
+        FORK_GENERATION.fetch_add(1, Ordering::Relaxed);
+    }
+    static WATCH: Once = Once::new();
+    WATCH.call_once(|| unsafe { ::libc::pthread_atfork(None, None, Some(forked)); });
+}
+
+#[cfg(not(unix))]
+fn watch_forks() {}
+
+/// A generator for the current thread, reseeded if the process forked since it was created
+/// so that children of a pre-fork server do not sample in lockstep.
+pub fn random() -> u32 {
+    thread_local! {
+        static PCG32_STATE: RefCell<(Pcg32, u64)> = {
+            watch_forks();
+            RefCell::new((Pcg32::new(seed()), FORK_GENERATION.load(Ordering::Relaxed)))
+        };
+    }
+
+    PCG32_STATE.with(|pcg| {
+        let mut pcg = pcg.borrow_mut();
+        let generation = FORK_GENERATION.load(Ordering::Relaxed);
+        if pcg.1 != generation {
+            *pcg = (Pcg32::new(seed()), generation);
+        }
+        pcg.0.next_u32()
     })
 }
 
+/// Like `random`, from another generator for the current thread which is never reseeded:
+/// processes forked after it was created go on with the same stream as their parent.
+pub fn random_inherited() -> u32 {
+    thread_local! {
+        static PCG32_STATE: RefCell<Pcg32> = RefCell::new(Pcg32::new(seed()));
+    }
+
+    PCG32_STATE.with(|pcg| pcg.borrow_mut().next_u32())
+}
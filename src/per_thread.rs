@@ -0,0 +1,158 @@
+/// A client handing each thread its own UDP socket, for thread-per-core servers.
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::io::Result;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use {SenderStats, StatsdClient, StartTime};
+
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    static OUTLETS: RefCell<HashMap<usize, Outlet>> = RefCell::new(HashMap::new());
+}
+
+/// The outlets of a `ThreadLocalClient` in use by live threads, and the counters of those whose thread exited.
+#[derive(Default)]
+struct Outlets {
+    live: Vec<Arc<StatsdClient>>,
+    retired: SenderStats,
+}
+
+/// A thread's outlet, registered with its `ThreadLocalClient` until the thread exits.
+struct Outlet {
+    client: Arc<StatsdClient>,
+    outlets: Arc<Mutex<Outlets>>,
+}
+
+impl Outlet {
+    fn register(client: StatsdClient, outlets: &Arc<Mutex<Outlets>>) -> Outlet {
+        let client = Arc::new(client);
+        outlets.lock().unwrap().live.push(client.clone());
+        Outlet { client, outlets: outlets.clone() }
+    }
+}
+
+impl Drop for Outlet {
+    fn drop(&mut self) {
+        // a poisoned lock only loses the counters of this thread
+        if let Ok(mut outlets) = self.outlets.lock() {
+            outlets.live.retain(|client| !Arc::ptr_eq(client, &self.client));
+            let stats = self.client.stats();
+            add_stats(&mut outlets.retired, &stats);
+        }
+    }
+}
+
+fn add_stats(total: &mut SenderStats, stats: &SenderStats) {
+    total.packets += stats.packets;
+    total.bytes += stats.bytes;
+    total.errors += stats.errors;
+    total.would_block += stats.would_block;
+    total.malformed += stats.malformed;
+    total.overflows += stats.overflows;
+}
+
+/// A single logical client lazily creating a `StatsdClient` (and socket) for every thread using it.
+/// All sockets target the same address, resolved once, and share the same prefix and sampling rate.
+/// Per-thread outlets live as long as their thread, even if the `ThreadLocalClient` is dropped first.
+pub struct ThreadLocalClient {
+    id: usize,
+    targets: Vec<SocketAddr>,
+    prefix: String,
+    float_rate: f64,
+    sockets: AtomicU64,
+    socket_errors: AtomicU64,
+    outlets: Arc<Mutex<Outlets>>,
+}
+
+/// Counters aggregated across all threads using a `ThreadLocalClient`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ThreadLocalStats {
+    /// Number of per-thread sockets opened.
+    pub sockets: u64,
+    /// Number of failed attempts at opening a per-thread socket. Metrics are dropped when this happens.
+    pub socket_errors: u64,
+    /// Counters of all per-thread clients summed, including those of threads that exited.
+    pub sent: SenderStats,
+}
+
+impl ThreadLocalClient {
+    /// Create a new `ThreadLocalClient` sending packets to the specified `address`.
+    /// Arguments are the same as `StatsdClient::new`.
+    /// The calling thread's socket is opened right away so configuration errors are reported early.
+    pub fn new<A: ToSocketAddrs>(address: A, prefix_str: &str, float_rate: f64) -> Result<ThreadLocalClient> {
+        let client = ThreadLocalClient {
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            targets: address.to_socket_addrs()?.collect(),
+            prefix: prefix_str.to_string(),
+            float_rate,
+            sockets: AtomicU64::new(0),
+            socket_errors: AtomicU64::new(0),
+            outlets: Arc::new(Mutex::new(Outlets::default())),
+        };
+        let outlet = Outlet::register(StatsdClient::new(&client.targets[..], prefix_str, float_rate)?, &client.outlets);
+        client.sockets.fetch_add(1, Ordering::Relaxed);
+        OUTLETS.with(|outlets| outlets.borrow_mut().insert(client.id, outlet));
+        Ok(client)
+    }
+
+    /// Counters aggregated across all threads.
+    pub fn stats(&self) -> ThreadLocalStats {
+        let outlets = self.outlets.lock().unwrap();
+        let mut sent = outlets.retired;
+        for client in &outlets.live {
+            add_stats(&mut sent, &client.stats());
+        }
+        ThreadLocalStats {
+            sockets: self.sockets.load(Ordering::Relaxed),
+            socket_errors: self.socket_errors.load(Ordering::Relaxed),
+            sent,
+        }
+    }
+
+    /// Run `f` with the current thread's outlet, creating it if required.
+    fn with<F: FnOnce(&StatsdClient)>(&self, f: F) {
+        OUTLETS.with(|outlets| {
+            match outlets.borrow_mut().entry(self.id) {
+                Entry::Occupied(outlet) => f(&outlet.get().client),
+                Entry::Vacant(vacant) => match StatsdClient::new(&self.targets[..], &self.prefix, self.float_rate) {
+                    Ok(outlet) => {
+                        self.sockets.fetch_add(1, Ordering::Relaxed);
+                        f(&vacant.insert(Outlet::register(outlet, &self.outlets)).client)
+                    }
+                    Err(_) => {
+                        self.socket_errors.fetch_add(1, Ordering::Relaxed);
+                    }
+                }
+            }
+        })
+    }
+
+    /// Report to statsd a count of items.
+    pub fn count(&self, key: &str, value: u64) {
+        self.with(|outlet| outlet.count(key, value))
+    }
+
+    /// Report to statsd a non-cumulative (instant) count of items.
+    pub fn gauge(&self, key: &str, value: u64) {
+        self.with(|outlet| outlet.gauge(key, value))
+    }
+
+    /// Report to statsd a time interval of items.
+    pub fn time_interval_ms(&self, key: &str, interval_ms: u64) {
+        self.with(|outlet| outlet.time_interval_ms(key, interval_ms))
+    }
+
+    /// Query current time to use eventually with `stop_time()`
+    pub fn start_time(&self) -> StartTime {
+        StartTime::now()
+    }
+
+    /// An efficient timer that skips querying for stop time if sample will not be collected.
+    pub fn stop_time(&self, key: &str, start_time: StartTime) {
+        self.with(|outlet| outlet.stop_time(key, start_time))
+    }
+}
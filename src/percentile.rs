@@ -0,0 +1,64 @@
+/// Client-side percentiles for servers unable to compute them.
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind, Result};
+use std::sync::Mutex;
+use {pcg32, SendStats, StatsdOutlet};
+
+/// Collects values per key over a window and reports the configured percentiles as gauges (`key.p95`) on flush.
+/// Each key keeps a uniform reservoir sample of at most `capacity` values, percentiles are exact below that.
+pub struct PercentileTracker {
+    percentiles: Vec<u8>,
+    capacity: usize,
+    reservoirs: Mutex<HashMap<String, Reservoir>>,
+}
+
+struct Reservoir {
+    seen: u64,
+    samples: Vec<u64>,
+}
+
+impl PercentileTracker {
+    /// Track the given `percentiles` (between 1 and 100) keeping up to `capacity` values per key.
+    /// Returns an `InvalidInput` error for a percentile out of range or a zero capacity.
+    pub fn new(percentiles: &[u8], capacity: usize) -> Result<PercentileTracker> {
+        if let Some(p) = percentiles.iter().find(|p| !(1..=100).contains(*p)) {
+            return Err(Error::new(ErrorKind::InvalidInput, format!("percentile {} is not within 1 to 100", p)))
+        }
+        if capacity == 0 {
+            return Err(Error::new(ErrorKind::InvalidInput, "percentile capacity is zero"))
+        }
+        Ok(PercentileTracker { percentiles: percentiles.to_vec(), capacity, reservoirs: Mutex::new(HashMap::new()) })
+    }
+
+    /// Record a value for `key`.
+    pub fn observe(&self, key: &str, value: u64) {
+        let mut reservoirs = self.reservoirs.lock().unwrap();
+        if !reservoirs.contains_key(key) {
+            reservoirs.insert(key.to_string(), Reservoir { seen: 0, samples: Vec::new() });
+        }
+        let reservoir = reservoirs.get_mut(key).unwrap();
+        reservoir.seen += 1;
+        if reservoir.samples.len() < self.capacity {
+            reservoir.samples.push(value);
+        } else {
+            let slot = ((u64::from(pcg32::random()) << 32 | u64::from(pcg32::random())) % reservoir.seen) as usize;
+            if slot < self.capacity {
+                reservoir.samples[slot] = value;
+            }
+        }
+    }
+
+    /// Report the percentiles of every key observed since the last flush, then start a new window.
+    /// Percentiles use the nearest-rank method.
+    pub fn flush<S: SendStats>(&self, outlet: &StatsdOutlet<S>) {
+        let reservoirs = ::std::mem::take(&mut *self.reservoirs.lock().unwrap());
+        for (key, mut reservoir) in reservoirs {
+            reservoir.samples.sort_unstable();
+            let len = reservoir.samples.len();
+            for p in &self.percentiles {
+                let rank = (usize::from(*p) * len).div_ceil(100);
+                outlet.gauge(&format!("{}.p{}", key, p), reservoir.samples[rank.max(1) - 1]);
+            }
+        }
+    }
+}
@@ -0,0 +1,158 @@
+/// Export to a Prometheus pushgateway using the text exposition format.
+/// Counts map to counters, gauges to gauges and timings (or histograms and distributions) to summaries without quantiles.
+/// Only plain `http://` endpoints are supported.
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::io::Result;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+use http_export::{parse_endpoint, parse_line, request};
+use {SendStats, StatsdOutlet};
+
+/// Time allowed to connect to the pushgateway, then for each read and write of a push.
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+
+enum Series {
+    Counter(f64),
+    Gauge(f64),
+    Summary { sum: f64, count: u64 },
+}
+
+/// A sink accumulating metrics and pushing all of them to a pushgateway on `push()` and when dropped.
+/// Since the pushgateway replaces the metrics of a job on every push, counters and summaries
+/// accumulate for the life of the sink rather than being reset after each push.
+/// Sampled counts are scaled back by their rate, metric names have characters not allowed by Prometheus replaced by `_`.
+/// Negative counts are ignored since Prometheus counters cannot decrease, relative `+`/`-` gauges are applied to the last value.
+/// Metric types without a Prometheus equivalent are ignored.
+pub struct PushgatewaySink {
+    host: String,
+    path: String,
+    timeout: Duration,
+    series: Mutex<BTreeMap<String, Series>>,
+    errors: AtomicU64,
+}
+
+impl PushgatewaySink {
+    /// Push metrics under `job` to the pushgateway at `endpoint`, e.g. `http://localhost:9091`.
+    /// The job name is percent-encoded in the push URL, so it may hold any character.
+    pub fn new(endpoint: &str, job: &str) -> Result<PushgatewaySink> {
+        let (host, base) = parse_endpoint(endpoint, "pushgateway", "")?;
+        Ok(PushgatewaySink {
+            host,
+            path: format!("{}/metrics/job/{}", base.trim_end_matches('/'), percent_encoded(job)),
+            timeout: DEFAULT_TIMEOUT,
+            series: Mutex::new(BTreeMap::new()),
+            errors: AtomicU64::new(0),
+        })
+    }
+
+    /// Bound connecting to the pushgateway and each read or write of a push by `timeout`, 5 seconds by default.
+    pub fn timeout(mut self, timeout: Duration) -> PushgatewaySink {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Number of pushes that could not be delivered.
+    pub fn errors(&self) -> u64 {
+        self.errors.load(Ordering::Relaxed)
+    }
+
+    /// Push all metrics to the pushgateway. Nothing is pushed if no metric was ever received.
+    pub fn push(&self) -> Result<()> {
+        let body = {
+            let series = self.series.lock().unwrap();
+            if series.is_empty() { return Ok(()) }
+            expose(&series)
+        };
+        let result = request("PUT", &self.host, &self.path, "text/plain; version=0.0.4", &body, self.timeout, "pushgateway");
+        if result.is_err() {
+            self.errors.fetch_add(1, Ordering::Relaxed);
+        }
+        result
+    }
+
+}
+
+impl SendStats for PushgatewaySink {
+    fn send_stats(&self, str: String) -> Result<()> {
+        let mut series = self.series.lock().unwrap();
+        for line in str.lines() {
+            record(line, &mut series);
+        }
+        Ok(())
+    }
+}
+
+impl Drop for PushgatewaySink {
+    fn drop(&mut self) {
+        let _ = self.push();
+    }
+}
+
+impl StatsdOutlet<PushgatewaySink> {
+    /// Create a client pushing metrics under `job` to the pushgateway at `endpoint`.
+    pub fn prometheus(endpoint: &str, job: &str, prefix_str: &str, float_rate: f64) -> Result<StatsdOutlet<PushgatewaySink>> {
+        StatsdOutlet::outlet(PushgatewaySink::new(endpoint, job)?, prefix_str, float_rate)
+    }
+
+    /// Push all metrics to the pushgateway.
+    pub fn push(&self) -> Result<()> {
+        self.sender().push()
+    }
+}
+
+/// Accumulate the statsd `line` (possibly holding many `:`-separated values) into `series`.
+fn record(line: &str, series: &mut BTreeMap<String, Series>) {
+    let (name, values) = match parse_line(line) {
+        Some(parsed) => parsed,
+        None => return,
+    };
+    let name: String = name.chars().map(|c| if c.is_ascii_alphanumeric() || c == '_' || c == ':' { c } else { '_' }).collect();
+    for value in values {
+        match value.kind {
+            "c" if value.value >= 0.0 => {
+                let count = value.unsampled();
+                match series.get_mut(&name) {
+                    Some(&mut Series::Counter(ref mut total)) => *total += count,
+                    _ => { series.insert(name.clone(), Series::Counter(count)); }
+                }
+            }
+            "g" => match series.get_mut(&name) {
+                Some(&mut Series::Gauge(ref mut last)) if value.relative => *last += value.value,
+                _ => { series.insert(name.clone(), Series::Gauge(value.value)); }
+            },
+            "ms" | "h" | "d" => {
+                match series.get_mut(&name) {
+                    Some(&mut Series::Summary { ref mut sum, ref mut count }) => { *sum += value.value; *count += 1 }
+                    _ => { series.insert(name.clone(), Series::Summary { sum: value.value, count: 1 }); }
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn expose(series: &BTreeMap<String, Series>) -> String {
+    let mut text = String::new();
+    for (name, series) in series {
+        let _ = match *series {
+            Series::Counter(total) => write!(text, "# TYPE {0} counter\n{0} {1}\n", name, total),
+            Series::Gauge(value) => write!(text, "# TYPE {0} gauge\n{0} {1}\n", name, value),
+            Series::Summary { sum, count } => write!(text, "# TYPE {0} summary\n{0}_sum {1}\n{0}_count {2}\n", name, sum, count),
+        };
+    }
+    text
+}
+
+/// Escape everything but unreserved characters of `str` for use as a URL path segment.
+fn percent_encoded(str: &str) -> String {
+    let mut encoded = String::with_capacity(str.len());
+    for byte in str.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => encoded.push(byte as char),
+            _ => { let _ = write!(encoded, "%{:02X}", byte); }
+        }
+    }
+    encoded
+}
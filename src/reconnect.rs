@@ -0,0 +1,75 @@
+/// A UDP sender recreating its socket when it gets stuck in an error state.
+use std::io::{ErrorKind, Result};
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, RwLock};
+use std::time::{Duration, Instant};
+use {connect_udp_first, SendStats, StatsdOutlet};
+
+/// A connected UDP socket that is rebound and reconnected after a send error,
+/// e.g. a pending ICMP port-unreachable, at most once per `cooldown`.
+/// `WouldBlock` errors only mean the send buffer is full and never trigger a recreation.
+/// The address is resolved once, recreated sockets connect to the first of its addresses that can be.
+pub struct ReconnectingSocket {
+    targets: Vec<SocketAddr>,
+    cooldown: Duration,
+    socket: RwLock<UdpSocket>,
+    last_recreate: Mutex<Option<Instant>>,
+    recreations: AtomicU64,
+}
+
+impl ReconnectingSocket {
+    /// Connect a new socket to `address`.
+    pub fn new<A: ToSocketAddrs>(address: A, cooldown: Duration) -> Result<ReconnectingSocket> {
+        let targets: Vec<SocketAddr> = address.to_socket_addrs()?.collect();
+        Ok(ReconnectingSocket {
+            socket: RwLock::new(connect_udp_first(&targets, None)?),
+            targets,
+            cooldown,
+            last_recreate: Mutex::new(None),
+            recreations: AtomicU64::new(0),
+        })
+    }
+
+    /// Number of times the socket was recreated.
+    pub fn recreations(&self) -> u64 {
+        self.recreations.load(Ordering::Relaxed)
+    }
+
+    fn recreate(&self) {
+        let mut last = self.last_recreate.lock().unwrap();
+        let now = Instant::now();
+        if last.map(|l| now.duration_since(l) < self.cooldown).unwrap_or(false) {
+            return
+        }
+        *last = Some(now);
+        if let Ok(socket) = connect_udp_first(&self.targets, None) {
+            *self.socket.write().unwrap() = socket;
+            self.recreations.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+impl SendStats for ReconnectingSocket {
+    fn send_stats(&self, str: String) -> Result<()> {
+        let result = self.socket.read().unwrap().send(str.as_bytes());
+        match result {
+            Err(ref e) if e.kind() != ErrorKind::WouldBlock => self.recreate(),
+            _ => {}
+        }
+        result.map(|_| ())
+    }
+}
+
+impl StatsdOutlet<ReconnectingSocket> {
+    /// Create a new client like `StatsdClient::new`, recreating its socket at most once per `cooldown` after send errors.
+    pub fn reconnecting<A: ToSocketAddrs>(address: A, prefix_str: &str, float_rate: f64, cooldown: Duration)
+                        -> Result<StatsdOutlet<ReconnectingSocket>> {
+        StatsdOutlet::outlet(ReconnectingSocket::new(address, cooldown)?, prefix_str, float_rate)
+    }
+
+    /// Number of times the socket was recreated.
+    pub fn recreations(&self) -> u64 {
+        self.sender().recreations()
+    }
+}
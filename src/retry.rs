@@ -0,0 +1,83 @@
+/// Retrying packets that would have blocked, rather than dropping them right away.
+use std::collections::VecDeque;
+use std::io::{ErrorKind, Result};
+use std::sync::Mutex;
+use std::sync::atomic::Ordering;
+use {SendStats, StatsdOutlet};
+
+/// Packets waiting for room in the socket send buffer, oldest first.
+pub(crate) struct RetryQueue {
+    capacity: usize,
+    packets: Mutex<VecDeque<String>>,
+}
+
+impl RetryQueue {
+    /// Whether the queue can be locked right away, i.e. no thread holds it.
+    pub(crate) fn is_unlocked(&self) -> bool {
+        self.packets.try_lock().is_ok()
+    }
+}
+
+impl<S: SendStats> StatsdOutlet<S> {
+
+    /// Keep up to `capacity` (at least 1) packets whose send would have blocked and send them again,
+    /// in order, before the next packet and on `flush()`. A queued packet is not an error.
+    /// When the queue is full the oldest packet is dropped and counted in `would_block()`,
+    /// so that a persistently full socket does not grow memory without limit.
+    /// Packets still queued when the client is dropped are lost.
+    pub fn with_retry_queue(mut self, capacity: usize) -> StatsdOutlet<S> {
+        let capacity = capacity.max(1);
+        self.retry = Some(RetryQueue { capacity, packets: Mutex::new(VecDeque::with_capacity(capacity)) });
+        self
+    }
+
+    /// Number of packets waiting to be sent again.
+    pub fn queued_retries(&self) -> usize {
+        self.retry.as_ref().map_or(0, |retry| retry.packets.lock().unwrap().len())
+    }
+
+    /// Send the queued packets then `packet`, queueing it instead if the socket is still full.
+    pub(crate) fn deliver_queued(&self, retry: &RetryQueue, packet: &str) -> Result<()> {
+        let mut packets = retry.packets.lock().unwrap();
+        if self.drain(&mut packets) {
+            let result = self.sender().send_stats_str(packet);
+            match result {
+                Err(ref error) if error.kind() == ErrorKind::WouldBlock => {}
+                _ => {
+                    self.count_delivery(packet, &result);
+                    return result
+                }
+            }
+        }
+        if packets.len() == retry.capacity {
+            packets.pop_front();
+            self.would_block.fetch_add(1, Ordering::Relaxed);
+        }
+        packets.push_back(packet.to_string());
+        Ok(())
+    }
+
+    /// Send the queued packets, returning a `WouldBlock` error if some remain queued.
+    pub(crate) fn flush_retries(&self) -> Result<()> {
+        if let Some(ref retry) = self.retry {
+            if !self.drain(&mut retry.packets.lock().unwrap()) {
+                return Err(ErrorKind::WouldBlock.into())
+            }
+        }
+        Ok(())
+    }
+
+    /// Send queued packets until the queue is empty, returning `true`, or the socket is full again.
+    /// Packets failing with other errors are counted and dropped.
+    fn drain(&self, packets: &mut VecDeque<String>) -> bool {
+        while let Some(packet) = packets.front() {
+            let result = self.sender().send_stats_str(packet);
+            match result {
+                Err(ref error) if error.kind() == ErrorKind::WouldBlock => return false,
+                _ => self.count_delivery(packet, &result),
+            }
+            packets.pop_front();
+        }
+        true
+    }
+}
@@ -0,0 +1,36 @@
+/// Sampling rates chosen per call, for noisy metrics sharing a client with others.
+use std::sync::atomic::Ordering;
+use {check_rate, to_int_rate, MetricValue, SendStats, StatsdOutlet};
+
+impl<S: SendStats> StatsdOutlet<S> {
+
+    /// Report to statsd a count of items, sampled at `rate` instead of the client's rate.
+    /// A rate outside of 0.0 to 1.0 counts the metric as `malformed()`.
+    pub fn count_sampled<V: MetricValue>(&self, key: &str, value: V, rate: f64) {
+        self.send_sampled(key, value, "c", rate)
+    }
+
+    /// Report to statsd a non-cumulative (instant) count of items, sampled at `rate` instead of the client's rate.
+    pub fn gauge_sampled<V: MetricValue>(&self, key: &str, value: V, rate: f64) {
+        self.send_sampled(key, value, "g", rate)
+    }
+
+    /// Report to statsd a time interval, sampled at `rate` instead of the client's rate.
+    pub fn time_interval_ms_sampled<V: MetricValue>(&self, key: &str, interval_ms: V, rate: f64) {
+        self.send_sampled(key, interval_ms, "ms", rate)
+    }
+
+    /// Sample at `rate`, then send the value with the `kind` type and the matching `|@rate` suffix.
+    fn send_sampled<V: MetricValue>(&self, key: &str, value: V, kind: &str, rate: f64) {
+        if check_rate(rate).is_err() {
+            self.malformed.fetch_add(1, Ordering::Relaxed);
+            return
+        }
+        // normalize -0 so that the rate suffix reads @0
+        let rate = rate + 0.0;
+        if self.sample_at(key, to_int_rate(rate)) {
+            let suffix = if rate < 1.0 { format!("|{}|@{}", kind, rate) } else { format!("|{}", kind) };
+            self.send_value(key, value, &suffix);
+        }
+    }
+}
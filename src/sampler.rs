@@ -0,0 +1,69 @@
+/// Pluggable sampling decisions.
+use std::sync::Mutex;
+use {accept_sample, pcg32};
+
+/// Decides whether a metric under `key` is kept when sampling at `int_rate`,
+/// the integer form of the rate where 0 keeps everything and `u32::MAX` nothing (see `to_int_rate`).
+pub trait Sampler {
+    fn accept(&self, int_rate: u32, key: &str) -> bool;
+}
+
+/// Random sampling from fast per-thread generators, what clients do unless given another sampler.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Pcg32Sampler;
+
+impl Sampler for Pcg32Sampler {
+    fn accept(&self, int_rate: u32, _key: &str) -> bool {
+        accept_sample(int_rate)
+    }
+}
+
+/// Random sampling from per-thread generators that forked processes inherit as is, see `StatsdOutlet::with_fork_reseeding`.
+pub(crate) struct InheritedSampler;
+
+impl Sampler for InheritedSampler {
+    fn accept(&self, int_rate: u32, _key: &str) -> bool {
+        pcg32::random_inherited() > int_rate
+    }
+}
+
+/// Sampling by a hash of the key, so that all metrics of a given key are either kept or dropped together
+/// at a given rate, and the keys kept at a rate are also kept at any higher rate.
+/// The hash is 64-bit FNV-1a followed by the MurmurHash3 finalizer, so decisions are the same in every process and every build.
+/// Kept metrics still carry the `|@rate` suffix: the server scales them up as if sampled randomly,
+/// which is only accurate over many keys.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HashSampler;
+
+impl Sampler for HashSampler {
+    fn accept(&self, int_rate: u32, key: &str) -> bool {
+        (key_hash(key) >> 32) as u32 > int_rate
+    }
+}
+
+/// 64-bit FNV-1a of `key` followed by the MurmurHash3 finalizer, stable across processes and builds.
+pub(crate) fn key_hash(key: &str) -> u64 {
+    let mut hash = key.bytes().fold(0xcbf2_9ce4_8422_2325u64, |hash, byte| (hash ^ u64::from(byte)).wrapping_mul(0x0000_0100_0000_01b3));
+    // FNV alone spreads keys differing in their last bytes poorly
+    hash = (hash ^ (hash >> 33)).wrapping_mul(0xff51_afd7_ed55_8ccd);
+    hash = (hash ^ (hash >> 33)).wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+    hash ^ (hash >> 33)
+}
+
+/// Random sampling from a single seeded generator, see `StatsdOutlet::with_seed`.
+pub(crate) struct SeededSampler(Mutex<pcg32::Pcg32>);
+
+impl SeededSampler {
+    pub(crate) fn new(seed: u64) -> SeededSampler {
+        let mut rng = pcg32::Pcg32::new(seed);
+        // the first output is the seed itself, mix it first
+        rng.next_u32();
+        SeededSampler(Mutex::new(rng))
+    }
+}
+
+impl Sampler for SeededSampler {
+    fn accept(&self, int_rate: u32, _key: &str) -> bool {
+        self.0.lock().unwrap().next_u32() > int_rate
+    }
+}
@@ -0,0 +1,35 @@
+/// Replacement of characters a backend does not accept in metric keys.
+use std::borrow::Cow;
+
+/// Characters reserved by the statsd line format.
+pub const STATSD_RESERVED: &[char] = &[':', '|', '@', '\n'];
+
+/// Replaces every disallowed character of a key with a replacement character.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sanitizer {
+    disallowed: Vec<char>,
+    replacement: char,
+}
+
+impl Default for Sanitizer {
+    /// Replace the statsd reserved characters with `_`.
+    fn default() -> Sanitizer {
+        Sanitizer::new(STATSD_RESERVED, '_')
+    }
+}
+
+impl Sanitizer {
+    /// Replace any of the `disallowed` characters with `replacement`.
+    pub fn new(disallowed: &[char], replacement: char) -> Sanitizer {
+        Sanitizer { disallowed: disallowed.to_vec(), replacement }
+    }
+
+    /// The key with every disallowed character replaced. Only allocates if a replacement was needed.
+    pub fn sanitize<'a>(&self, key: &'a str) -> Cow<'a, str> {
+        if key.contains(self.disallowed.as_slice()) {
+            Cow::Owned(key.chars().map(|c| if self.disallowed.contains(&c) { self.replacement } else { c }).collect())
+        } else {
+            Cow::Borrowed(key)
+        }
+    }
+}
@@ -0,0 +1,114 @@
+/// Fixed unit conversion of metric values (bytes to KB, ns to ms...) applied before formatting.
+/// Scales are rational to avoid floats, intermediate results are computed on 128 bits.
+use std::io::{Error, ErrorKind, Result};
+use std::sync::atomic::Ordering;
+use {SendStats, StatsdOutlet};
+
+/// How to round a scaled value that falls between two integers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rounding {
+    /// Round toward zero (truncate).
+    Down,
+    /// Round to the nearest integer, halves going up.
+    Nearest,
+    /// Round away from zero.
+    Up,
+}
+
+/// What to do with a scaled value exceeding the configured maximum.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Overflow {
+    /// Send the maximum value instead.
+    Saturate(u64),
+    /// Do not send anything.
+    Drop,
+}
+
+/// A `multiplier / divisor` factor applied to values before they are sent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Scale {
+    multiplier: u64,
+    divisor: u64,
+    rounding: Rounding,
+    overflow: Overflow,
+}
+
+impl Scale {
+    /// Scale values by `multiplier / divisor`, rounding according to `rounding`.
+    /// Returns an `InvalidInput` error if the divisor is zero.
+    pub fn new(multiplier: u64, divisor: u64, rounding: Rounding) -> Result<Scale> {
+        if divisor == 0 {
+            return Err(Error::new(ErrorKind::InvalidInput, "scale divisor is zero"))
+        }
+        Ok(Scale { multiplier, divisor, rounding, overflow: Overflow::Saturate(u64::MAX) })
+    }
+
+    /// Change what happens to values overflowing after scaling.
+    /// By default they saturate to `u64::MAX`.
+    pub fn with_overflow(self, overflow: Overflow) -> Scale {
+        Scale { overflow, ..self }
+    }
+
+    /// Multiply values by `multiplier`, e.g. seconds to milliseconds.
+    pub fn up(multiplier: u64) -> Scale {
+        Scale { multiplier, divisor: 1, rounding: Rounding::Down, overflow: Overflow::Saturate(u64::MAX) }
+    }
+
+    /// Divide values by `divisor`, e.g. bytes to kilobytes. Returns an `InvalidInput` error if the divisor is zero.
+    pub fn down(divisor: u64, rounding: Rounding) -> Result<Scale> {
+        Scale::new(1, divisor, rounding)
+    }
+
+    /// Scale `value`, applying the overflow policy if the result does not fit.
+    /// Returns `None` if the value should be dropped.
+    pub fn apply(&self, value: u64) -> Option<u64> {
+        self.convert(value).0
+    }
+
+    /// Scale `value`, also telling if it overflowed.
+    fn convert(&self, value: u64) -> (Option<u64>, bool) {
+        let product = u128::from(value) * u128::from(self.multiplier);
+        let divisor = u128::from(self.divisor);
+        let scaled = match self.rounding {
+            Rounding::Down => product / divisor,
+            Rounding::Nearest => (product + divisor / 2) / divisor,
+            Rounding::Up => product.div_ceil(divisor),
+        };
+        match self.overflow {
+            Overflow::Saturate(max) if scaled > u128::from(max) => (Some(max), true),
+            Overflow::Drop if scaled > u128::from(u64::MAX) => (None, true),
+            _ => (Some(scaled as u64), false),
+        }
+    }
+}
+
+impl<S: SendStats> StatsdOutlet<S> {
+
+    /// Report to statsd a count of items, converted using `scale`.
+    pub fn count_scaled(&self, key: &str, value: u64, scale: &Scale) {
+        if let Some(value) = self.scale(value, scale) { self.count(key, value) }
+    }
+
+    /// Report to statsd a non-cumulative (instant) count of items, converted using `scale`.
+    pub fn gauge_scaled(&self, key: &str, value: u64, scale: &Scale) {
+        if let Some(value) = self.scale(value, scale) { self.gauge(key, value) }
+    }
+
+    /// Report to statsd a time interval, converted to milliseconds using `scale`.
+    pub fn time_interval_scaled(&self, key: &str, interval: u64, scale: &Scale) {
+        if let Some(interval) = self.scale(interval, scale) { self.time_interval_ms(key, interval) }
+    }
+
+    /// Number of scaled values that overflowed and were either saturated or dropped, and of aggregated totals that saturated.
+    pub fn overflows(&self) -> u64 {
+        self.overflows.load(Ordering::Relaxed)
+    }
+
+    fn scale(&self, value: u64, scale: &Scale) -> Option<u64> {
+        let (value, overflowed) = scale.convert(value);
+        if overflowed {
+            self.overflows.fetch_add(1, Ordering::Relaxed);
+        }
+        value
+    }
+}
@@ -0,0 +1,121 @@
+/// Partitioning of metrics across many collectors, so that each key is always aggregated by the same one.
+use std::io::Result;
+use std::net::{ToSocketAddrs, UdpSocket};
+use std::sync::atomic::{AtomicU64, Ordering};
+use sampler::key_hash;
+use {connect_udp, Encoding, SendStats, StatsdOutlet};
+
+/// A sender routing each metric line to `shards[hash(key) % shards.len()]`, the key being the (prefixed) metric name
+/// as written in the line, found according to the separator and the encoding of the client.
+/// The hash is the one of `HashSampler`, so routing is the same in every process and every build.
+/// Packets holding many lines are split, each shard receiving its lines as one packet in their original order.
+pub struct Sharded<S> {
+    shards: Vec<S>,
+    separator: String,
+    encoding: Encoding,
+    sent: Vec<AtomicU64>,
+}
+
+pub type ShardedClient = StatsdOutlet<Sharded<UdpSocket>>;
+
+impl<S: SendStats> Sharded<S> {
+    pub fn new(shards: Vec<S>) -> Sharded<S> {
+        assert!(!shards.is_empty());
+        let sent = shards.iter().map(|_| AtomicU64::new(0)).collect();
+        Sharded { shards, separator: ":".to_string(), encoding: Encoding::Statsd, sent }
+    }
+
+    /// Find keys in lines up to `separator` rather than `:`, which must be the one given to the client's `with_separator`.
+    pub fn with_separator(mut self, separator: &str) -> Sharded<S> {
+        self.separator = separator.to_string();
+        self
+    }
+
+    /// Find keys in lines of `encoding` rather than statsd lines, which must be the one given to the client's `with_encoding`.
+    /// Graphite keys end at the first space, Influx measurements at the first unescaped comma or space.
+    pub fn with_encoding(mut self, encoding: Encoding) -> Sharded<S> {
+        self.encoding = encoding;
+        self
+    }
+
+    /// Index of the shard receiving metrics for `key`, which must include the client prefix if any.
+    pub fn shard_of(&self, key: &str) -> usize {
+        (key_hash(key) % self.shards.len() as u64) as usize
+    }
+
+    fn shard_of_line(&self, line: &str) -> usize {
+        let key = match self.encoding {
+            Encoding::Statsd => line.split(self.separator.as_str()).next().unwrap_or(""),
+            #[cfg(feature = "graphite")]
+            Encoding::Graphite => line.split(' ').next().unwrap_or(""),
+            #[cfg(feature = "influx")]
+            Encoding::Influx => influx_measurement(line),
+        };
+        self.shard_of(key)
+    }
+
+    /// Number of lines handed to each shard, in shard order.
+    pub fn shard_sends(&self) -> Vec<u64> {
+        self.sent.iter().map(|sent| sent.load(Ordering::Relaxed)).collect()
+    }
+
+    /// The underlying senders, in shard order.
+    pub fn into_inner(self) -> Vec<S> {
+        self.shards
+    }
+}
+
+/// The measurement starting an Influx line, up to the first comma or space not escaped by a backslash.
+#[cfg(feature = "influx")]
+fn influx_measurement(line: &str) -> &str {
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            ',' | ' ' => return &line[..i],
+            _ => {}
+        }
+    }
+    line
+}
+
+impl<S: SendStats> SendStats for Sharded<S> {
+    fn send_stats(&self, str: String) -> Result<()> {
+        if !str.contains('\n') {
+            let shard = self.shard_of_line(&str);
+            self.sent[shard].fetch_add(1, Ordering::Relaxed);
+            return self.shards[shard].send_stats(str)
+        }
+        let mut packets = vec![String::new(); self.shards.len()];
+        for line in str.split('\n') {
+            let shard = self.shard_of_line(line);
+            self.sent[shard].fetch_add(1, Ordering::Relaxed);
+            let packet = &mut packets[shard];
+            if !packet.is_empty() { packet.push('\n') }
+            packet.push_str(line);
+        }
+        // every shard gets its lines even if another one fails, the first error is returned
+        let mut result = Ok(());
+        for (shard, packet) in self.shards.iter().zip(packets) {
+            if !packet.is_empty() {
+                result = result.and(shard.send_stats(packet));
+            }
+        }
+        result
+    }
+}
+
+impl ShardedClient {
+    /// Create a client spreading metrics across the collectors at `addresses` by key.
+    /// The order of `addresses` determines routing and must be the same for every process.
+    pub fn sharded<A: ToSocketAddrs>(addresses: &[A], prefix_str: &str, float_rate: f64) -> Result<ShardedClient> {
+        let sockets = addresses.iter().map(connect_udp).collect::<Result<Vec<_>>>()?;
+        StatsdOutlet::outlet(Sharded::new(sockets), prefix_str, float_rate)
+    }
+
+    /// Number of lines handed to each collector, in the order of the addresses.
+    pub fn shard_sends(&self) -> Vec<u64> {
+        self.sender().shard_sends()
+    }
+}
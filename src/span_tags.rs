@@ -0,0 +1,116 @@
+/// Tagging of metrics with fields of the current `tracing` span.
+/// Requires the `SpanTags` layer to be installed on a `tracing_subscriber::Registry`.
+use std::fmt;
+use tracing_core::field::{Field, Visit};
+use tracing_core::span::{Attributes, Id, Record};
+use tracing_core::{dispatcher, Subscriber};
+use tracing_subscriber::layer::{Context, Layer};
+use tracing_subscriber::registry::{LookupSpan, Registry};
+use {Metric, SendStats, StatsdOutlet};
+
+/// A layer recording the selected fields of every span so that they can be attached to metrics as DogStatsD tags.
+pub struct SpanTags {
+    fields: Vec<&'static str>,
+}
+
+impl SpanTags {
+    /// Forward the span fields named in `fields`, other fields are ignored.
+    pub fn new(fields: &[&'static str]) -> SpanTags {
+        SpanTags { fields: fields.to_vec() }
+    }
+}
+
+/// Tags recorded for a span, stored in its extensions.
+struct Tags(Vec<(&'static str, String)>);
+
+struct TagVisitor<'a> {
+    fields: &'a [&'static str],
+    tags: &'a mut Vec<(&'static str, String)>,
+}
+
+impl<'a> Visit for TagVisitor<'a> {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if self.fields.contains(&field.name()) {
+            self.tags.retain(|&(name, _)| name != field.name());
+            self.tags.push((field.name(), value.to_string()));
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.record_str(field, &format!("{:?}", value))
+    }
+}
+
+impl<S: Subscriber + for<'a> LookupSpan<'a>> Layer<S> for SpanTags {
+    fn on_new_span(&self, attrs: &Attributes, id: &Id, ctx: Context<S>) {
+        let mut tags = Vec::new();
+        attrs.record(&mut TagVisitor { fields: &self.fields, tags: &mut tags });
+        if let Some(span) = ctx.span(id) {
+            span.extensions_mut().insert(Tags(tags));
+        }
+    }
+
+    fn on_record(&self, id: &Id, values: &Record, ctx: Context<S>) {
+        if let Some(span) = ctx.span(id) {
+            if let Some(tags) = span.extensions_mut().get_mut::<Tags>() {
+                values.record(&mut TagVisitor { fields: &self.fields, tags: &mut tags.0 });
+            }
+        }
+    }
+}
+
+/// The tags for the current span and its parents, innermost fields first.
+/// Empty if not in a span or if no `SpanTags` layer is installed.
+fn current_tags() -> Vec<(&'static str, String)> {
+    dispatcher::get_default(|dispatch| {
+        let mut current = Vec::new();
+        let registry = match dispatch.downcast_ref::<Registry>() {
+            Some(registry) => registry,
+            None => return current,
+        };
+        let span = match dispatch.current_span().id().and_then(|id| registry.span(id)) {
+            Some(span) => span,
+            None => return current,
+        };
+        for span in span.scope() {
+            if let Some(tags) = span.extensions().get::<Tags>() {
+                for &(name, ref value) in &tags.0 {
+                    if current.iter().any(|&(seen, _)| seen == name) { continue }
+                    current.push((name, value.clone()));
+                }
+            }
+        }
+        current
+    })
+}
+
+impl<S: SendStats> StatsdOutlet<S> {
+
+    /// Report to statsd a count of items, tagged with the fields of the current span.
+    pub fn count_traced(&self, key: &str, value: u64) {
+        if self.sample(key)  {
+            self.send_traced(Metric::Count { key, value });
+        }
+    }
+
+    /// Report to statsd a non-cumulative (instant) count of items, tagged with the fields of the current span.
+    pub fn gauge_traced(&self, key: &str, value: u64) {
+        if self.sample(key)  {
+            self.send_traced(Metric::Gauge { key, value });
+        }
+    }
+
+    /// Report to statsd a time interval of items, tagged with the fields of the current span.
+    pub fn time_interval_ms_traced(&self, key: &str, interval_ms: u64) {
+        if self.sample(key)  {
+            self.send_traced(Metric::Timing { key, value: interval_ms });
+        }
+    }
+
+    /// Send `metric` tagged with the fields of the current span.
+    fn send_traced(&self, metric: Metric<u64>) {
+        let tags = current_tags();
+        let tags: Vec<(&str, &str)> = tags.iter().map(|&(name, ref value)| (name, value.as_str())).collect();
+        self.send_metric_tagged(metric, &tags);
+    }
+}
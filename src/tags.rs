@@ -0,0 +1,65 @@
+/// DogStatsD tags, appended to metric lines as a `|#name:value,...` block after the type and rate.
+/// Tag names and values containing `,`, `|`, `#` or a newline would corrupt the line, such tags are counted as `malformed()`.
+/// Influx lines escape tags instead, accepting any.
+use std::sync::atomic::Ordering;
+use {Metric, SendStats, StatsdOutlet};
+
+/// Characters ending a tag or the tags block.
+const TAG_RESERVED: &[char] = &[',', '|', '#', '\n'];
+
+impl<S: SendStats> StatsdOutlet<S> {
+
+    /// Attach `tags` to every metric sent by this client, ahead of any per-call tags.
+    /// Tags holding reserved characters are left out of statsd lines, each counted as `malformed()`.
+    pub fn with_default_tags(mut self, tags: &[(&str, &str)]) -> StatsdOutlet<S> {
+        self.default_tags = tags.iter().map(|&(name, value)| (name.to_string(), value.to_string())).collect();
+        let valid: Vec<(&str, &str)> = tags.iter().cloned().filter(|&tag| valid_tags(&[tag])).collect();
+        self.malformed.fetch_add((tags.len() - valid.len()) as u64, Ordering::Relaxed);
+        self.default_tag_block = tag_list(&valid);
+        self
+    }
+
+    /// Tags attached to every metric sent by this client.
+    pub fn default_tags(&self) -> &[(String, String)] {
+        &self.default_tags
+    }
+
+    /// Report to statsd a count of items with the given tags.
+    /// The metric is counted as `malformed()` instead of sent if a tag holds reserved characters.
+    pub fn count_tagged(&self, key: &str, value: u64, tags: &[(&str, &str)]) {
+        if self.sample(key)  {
+            self.send_metric_tagged(Metric::Count { key, value }, tags);
+        }
+    }
+
+    /// Report to statsd a non-cumulative (instant) count of items with the given tags.
+    pub fn gauge_tagged(&self, key: &str, value: u64, tags: &[(&str, &str)]) {
+        if self.sample(key)  {
+            self.send_metric_tagged(Metric::Gauge { key, value }, tags);
+        }
+    }
+
+    /// Report to statsd a time interval of items with the given tags.
+    pub fn time_interval_ms_tagged(&self, key: &str, interval_ms: u64, tags: &[(&str, &str)]) {
+        if self.sample(key)  {
+            self.send_metric_tagged(Metric::Timing { key, value: interval_ms }, tags);
+        }
+    }
+}
+
+/// Whether no name or value of `tags` holds a character reserved by the tags block.
+pub(crate) fn valid_tags(tags: &[(&str, &str)]) -> bool {
+    tags.iter().all(|&(name, value)| !name.contains(TAG_RESERVED) && !value.contains(TAG_RESERVED))
+}
+
+/// Comma separated `name:value` pairs, without the leading `|#`.
+pub(crate) fn tag_list(tags: &[(&str, &str)]) -> String {
+    let mut list = String::new();
+    for &(name, value) in tags {
+        if !list.is_empty() { list.push(',') }
+        list.push_str(name);
+        list.push(':');
+        list.push_str(value);
+    }
+    list
+}
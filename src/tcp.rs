@@ -0,0 +1,129 @@
+/// The statsd TCP line protocol, for metrics that should not be lost to UDP.
+use std::io::{Result, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::Mutex;
+use std::time::Duration;
+use {MetricValue, SendStats, StatsdOutlet};
+
+/// A TCP connection writing each packet under a lock, so that lines from concurrent callers never interleave,
+/// optionally waiting for the server host to acknowledge it. Every line is followed by the `\n` terminator
+/// required to frame it in the byte stream. A bare `TcpStream` is not a sender: `write_all` may take many writes,
+/// which would interleave.
+pub struct TcpSender {
+    stream: TcpStream,
+    // held while writing a packet
+    writing: Mutex<()>,
+    confirm_timeout: Option<Duration>,
+}
+
+impl TcpSender {
+    /// Wrap a connected stream, sending without waiting for acknowledgements.
+    pub fn new(stream: TcpStream) -> TcpSender {
+        TcpSender { stream, writing: Mutex::new(()), confirm_timeout: None }
+    }
+
+    /// Wait after every write, for at most `timeout`, until the kernel of the server host has acknowledged
+    /// all the bytes sent, failing with `TimedOut` otherwise. A successful send then means that the lines left
+    /// the local send buffer, not that the server read them: they are still lost if it exits before reading.
+    /// Other callers can write while a send waits, which then also waits for their lines to be acknowledged.
+    /// Acknowledgements can only be observed on Linux, elsewhere a confirmed send returns once written.
+    pub fn confirm_delivery(mut self, timeout: Duration) -> TcpSender {
+        self.confirm_timeout = Some(timeout);
+        self
+    }
+
+    /// Release the underlying stream.
+    pub fn into_inner(self) -> TcpStream {
+        self.stream
+    }
+}
+
+impl SendStats for TcpSender {
+    fn send_stats(&self, str: String) -> Result<()> {
+        self.send_stats_str(&str)
+    }
+
+    fn send_stats_str(&self, str: &str) -> Result<()> {
+        {
+            let _writing = self.writing.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            let mut stream = &self.stream;
+            stream.write_all(str.as_bytes())?;
+            stream.write_all(b"\n")?;
+        }
+        match self.confirm_timeout {
+            Some(timeout) => wait_acknowledged(&self.stream, timeout),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Poll until no byte written to `stream` is left unacknowledged by the peer's kernel, for at most `timeout`.
+#[cfg(target_os = "linux")]
+fn wait_acknowledged(stream: &TcpStream, timeout: Duration) -> Result<()> {
+    use libc;
+    use std::io::{Error, ErrorKind};
+    use std::os::unix::io::AsRawFd;
+    use std::thread;
+    use std::time::Instant;
+
+    let deadline = Instant::now() + timeout;
+    loop {
+        // bytes written but not yet acknowledged by the peer
+        let mut pending: libc::c_int = 0;
+        if unsafe { libc::ioctl(stream.as_raw_fd(), libc::TIOCOUTQ, &mut pending) } < 0 {
+            return Err(Error::last_os_error());
+        }
+        if pending == 0 {
+            return Ok(());
+        }
+        if let Some(err) = stream.take_error()? {
+            return Err(err);
+        }
+        if Instant::now() >= deadline {
+            return Err(ErrorKind::TimedOut.into());
+        }
+        thread::sleep(Duration::from_millis(1));
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn wait_acknowledged(_stream: &TcpStream, _timeout: Duration) -> Result<()> {
+    Ok(())
+}
+
+impl StatsdOutlet<TcpSender> {
+    /// Create a new client sending metrics over a TCP connection to `address`.
+    pub fn new_tcp<A: ToSocketAddrs>(address: A, prefix_str: &str, float_rate: f64) -> Result<StatsdOutlet<TcpSender>> {
+        let stream = TcpStream::connect(address)?;
+        stream.set_nodelay(true)?;
+        StatsdOutlet::outlet(TcpSender::new(stream), prefix_str, float_rate)
+    }
+
+    /// Create a new client over TCP to `address` whose sends wait up to `timeout` for the server host to acknowledge them,
+    /// see `TcpSender::confirm_delivery`.
+    pub fn new_tcp_confirmed<A: ToSocketAddrs>(address: A, prefix_str: &str, float_rate: f64, timeout: Duration) -> Result<StatsdOutlet<TcpSender>> {
+        let stream = TcpStream::connect(address)?;
+        stream.set_nodelay(true)?;
+        StatsdOutlet::outlet(TcpSender::new(stream).confirm_delivery(timeout), prefix_str, float_rate)
+    }
+
+    /// Increment or decrement the counter, returning once the server host acknowledged it at the TCP level,
+    /// which does not mean the server read it yet. See `TcpSender::confirm_delivery`.
+    /// Batched or aggregated values are flushed first, a sampled-out value returns `Ok` without sending.
+    pub fn count_confirmed<V: MetricValue>(&self, key: &str, value: V) -> Result<()> {
+        self.try_count(key, value)?;
+        self.try_flush()
+    }
+
+    /// Set the gauge, returning once the server host acknowledged it at the TCP level.
+    pub fn gauge_confirmed<V: MetricValue>(&self, key: &str, value: V) -> Result<()> {
+        self.try_gauge(key, value)?;
+        self.try_flush()
+    }
+
+    /// Record the timing, returning once the server host acknowledged it at the TCP level.
+    pub fn time_interval_ms_confirmed<V: MetricValue>(&self, key: &str, interval_ms: V) -> Result<()> {
+        self.try_time_interval_ms(key, interval_ms)?;
+        self.try_flush()
+    }
+}
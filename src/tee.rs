@@ -0,0 +1,38 @@
+/// Fan-out of metrics to two senders, e.g. to dual-write while migrating between statsd hosts.
+use std::io::Result;
+use SendStats;
+
+/// Hands every metric to both senders, `first` then `second`.
+/// The line is borrowed by both, so a sender that does not override `send_stats_str`
+/// (and needs an owned `String`) costs a copy of the line.
+/// Both senders are always tried, the first error is returned.
+pub struct TeeSender<A: SendStats, B: SendStats> {
+    first: A,
+    second: B,
+}
+
+impl<A: SendStats, B: SendStats> TeeSender<A, B> {
+    /// Send through both `first` and `second`.
+    pub fn new(first: A, second: B) -> TeeSender<A, B> {
+        TeeSender { first, second }
+    }
+
+    /// The senders, in the order they were given.
+    pub fn into_inner(self) -> (A, B) {
+        (self.first, self.second)
+    }
+}
+
+impl<A: SendStats, B: SendStats> SendStats for TeeSender<A, B> {
+    fn send_stats(&self, str: String) -> Result<()> {
+        let first = self.first.send_stats_str(&str);
+        let second = self.second.send_stats(str);
+        first.and(second)
+    }
+
+    fn send_stats_str(&self, str: &str) -> Result<()> {
+        let first = self.first.send_stats_str(str);
+        let second = self.second.send_stats_str(str);
+        first.and(second)
+    }
+}
@@ -0,0 +1,45 @@
+/// Timing of futures, runtime agnostic as it only relies on `std::future`.
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use {SendStats, StartTime, StatsdOutlet};
+
+/// A future reporting the wall-clock time elapsed from its first poll to its completion.
+/// Created with `StatsdOutlet::time_future`.
+pub struct TimedFuture<'a, S: SendStats + 'a, F> {
+    outlet: &'a StatsdOutlet<S>,
+    key: &'a str,
+    future: Pin<Box<F>>,
+    // None until first polled, then Some(None) if the sample was rejected
+    start: Option<Option<StartTime>>,
+}
+
+impl<S: SendStats> StatsdOutlet<S> {
+
+    /// Wrap `future` so that the time elapsed between its first poll and its completion is reported under `key`.
+    /// The sampling decision is made on first poll, clocks are not read at all if the sample will not be taken.
+    /// The wrapped future is boxed to keep the wrapper `Unpin`.
+    pub fn time_future<'a, F: Future>(&'a self, key: &'a str, future: F) -> TimedFuture<'a, S, F> {
+        TimedFuture { outlet: self, key, future: Box::pin(future), start: None }
+    }
+}
+
+impl<'a, S: SendStats, F: Future> Future for TimedFuture<'a, S, F> {
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<F::Output> {
+        let this = self.get_mut();
+        if this.start.is_none() {
+            this.start = Some(if this.outlet.sample(this.key) { Some(StartTime::now()) } else { None });
+        }
+        match this.future.as_mut().poll(cx) {
+            Poll::Ready(output) => {
+                if let Some(Some(start)) = this.start.take() {
+                    this.outlet.send_time_ms(this.key, start.elapsed_ms());
+                }
+                Poll::Ready(output)
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
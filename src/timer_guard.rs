@@ -0,0 +1,27 @@
+/// Timing of scopes, however they are exited.
+use {SendStats, StartTime, StatsdOutlet};
+
+/// Reports the time elapsed since its creation when dropped, at the end of the scope or on early return or panic.
+/// Created with `StatsdOutlet::scoped_time`.
+pub struct TimerGuard<'a, S: SendStats + 'a> {
+    outlet: &'a StatsdOutlet<S>,
+    key: &'a str,
+    // None once reported
+    start: Option<StartTime>,
+}
+
+impl<S: SendStats> StatsdOutlet<S> {
+
+    /// Start timing the current scope, the interval is reported under `key` when the returned guard is dropped.
+    pub fn scoped_time<'a>(&'a self, key: &'a str) -> TimerGuard<'a, S> {
+        TimerGuard { outlet: self, key, start: Some(StartTime::now()) }
+    }
+}
+
+impl<'a, S: SendStats> Drop for TimerGuard<'a, S> {
+    fn drop(&mut self) {
+        if let Some(start) = self.start.take() {
+            self.outlet.stop_time(self.key, start);
+        }
+    }
+}
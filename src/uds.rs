@@ -0,0 +1,27 @@
+/// Unix domain datagram sockets, for agents on the same host.
+use std::io::Result;
+use std::os::unix::net::UnixDatagram;
+use std::path::Path;
+use {SendStats, StatsdOutlet};
+
+/// Send a datagram for every stat, like for UDP
+impl SendStats for UnixDatagram {
+    fn send_stats(&self, str: String) -> Result<()> {
+        self.send_stats_str(&str)
+    }
+
+    fn send_stats_str(&self, str: &str) -> Result<()> {
+        self.send(str.as_bytes()).map(|_| ())
+    }
+}
+
+impl StatsdOutlet<UnixDatagram> {
+    /// Create a new client sending datagrams to the Unix domain socket at `path`, e.g. `/var/run/datadog/dsd.socket`.
+    /// The socket is connected at construction just like for UDP, so that the agent must already be listening.
+    pub fn new_uds<P: AsRef<Path>>(path: P, prefix_str: &str, float_rate: f64) -> Result<StatsdOutlet<UnixDatagram>> {
+        let socket = UnixDatagram::unbound()?;
+        socket.set_nonblocking(true)?;
+        socket.connect(path)?;
+        StatsdOutlet::outlet(socket, prefix_str, float_rate)
+    }
+}
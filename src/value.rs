@@ -0,0 +1,82 @@
+/// Numeric types accepted as metric values.
+use std::convert::TryFrom;
+use std::fmt::Write;
+
+/// A value that can be written into a metric line.
+pub trait MetricValue {
+    /// Append the statsd representation of the value to `buf`.
+    fn write_value(&self, buf: &mut String);
+
+    /// Whether the value can be represented at all, lines with values that cannot are not sent.
+    fn is_valid(&self) -> bool {
+        true
+    }
+
+    /// The value as a whole count that can be summed with others, see `StatsdOutlet::with_aggregation`.
+    /// `None` for values that are always sent as they are.
+    fn to_count(&self) -> Option<i64> {
+        None
+    }
+}
+
+macro_rules! integer_value {
+    ($($t:ty),*) => {$(
+        impl MetricValue for $t {
+            fn write_value(&self, buf: &mut String) {
+                let _ = write!(buf, "{}", self);
+            }
+
+            fn to_count(&self) -> Option<i64> {
+                i64::try_from(*self).ok()
+            }
+        }
+    )*}
+}
+
+integer_value!(u8, u16, u32, u64, usize, i8, i16, i32, i64, isize);
+
+impl<T: MetricValue + ?Sized> MetricValue for &T {
+    fn write_value(&self, buf: &mut String) {
+        (**self).write_value(buf)
+    }
+
+    fn is_valid(&self) -> bool {
+        (**self).is_valid()
+    }
+
+    fn to_count(&self) -> Option<i64> {
+        (**self).to_count()
+    }
+}
+
+/// A relative change, always written with its sign (`+0` included).
+pub(crate) struct Signed(pub(crate) i64);
+
+impl MetricValue for Signed {
+    fn write_value(&self, buf: &mut String) {
+        let _ = write!(buf, "{:+}", self.0);
+    }
+}
+
+/// Floats are written in their shortest form without a trailing `.0` (`1.0` is written as `1`).
+/// Infinities and NaN are not valid.
+impl MetricValue for f64 {
+    fn write_value(&self, buf: &mut String) {
+        // adding zero turns -0.0 into 0.0
+        let _ = write!(buf, "{}", self + 0.0);
+    }
+
+    fn is_valid(&self) -> bool {
+        self.is_finite()
+    }
+}
+
+impl MetricValue for f32 {
+    fn write_value(&self, buf: &mut String) {
+        let _ = write!(buf, "{}", self + 0.0);
+    }
+
+    fn is_valid(&self) -> bool {
+        self.is_finite()
+    }
+}